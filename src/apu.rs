@@ -0,0 +1,921 @@
+use std::io::Result;
+
+use crate::resampler::*;
+use crate::state::*;
+use crate::utils::*;
+
+// Each duty cycle is 8 steps long - 1 means the wave is high for that step
+const DUTY_PATTERNS: [[u8; 8]; 4] = [
+    [0, 0, 0, 0, 0, 0, 0, 1],  // 12.5%
+    [1, 0, 0, 0, 0, 0, 0, 1],  // 25%
+    [1, 0, 0, 0, 0, 1, 1, 1],  // 50%
+    [0, 1, 1, 1, 1, 1, 1, 0],  // 75%
+];
+
+const NOISE_DIVISORS: [usize; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
+
+// Bits which always read back as 1 for each register from 0xFF10 - 0xFF2F, taken from the Pan Docs.
+// Write only bits (i.e. frequency and the trigger bits) and unused registers read back as set
+const REGISTER_READ_MASKS: [Byte; 0x20] = [
+    0x80, 0x3F, 0x00, 0xFF, 0xBF,  // NR10 - NR14
+    0xFF, 0x3F, 0x00, 0xFF, 0xBF,  // Unused, NR21 - NR24
+    0x7F, 0xFF, 0x9F, 0xFF, 0xBF,  // NR30 - NR34
+    0xFF, 0xFF, 0x00, 0x00, 0xBF,  // Unused, NR41 - NR44
+    0x00, 0x00, 0x70,              // NR50 - NR52
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,  // Unused
+];
+
+#[derive(Debug)]
+struct LengthCounter {
+    counter: usize,
+    max_length: usize,
+    enabled: bool,
+}
+
+impl LengthCounter {
+    fn new(max_length: usize) -> LengthCounter {
+        LengthCounter {
+            counter: 0,
+            max_length: max_length,
+            enabled: false,
+        }
+    }
+
+    fn load(&mut self, length_data: usize) {
+        // The length register holds how much has already "elapsed", so the counter is
+        // the remaining amount
+        self.counter = self.max_length - length_data;
+    }
+
+    fn trigger(&mut self) {
+        if self.counter == 0 {
+            self.counter = self.max_length;
+        }
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_usize(self.counter);
+        writer.write_bool(self.enabled);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        self.counter = reader.read_usize()?;
+        self.enabled = reader.read_bool()?;
+        Ok(())
+    }
+
+    fn clock(&mut self, channel_enabled: &mut bool) {
+        // When the length counter is enabled and runs out, the channel is turned off
+        if self.enabled && self.counter > 0 {
+            self.counter -= 1;
+            if self.counter == 0 {
+                *channel_enabled = false;
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Envelope {
+    initial_volume: u8,
+    increase: bool,
+    period: u8,
+    timer: u8,
+    volume: u8,
+}
+
+impl Envelope {
+    fn new() -> Envelope {
+        Envelope {
+            initial_volume: 0,
+            increase: false,
+            period: 0,
+            timer: 0,
+            volume: 0,
+        }
+    }
+
+    fn write(&mut self, data: Byte) {
+        // Bits 7-4 - Initial volume
+        // Bit 3    - Direction (0 = decrease, 1 = increase)
+        // Bits 2-0 - Sweep pace (0 = no sweep)
+        self.initial_volume = data >> 4;
+        self.increase = is_bit_set(&data, 3);
+        self.period = data & 0x7;
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_byte(self.timer);
+        writer.write_byte(self.volume);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        self.timer = reader.read_byte()?;
+        self.volume = reader.read_byte()?;
+        Ok(())
+    }
+
+    fn trigger(&mut self) {
+        self.volume = self.initial_volume;
+        self.timer = if self.period == 0 { 8 } else { self.period };
+    }
+
+    fn clock(&mut self) {
+        if self.period == 0 {
+            return;
+        }
+
+        if self.timer > 0 {
+            self.timer -= 1;
+        }
+
+        if self.timer == 0 {
+            self.timer = self.period;
+
+            if self.increase && self.volume < 15 {
+                self.volume += 1;
+            } else if !self.increase && self.volume > 0 {
+                self.volume -= 1;
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Sweep {
+    period: u8,
+    negate: bool,
+    shift: u8,
+    timer: u8,
+    enabled: bool,
+    shadow_frequency: Word,
+}
+
+impl Sweep {
+    fn new() -> Sweep {
+        Sweep {
+            period: 0,
+            negate: false,
+            shift: 0,
+            timer: 0,
+            enabled: false,
+            shadow_frequency: 0,
+        }
+    }
+
+    fn write(&mut self, data: Byte) {
+        // Bits 6-4 - Sweep pace
+        // Bit 3    - Direction (0 = addition, 1 = subtraction)
+        // Bits 2-0 - Individual step
+        self.period = (data >> 4) & 0x7;
+        self.negate = is_bit_set(&data, 3);
+        self.shift = data & 0x7;
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_byte(self.timer);
+        writer.write_bool(self.enabled);
+        writer.write_word(self.shadow_frequency);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        self.timer = reader.read_byte()?;
+        self.enabled = reader.read_bool()?;
+        self.shadow_frequency = reader.read_word()?;
+        Ok(())
+    }
+
+    fn calculate_frequency(&self) -> Word {
+        let delta = self.shadow_frequency >> self.shift;
+        match self.negate {
+            true => self.shadow_frequency.wrapping_sub(delta),
+            false => self.shadow_frequency + delta,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct SquareChannel {
+    enabled: bool,
+    dac_enabled: bool,
+    duty: usize,
+    duty_position: usize,
+    frequency: Word,
+    timer: isize,
+    length: LengthCounter,
+    envelope: Envelope,
+
+    // Only Channel 1 has a frequency sweep
+    sweep: Option<Sweep>,
+}
+
+impl SquareChannel {
+    fn new(has_sweep: bool) -> SquareChannel {
+        SquareChannel {
+            enabled: false,
+            dac_enabled: false,
+            duty: 0,
+            duty_position: 0,
+            frequency: 0,
+            timer: 0,
+            length: LengthCounter::new(64),
+            envelope: Envelope::new(),
+            sweep: if has_sweep { Some(Sweep::new()) } else { None },
+        }
+    }
+
+    fn get_period(&self) -> isize {
+        (2048 - self.frequency as isize) * 4
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.enabled);
+        writer.write_usize(self.duty_position);
+        writer.write_word(self.frequency);
+        writer.write_isize(self.timer);
+        self.length.save_state(writer);
+        self.envelope.save_state(writer);
+
+        if let Some(sweep) = &self.sweep {
+            sweep.save_state(writer);
+        }
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        self.enabled = reader.read_bool()?;
+        self.duty_position = reader.read_usize()? % 8;
+        self.frequency = reader.read_word()? & 0x7FF;
+        self.timer = reader.read_isize()?;
+        self.length.load_state(reader)?;
+        self.envelope.load_state(reader)?;
+
+        if let Some(sweep) = &mut self.sweep {
+            sweep.load_state(reader)?;
+        }
+
+        Ok(())
+    }
+
+    fn step(&mut self, cycles: usize) {
+        self.timer -= cycles as isize;
+        while self.timer <= 0 {
+            self.timer += self.get_period();
+            self.duty_position = (self.duty_position + 1) % 8;
+        }
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.dac_enabled;
+        self.timer = self.get_period();
+        self.length.trigger();
+        self.envelope.trigger();
+
+        if let Some(sweep) = &mut self.sweep {
+            // Triggering copies the frequency to the shadow register and reloads the sweep timer.
+            // If there is a shift, the overflow check is done immediately
+            sweep.shadow_frequency = self.frequency;
+            sweep.timer = if sweep.period == 0 { 8 } else { sweep.period };
+            sweep.enabled = sweep.period != 0 || sweep.shift != 0;
+
+            if sweep.shift != 0 && sweep.calculate_frequency() > 2047 {
+                self.enabled = false;
+            }
+        }
+    }
+
+    fn clock_sweep(&mut self) {
+        if let Some(sweep) = &mut self.sweep {
+            if sweep.timer > 0 {
+                sweep.timer -= 1;
+            }
+
+            if sweep.timer == 0 {
+                sweep.timer = if sweep.period == 0 { 8 } else { sweep.period };
+
+                if sweep.enabled && sweep.period != 0 {
+                    let new_frequency = sweep.calculate_frequency();
+
+                    // Overflowing past 2047 disables the channel, otherwise the new frequency is
+                    // written back and the overflow check is run a second time with it
+                    if new_frequency > 2047 {
+                        self.enabled = false;
+                    } else if sweep.shift != 0 {
+                        sweep.shadow_frequency = new_frequency;
+                        self.frequency = new_frequency;
+
+                        if sweep.calculate_frequency() > 2047 {
+                            self.enabled = false;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn get_output(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+
+        DUTY_PATTERNS[self.duty][self.duty_position] * self.envelope.volume
+    }
+}
+
+#[derive(Debug)]
+struct WaveChannel {
+    enabled: bool,
+    dac_enabled: bool,
+    volume_shift: u8,
+    frequency: Word,
+    timer: isize,
+    position: usize,
+    length: LengthCounter,
+}
+
+impl WaveChannel {
+    fn new() -> WaveChannel {
+        WaveChannel {
+            enabled: false,
+            dac_enabled: false,
+            volume_shift: 4,
+            frequency: 0,
+            timer: 0,
+            position: 0,
+            length: LengthCounter::new(256),
+        }
+    }
+
+    fn get_period(&self) -> isize {
+        (2048 - self.frequency as isize) * 2
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.enabled);
+        writer.write_usize(self.position);
+        writer.write_word(self.frequency);
+        writer.write_isize(self.timer);
+        self.length.save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        self.enabled = reader.read_bool()?;
+        self.position = reader.read_usize()? % 32;
+        self.frequency = reader.read_word()? & 0x7FF;
+        self.timer = reader.read_isize()?;
+        self.length.load_state(reader)?;
+        Ok(())
+    }
+
+    fn step(&mut self, cycles: usize) {
+        self.timer -= cycles as isize;
+        while self.timer <= 0 {
+            self.timer += self.get_period();
+            self.position = (self.position + 1) % 32;
+        }
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.dac_enabled;
+        self.timer = self.get_period();
+        self.position = 0;
+        self.length.trigger();
+    }
+
+    fn get_output(&self, wave_ram: &[Byte]) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+
+        // Each byte of wave RAM holds two samples, upper nibble is played first
+        let sample_byte = wave_ram[self.position / 2];
+        let sample = match self.position % 2 {
+            0 => sample_byte >> 4,
+            _ => sample_byte & 0xF,
+        };
+
+        sample >> self.volume_shift
+    }
+}
+
+#[derive(Debug)]
+struct NoiseChannel {
+    enabled: bool,
+    dac_enabled: bool,
+    clock_shift: u8,
+    width_mode: bool,
+    divisor_code: usize,
+    lfsr: Word,
+    timer: isize,
+    length: LengthCounter,
+    envelope: Envelope,
+}
+
+impl NoiseChannel {
+    fn new() -> NoiseChannel {
+        NoiseChannel {
+            enabled: false,
+            dac_enabled: false,
+            clock_shift: 0,
+            width_mode: false,
+            divisor_code: 0,
+            lfsr: 0x7FFF,
+            timer: 0,
+            length: LengthCounter::new(64),
+            envelope: Envelope::new(),
+        }
+    }
+
+    fn get_period(&self) -> isize {
+        (NOISE_DIVISORS[self.divisor_code] << self.clock_shift) as isize
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.enabled);
+        writer.write_word(self.lfsr);
+        writer.write_isize(self.timer);
+        self.length.save_state(writer);
+        self.envelope.save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        self.enabled = reader.read_bool()?;
+        self.lfsr = reader.read_word()?;
+        self.timer = reader.read_isize()?;
+        self.length.load_state(reader)?;
+        self.envelope.load_state(reader)?;
+        Ok(())
+    }
+
+    fn step(&mut self, cycles: usize) {
+        self.timer -= cycles as isize;
+        while self.timer <= 0 {
+            self.timer += self.get_period();
+
+            // The LFSR XORs its lowest 2 bits, shifts right and puts the result into bit 14.
+            // In 7-bit width mode, the result is also placed into bit 6
+            let xor = (self.lfsr & 0x1) ^ ((self.lfsr >> 1) & 0x1);
+            self.lfsr = (self.lfsr >> 1) | (xor << 14);
+
+            if self.width_mode {
+                self.lfsr = (self.lfsr & !(1 << 6)) | (xor << 6);
+            }
+        }
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.dac_enabled;
+        self.timer = self.get_period();
+        self.lfsr = 0x7FFF;
+        self.length.trigger();
+        self.envelope.trigger();
+    }
+
+    fn get_output(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+
+        // Output is the inverse of bit 0 of the LFSR
+        ((!self.lfsr & 0x1) as u8) * self.envelope.volume
+    }
+}
+
+#[derive(Debug)]
+pub struct Apu {
+    /**
+    * Audio Processing Unit for the Gameboy. There are 4 sound channels:
+    *    Channel 1 - Square wave with frequency sweep and volume envelope
+    *    Channel 2 - Square wave with volume envelope
+    *    Channel 3 - Arbitrary 4-bit wave pattern read from Wave RAM
+    *    Channel 4 - Noise generated by a linear feedback shift register, with volume envelope
+    *
+    * The frame sequencer runs at 512 Hz and clocks the length counters (256 Hz), sweep (128 Hz)
+    * and volume envelopes (64 Hz). Each channel is mixed to the left and right outputs according
+    * to NR51, then scaled by the master volume in NR50
+    **/
+
+    // Raw register values for 0xFF10 - 0xFF3F, used for reading back
+    registers: [Byte; 0x30],
+    powered: bool,
+    channel_1: SquareChannel,
+    channel_2: SquareChannel,
+    channel_3: WaveChannel,
+    channel_4: NoiseChannel,
+    frame_sequencer_counter: usize,
+    frame_sequencer_step: u8,
+
+    // Amplitude changes are fed into the resampler at the clock they happened on, which
+    // is counted from the start of the current audio frame
+    resampler: Resampler,
+    frame_clocks: usize,
+    last_output: (f32, f32),
+
+    // Interleaved stereo samples (left, right) waiting to be consumed by the frontend
+    samples: Vec<f32>,
+}
+
+impl Apu {
+
+    pub fn new() -> Apu {
+        Apu {
+            registers: [0; 0x30],
+            powered: false,
+            channel_1: SquareChannel::new(true),
+            channel_2: SquareChannel::new(false),
+            channel_3: WaveChannel::new(),
+            channel_4: NoiseChannel::new(),
+            frame_sequencer_counter: 0,
+            frame_sequencer_step: 0,
+            resampler: Resampler::new(CLOCK_SPEED, AUDIO_SAMPLE_RATE, 2),
+            frame_clocks: 0,
+            last_output: (0.0, 0.0),
+            samples: Vec::new(),
+        }
+    }
+
+    pub fn reset(&mut self) {
+        // Power on values of the audio registers after the boot ROM has run. NR52 needs
+        // to be written first so that the APU accepts the rest of the writes. The trigger
+        // bits of NRx4 are left clear as they are write only and read back as 1 anyway
+        self.write_register(NR52_ADDR, 0xF1);
+        self.write_register(NR10_ADDR, 0x80);
+        self.write_register(NR11_ADDR, 0xBF);
+        self.write_register(NR12_ADDR, 0xF3);
+        self.write_register(NR14_ADDR, 0x3F);
+        self.write_register(NR21_ADDR, 0x3F);
+        self.write_register(NR22_ADDR, 0x00);
+        self.write_register(NR24_ADDR, 0x3F);
+        self.write_register(NR30_ADDR, 0x7F);
+        self.write_register(NR31_ADDR, 0xFF);
+        self.write_register(NR32_ADDR, 0x9F);
+        self.write_register(NR34_ADDR, 0x3F);
+        self.write_register(NR41_ADDR, 0xFF);
+        self.write_register(NR42_ADDR, 0x00);
+        self.write_register(NR43_ADDR, 0x00);
+        self.write_register(NR44_ADDR, 0x3F);
+        self.write_register(NR50_ADDR, 0x77);
+        self.write_register(NR51_ADDR, 0xF3);
+
+        // The boot ROM plays its chime on channel 1, so it is left running (but silent) after boot
+        self.channel_1.enabled = true;
+    }
+
+    pub fn debug(&self) -> String {
+        format!(
+            "NR50: 0x{:02X}\nNR51: 0x{:02X}\nNR52: 0x{:02X}\nCH1: {}\nCH2: {}\nCH3: {}\nCH4: {}",
+            self.read_register(NR50_ADDR),
+            self.read_register(NR51_ADDR),
+            self.read_register(NR52_ADDR),
+            self.channel_1.enabled,
+            self.channel_2.enabled,
+            self.channel_3.enabled,
+            self.channel_4.enabled,
+        )
+    }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.registers);
+        writer.write_bool(self.powered);
+        writer.write_usize(self.frame_sequencer_counter);
+        writer.write_byte(self.frame_sequencer_step);
+
+        self.channel_1.save_state(writer);
+        self.channel_2.save_state(writer);
+        self.channel_3.save_state(writer);
+        self.channel_4.save_state(writer);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        let mut registers = [0; 0x30];
+        reader.read_bytes_into(&mut registers)?;
+        self.powered = reader.read_bool()?;
+        self.frame_sequencer_counter = reader.read_usize()?;
+        self.frame_sequencer_step = reader.read_byte()? % 8;
+
+        // Replay the register writes so every channel's settings (duty, envelope, etc.) are
+        // decoded again, then restore the running state of each channel on top of that
+        self.registers = registers;
+        self.restore_channel_settings();
+
+        self.channel_1.load_state(reader)?;
+        self.channel_2.load_state(reader)?;
+        self.channel_3.load_state(reader)?;
+        self.channel_4.load_state(reader)?;
+
+        Ok(())
+    }
+
+    pub fn update(&mut self, cycles: u8) {
+        let cycles = cycles as usize;
+
+        if self.powered {
+            self.channel_1.step(cycles);
+            self.channel_2.step(cycles);
+            self.channel_3.step(cycles);
+            self.channel_4.step(cycles);
+
+            self.frame_sequencer_counter += cycles;
+            while self.frame_sequencer_counter >= CYCLES_PER_FRAME_SEQUENCER_STEP {
+                self.frame_sequencer_counter -= CYCLES_PER_FRAME_SEQUENCER_STEP;
+                self.clock_frame_sequencer();
+            }
+        }
+
+        // Only amplitude changes need to be recorded, the resampler fills in everything else
+        let (left, right) = self.mix();
+        if left != self.last_output.0 {
+            self.resampler.add_delta(0, self.frame_clocks, left - self.last_output.0);
+        }
+        if right != self.last_output.1 {
+            self.resampler.add_delta(1, self.frame_clocks, right - self.last_output.1);
+        }
+        self.last_output = (left, right);
+
+        self.frame_clocks += cycles;
+        if self.frame_clocks >= CYCLES_PER_AUDIO_FRAME {
+            let samples = self.resampler.end_frame(self.frame_clocks);
+            self.samples.extend(samples);
+            self.frame_clocks = 0;
+
+            // If nothing is consuming the samples (i.e. running headless), only hold on to
+            // the most recent second of audio
+            let max_samples = self.resampler.get_sample_rate() * 2;
+            if self.samples.len() > max_samples {
+                let excess = self.samples.len() - max_samples;
+                self.samples.drain(0..excess);
+            }
+        }
+    }
+
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        std::mem::take(&mut self.samples)
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: usize) {
+        // Any samples produced at the old rate are dropped
+        self.resampler = Resampler::new(CLOCK_SPEED, sample_rate, 2);
+        self.frame_clocks = 0;
+        self.last_output = (0.0, 0.0);
+        self.samples.clear();
+    }
+
+    pub fn read_register(&self, addr: Word) -> Byte {
+        let idx = (addr - AUDIO_REGISTERS_START_ADDR) as usize;
+
+        if addr >= WAVE_RAM_START_ADDR {
+            return self.registers[idx];
+        }
+
+        if addr == NR52_ADDR {
+            // Bit 7 is the power state and Bits 0-3 are whether each channel is currently on
+            let mut status = 0x70;
+            if self.powered { set_bit(&mut status, 7) }
+            if self.channel_1.enabled { set_bit(&mut status, 0) }
+            if self.channel_2.enabled { set_bit(&mut status, 1) }
+            if self.channel_3.enabled { set_bit(&mut status, 2) }
+            if self.channel_4.enabled { set_bit(&mut status, 3) }
+            return status;
+        }
+
+        self.registers[idx] | REGISTER_READ_MASKS[idx]
+    }
+
+    pub fn write_register(&mut self, addr: Word, data: Byte) {
+        let idx = (addr - AUDIO_REGISTERS_START_ADDR) as usize;
+
+        // Wave RAM is always accessible, even while the APU is off
+        if addr >= WAVE_RAM_START_ADDR {
+            self.registers[idx] = data;
+            return;
+        }
+
+        if addr == NR52_ADDR {
+            self.write_power_control(data);
+            return;
+        }
+
+        // While the APU is off, all registers are read only
+        if !self.powered {
+            return;
+        }
+
+        self.registers[idx] = data;
+
+        match addr {
+            NR10_ADDR => {
+                if let Some(sweep) = &mut self.channel_1.sweep {
+                    sweep.write(data);
+                }
+            },
+            NR11_ADDR => Apu::write_square_length(&mut self.channel_1, data),
+            NR12_ADDR => Apu::write_square_envelope(&mut self.channel_1, data),
+            NR13_ADDR => self.channel_1.frequency = (self.channel_1.frequency & 0x700) | data as Word,
+            NR14_ADDR => Apu::write_square_control(&mut self.channel_1, data),
+            NR21_ADDR => Apu::write_square_length(&mut self.channel_2, data),
+            NR22_ADDR => Apu::write_square_envelope(&mut self.channel_2, data),
+            NR23_ADDR => self.channel_2.frequency = (self.channel_2.frequency & 0x700) | data as Word,
+            NR24_ADDR => Apu::write_square_control(&mut self.channel_2, data),
+            NR30_ADDR => {
+                // Bit 7 turns the wave channel's DAC on and off
+                self.channel_3.dac_enabled = is_bit_set(&data, 7);
+                if !self.channel_3.dac_enabled {
+                    self.channel_3.enabled = false;
+                }
+            },
+            NR31_ADDR => self.channel_3.length.load(data as usize),
+            NR32_ADDR => {
+                // Bits 6-5 select the output level, 0 = mute, 1 = 100%, 2 = 50%, 3 = 25%
+                self.channel_3.volume_shift = match (data >> 5) & 0x3 {
+                    0 => 4,
+                    1 => 0,
+                    2 => 1,
+                    _ => 2,
+                };
+            },
+            NR33_ADDR => self.channel_3.frequency = (self.channel_3.frequency & 0x700) | data as Word,
+            NR34_ADDR => {
+                self.channel_3.frequency = (self.channel_3.frequency & 0xFF) | (((data & 0x7) as Word) << 8);
+                self.channel_3.length.enabled = is_bit_set(&data, 6);
+                if is_bit_set(&data, 7) {
+                    self.channel_3.trigger();
+                }
+            },
+            NR41_ADDR => self.channel_4.length.load((data & 0x3F) as usize),
+            NR42_ADDR => {
+                self.channel_4.envelope.write(data);
+                self.channel_4.dac_enabled = (data & 0xF8) != 0;
+                if !self.channel_4.dac_enabled {
+                    self.channel_4.enabled = false;
+                }
+            },
+            NR43_ADDR => {
+                // Bits 7-4 - Clock shift
+                // Bit 3    - LFSR width (0 = 15 bit, 1 = 7 bit)
+                // Bits 2-0 - Clock divider
+                self.channel_4.clock_shift = data >> 4;
+                self.channel_4.width_mode = is_bit_set(&data, 3);
+                self.channel_4.divisor_code = (data & 0x7) as usize;
+            },
+            NR44_ADDR => {
+                self.channel_4.length.enabled = is_bit_set(&data, 6);
+                if is_bit_set(&data, 7) {
+                    self.channel_4.trigger();
+                }
+            },
+            _ => (),
+        };
+    }
+
+    fn restore_channel_settings(&mut self) {
+        let register = |addr: Word| self.registers[(addr - AUDIO_REGISTERS_START_ADDR) as usize];
+
+        if let Some(sweep) = &mut self.channel_1.sweep {
+            sweep.write(register(NR10_ADDR));
+        }
+
+        self.channel_1.duty = (register(NR11_ADDR) >> 6) as usize;
+        self.channel_1.envelope.write(register(NR12_ADDR));
+        self.channel_1.dac_enabled = (register(NR12_ADDR) & 0xF8) != 0;
+
+        self.channel_2.duty = (register(NR21_ADDR) >> 6) as usize;
+        self.channel_2.envelope.write(register(NR22_ADDR));
+        self.channel_2.dac_enabled = (register(NR22_ADDR) & 0xF8) != 0;
+
+        self.channel_3.dac_enabled = is_bit_set(&register(NR30_ADDR), 7);
+        self.channel_3.volume_shift = match (register(NR32_ADDR) >> 5) & 0x3 {
+            0 => 4,
+            1 => 0,
+            2 => 1,
+            _ => 2,
+        };
+
+        self.channel_4.envelope.write(register(NR42_ADDR));
+        self.channel_4.dac_enabled = (register(NR42_ADDR) & 0xF8) != 0;
+        self.channel_4.clock_shift = register(NR43_ADDR) >> 4;
+        self.channel_4.width_mode = is_bit_set(&register(NR43_ADDR), 3);
+        self.channel_4.divisor_code = (register(NR43_ADDR) & 0x7) as usize;
+    }
+
+    fn write_power_control(&mut self, data: Byte) {
+        let powered = is_bit_set(&data, 7);
+
+        if self.powered && !powered {
+            // Turning the APU off clears every register (except Wave RAM) and silences all channels
+            for addr in NR10_ADDR..NR52_ADDR {
+                self.registers[(addr - AUDIO_REGISTERS_START_ADDR) as usize] = 0;
+            }
+
+            self.channel_1 = SquareChannel::new(true);
+            self.channel_2 = SquareChannel::new(false);
+            self.channel_3 = WaveChannel::new();
+            self.channel_4 = NoiseChannel::new();
+        } else if !self.powered && powered {
+            // Turning the APU on resets the frame sequencer so the next step is step 0
+            self.frame_sequencer_counter = 0;
+            self.frame_sequencer_step = 0;
+        }
+
+        self.powered = powered;
+    }
+
+    fn write_square_length(channel: &mut SquareChannel, data: Byte) {
+        // Bits 7-6 - Wave duty
+        // Bits 5-0 - Initial length timer
+        channel.duty = (data >> 6) as usize;
+        channel.length.load((data & 0x3F) as usize);
+    }
+
+    fn write_square_envelope(channel: &mut SquareChannel, data: Byte) {
+        channel.envelope.write(data);
+
+        // The DAC is off when the upper 5 bits are all 0, which also disables the channel
+        channel.dac_enabled = (data & 0xF8) != 0;
+        if !channel.dac_enabled {
+            channel.enabled = false;
+        }
+    }
+
+    fn write_square_control(channel: &mut SquareChannel, data: Byte) {
+        // Bit 7    - Trigger
+        // Bit 6    - Length enable
+        // Bits 2-0 - Upper 3 bits of the frequency
+        channel.frequency = (channel.frequency & 0xFF) | (((data & 0x7) as Word) << 8);
+        channel.length.enabled = is_bit_set(&data, 6);
+        if is_bit_set(&data, 7) {
+            channel.trigger();
+        }
+    }
+
+    fn clock_frame_sequencer(&mut self) {
+        // Step   Length Ctr  Vol Env     Sweep
+        // 0      Clock       -           -
+        // 1      -           -           -
+        // 2      Clock       -           Clock
+        // 3      -           -           -
+        // 4      Clock       -           -
+        // 5      -           -           -
+        // 6      Clock       -           Clock
+        // 7      -           Clock       -
+        if self.frame_sequencer_step % 2 == 0 {
+            self.channel_1.length.clock(&mut self.channel_1.enabled);
+            self.channel_2.length.clock(&mut self.channel_2.enabled);
+            self.channel_3.length.clock(&mut self.channel_3.enabled);
+            self.channel_4.length.clock(&mut self.channel_4.enabled);
+        }
+
+        if self.frame_sequencer_step == 2 || self.frame_sequencer_step == 6 {
+            self.channel_1.clock_sweep();
+        }
+
+        if self.frame_sequencer_step == 7 {
+            self.channel_1.envelope.clock();
+            self.channel_2.envelope.clock();
+            self.channel_4.envelope.clock();
+        }
+
+        self.frame_sequencer_step = (self.frame_sequencer_step + 1) % 8;
+    }
+
+    fn mix(&self) -> (f32, f32) {
+        if !self.powered {
+            return (0.0, 0.0);
+        }
+
+        let wave_ram = &self.registers[(WAVE_RAM_START_ADDR - AUDIO_REGISTERS_START_ADDR) as usize..];
+        let outputs = [
+            (self.channel_1.get_output(), self.channel_1.dac_enabled),
+            (self.channel_2.get_output(), self.channel_2.dac_enabled),
+            (self.channel_3.get_output(wave_ram), self.channel_3.dac_enabled),
+            (self.channel_4.get_output(), self.channel_4.dac_enabled),
+        ];
+
+        let panning = self.registers[(NR51_ADDR - AUDIO_REGISTERS_START_ADDR) as usize];
+        let mut left = 0.0;
+        let mut right = 0.0;
+
+        for (i, (output, dac_enabled)) in outputs.iter().enumerate() {
+            if !dac_enabled {
+                continue;
+            }
+
+            // Each DAC maps the digital 0 - 15 value onto an analog -1.0 to 1.0 range
+            let analog = (*output as f32 / 7.5) - 1.0;
+
+            // Bits 0-3 of NR51 send channels 1-4 to the right output, bits 4-7 to the left
+            if is_bit_set(&panning, i + 4) {
+                left += analog;
+            }
+            if is_bit_set(&panning, i) {
+                right += analog;
+            }
+        }
+
+        // NR50 Bits 6-4 are the left volume and Bits 2-0 the right volume, where a value of 0 is
+        // treated as 1/8 volume rather than silence
+        let master_volume = self.registers[(NR50_ADDR - AUDIO_REGISTERS_START_ADDR) as usize];
+        let left_volume = (((master_volume >> 4) & 0x7) + 1) as f32 / 8.0;
+        let right_volume = ((master_volume & 0x7) + 1) as f32 / 8.0;
+
+        (left / 4.0 * left_volume, right / 4.0 * right_volume)
+    }
+}
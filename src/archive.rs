@@ -0,0 +1,110 @@
+use std::io::{Cursor, Read};
+use std::path::Path;
+
+use flate2::read::GzDecoder;
+use zip::ZipArchive;
+
+use crate::error::*;
+use crate::utils::*;
+
+// The name of the ROM inside an archive (if it has one), its contents, and warnings about
+// anything surprising in the archive (e.g. it holding more than one ROM)
+pub type ExtractedRom = (Option<String>, Vec<u8>, Vec<String>);
+
+pub fn extract_rom(data: Vec<u8>, entry: Option<&str>) -> Result<ExtractedRom, RustyBoyError> {
+    // Anything that isn't an archive is passed straight through
+    if data.starts_with(&ZIP_MAGIC) {
+        extract_from_zip(data, entry)
+    } else if data.starts_with(&GZIP_MAGIC) {
+        extract_from_gzip(&data)
+    } else {
+        Ok((None, data, Vec::new()))
+    }
+}
+
+fn extract_from_zip(data: Vec<u8>, entry: Option<&str>) -> Result<ExtractedRom, RustyBoyError> {
+    // Without an entry name we take the first Game Boy ROM in the archive. Entries can be asked
+    // for by their full path in the archive or just their file name
+    let mut archive = ZipArchive::new(Cursor::new(data))
+        .map_err(|e| RustyBoyError::InvalidArchive(e.to_string()))?;
+
+    // Names are collected in the order they are stored, so "first" means the same as in any
+    // other zip tool
+    let mut names = Vec::with_capacity(archive.len());
+    for i in 0..archive.len() {
+        let file = archive.by_index_raw(i)
+            .map_err(|e| RustyBoyError::InvalidArchive(e.to_string()))?;
+        if file.is_file() {
+            names.push(file.name().to_string());
+        }
+    }
+
+    let roms: Vec<&String> = names.iter()
+        .filter(|name| match entry {
+            Some(entry) => *name == entry || get_file_name(name) == entry,
+            None => is_rom_file_name(name),
+        })
+        .collect();
+
+    let name = match (roms.first(), entry) {
+        (Some(name), _) => name.to_string(),
+        (None, Some(entry)) => return Err(RustyBoyError::InvalidArchive(format!("No entry named {}", entry))),
+        (None, None) => return Err(RustyBoyError::InvalidArchive(String::from("No .gb or .gbc file found"))),
+    };
+
+    let mut warnings = Vec::new();
+    if entry.is_none() && roms.len() > 1 {
+        warnings.push(format!("Archive holds {} ROMs, using {}", roms.len(), name));
+    }
+
+    let mut file = archive.by_name(&name)
+        .map_err(|e| RustyBoyError::InvalidArchive(e.to_string()))?;
+    let contents = read_rom(&mut file)?;
+
+    Ok((Some(get_file_name(&name).to_string()), contents, warnings))
+}
+
+fn extract_from_gzip(data: &[u8]) -> Result<ExtractedRom, RustyBoyError> {
+    // A gzip file only holds one file, which may have its original name stored in the header
+    let mut decoder = GzDecoder::new(data);
+    let contents = read_rom(&mut decoder)?;
+
+    let name = decoder.header()
+        .and_then(|header| header.filename())
+        .map(|name| get_file_name(&String::from_utf8_lossy(name)).to_string());
+
+    Ok((name, contents, Vec::new()))
+}
+
+fn read_rom(reader: &mut impl Read) -> Result<Vec<u8>, RustyBoyError> {
+    // Archives can claim any size and compress very well, so stop reading one byte past the
+    // largest ROM rather than trusting them not to fill memory
+    let mut contents = Vec::new();
+    reader.take(MAX_ROM_SIZE as u64 + 1).read_to_end(&mut contents)?;
+    if contents.len() > MAX_ROM_SIZE {
+        return Err(RustyBoyError::InvalidArchive(format!(
+            "ROM is larger than the {} bytes a cartridge can hold", MAX_ROM_SIZE
+        )));
+    }
+    Ok(contents)
+}
+
+fn is_rom_file_name(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    name.ends_with(".gb") || name.ends_with(".gbc")
+}
+
+fn get_file_name(path: &str) -> &str {
+    // Zip entries always use / as the separator, whatever platform made them
+    path.rsplit(['/', '\\']).next().unwrap_or(path)
+}
+
+pub fn guess_rom_file_name(file: &str) -> Option<String> {
+    // Best guess at the ROM's name when the archive doesn't store it, i.e. game.gb.gz -> game.gb
+    let file_name = Path::new(file).file_name()?.to_string_lossy().into_owned();
+    let lowercase = file_name.to_ascii_lowercase();
+    match lowercase.ends_with(".gz") {
+        true => Some(file_name[..(file_name.len() - 3)].to_string()),
+        false => Some(file_name),
+    }
+}
@@ -0,0 +1,193 @@
+use std::fs;
+use std::fs::File;
+use std::path::Path;
+
+use crate::error::*;
+use crate::utils::*;
+
+#[derive(Debug)]
+pub struct CameraSensor {
+    /**
+    * What the Game Boy Camera is pointed at. Each frame is a 128x112 greyscale image, one byte per
+    * pixel with 0 being black and 255 white. Every photo taken uses the next frame, going back to
+    * the first after the last, so a single frame behaves like a still image. With no frames at
+    * all the camera sees a flat grey
+    **/
+
+    frames: Vec<Vec<Byte>>,
+    next_frame: usize,
+}
+
+impl CameraSensor {
+
+    pub fn new() -> CameraSensor {
+        CameraSensor {
+            frames: Vec::new(),
+            next_frame: 0,
+        }
+    }
+
+    pub fn from_frames(frames: Vec<Vec<Byte>>) -> Result<CameraSensor, RustyBoyError> {
+        if let Some(frame) = frames.iter().find(|frame| frame.len() != CAMERA_WIDTH * CAMERA_HEIGHT) {
+            return Err(RustyBoyError::InvalidImage(format!(
+                "Camera frames must be {}x{} pixels, got {} bytes", CAMERA_WIDTH, CAMERA_HEIGHT, frame.len()
+            )));
+        }
+
+        Ok(CameraSensor {
+            frames: frames,
+            next_frame: 0,
+        })
+    }
+
+    pub fn from_path(path: &str) -> Result<CameraSensor, RustyBoyError> {
+        // A PNG is used as a still image, a directory as a sequence of every PNG inside it in
+        // name order
+        let path = Path::new(path);
+        if !path.is_dir() {
+            return CameraSensor::from_frames(vec![load_png(path)?]);
+        }
+
+        let mut files: Vec<_> = fs::read_dir(path)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|file| file.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("png")))
+            .collect();
+        files.sort();
+
+        if files.is_empty() {
+            return Err(RustyBoyError::InvalidImage(format!("No PNG files found in {}", path.display())));
+        }
+
+        let frames = files.iter()
+            .map(|file| load_png(file))
+            .collect::<Result<Vec<_>, _>>()?;
+        CameraSensor::from_frames(frames)
+    }
+
+    pub fn capture(&mut self) -> Vec<Byte> {
+        if self.frames.is_empty() {
+            return vec![0x80; CAMERA_WIDTH * CAMERA_HEIGHT];
+        }
+
+        let frame = self.frames[self.next_frame].clone();
+        self.next_frame = (self.next_frame + 1) % self.frames.len();
+        frame
+    }
+}
+
+fn load_png(path: &Path) -> Result<Vec<Byte>, RustyBoyError> {
+    // Decode to 8 bit greyscale, then crop to the camera's aspect ratio and scale it down
+    let mut decoder = png::Decoder::new(File::open(path)?);
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let mut reader = decoder.read_info()
+        .map_err(|e| RustyBoyError::InvalidImage(format!("{} - {}", path.display(), e)))?;
+
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer)
+        .map_err(|e| RustyBoyError::InvalidImage(format!("{} - {}", path.display(), e)))?;
+
+    let channels = info.color_type.samples();
+    let (width, height) = (info.width as usize, info.height as usize);
+    let luma: Vec<Byte> = buffer[..info.buffer_size()]
+        .chunks(channels)
+        .map(|pixel| match channels {
+            1 | 2 => pixel[0],
+            _ => ((pixel[0] as u32 * 299 + pixel[1] as u32 * 587 + pixel[2] as u32 * 114) / 1000) as Byte,
+        })
+        .collect();
+
+    let (crop_width, crop_height) = match width * CAMERA_HEIGHT > height * CAMERA_WIDTH {
+        true => (height * CAMERA_WIDTH / CAMERA_HEIGHT, height),
+        false => (width, width * CAMERA_HEIGHT / CAMERA_WIDTH),
+    };
+    let (crop_x, crop_y) = ((width - crop_width) / 2, (height - crop_height) / 2);
+
+    let mut frame = Vec::with_capacity(CAMERA_WIDTH * CAMERA_HEIGHT);
+    for y in 0..CAMERA_HEIGHT {
+        for x in 0..CAMERA_WIDTH {
+            let source_x = crop_x + (x * crop_width) / CAMERA_WIDTH;
+            let source_y = crop_y + (y * crop_height) / CAMERA_HEIGHT;
+            frame.push(luma[source_y * width + source_x]);
+        }
+    }
+
+    Ok(frame)
+}
+
+pub fn get_capture_cycles(registers: &[Byte]) -> usize {
+    // How long the camera is busy for after starting a capture - longer exposures take longer
+    let exposure = ((registers[0x02] as usize) << 8) | registers[0x03] as usize;
+    let n_cycles = match is_bit_set(&registers[0x01], 7) {
+        true => 0,
+        false => CAMERA_CAPTURE_N_CYCLES,
+    };
+
+    CAMERA_CAPTURE_BASE_CYCLES + n_cycles + (exposure * CAMERA_CAPTURE_EXPOSURE_CYCLES)
+}
+
+// Run a sensor frame through the M64282FP's processing and return it as Game Boy tiles (16x14
+// tiles, 2 bits per pixel), which is how the camera writes it into RAM. The registers are:
+//     A001    Bit 7 N (exclusive edge mode), bits 5 - 6 VH (edge direction), bits 0 - 4 gain
+//     A002    Exposure time, upper byte
+//     A003    Exposure time, lower byte - 0x1000 leaves the image as it is
+//     A004    Bits 4 - 6 edge enhancement ratio, bit 3 invert, bits 0 - 2 output voltage
+//     A005    Zero point calibration and output offset
+//     A006 - A035    4x4 dithering matrix, 3 thresholds per pixel
+// The gain is an approximation of the datasheet's curve, and the output voltage, zero point
+// and offset are ignored as the analogue side isn't emulated
+pub fn process_image(registers: &[Byte], frame: &[Byte]) -> Vec<Byte> {
+    let gain_db = 1.5 * (registers[0x01] & 0x0F) as f32 + if is_bit_set(&registers[0x01], 4) { 6.0 } else { 0.0 };
+    let gain = 10f32.powf(gain_db / 20.0);
+    let exposure = (((registers[0x02] as u32) << 8) | registers[0x03] as u32) as f32 / 0x1000 as f32;
+
+    let exposed: Vec<f32> = frame.iter()
+        .map(|pixel| *pixel as f32 * gain * exposure)
+        .collect();
+    let get_exposed = |x: isize, y: isize| {
+        let x = x.clamp(0, CAMERA_WIDTH as isize - 1) as usize;
+        let y = y.clamp(0, CAMERA_HEIGHT as isize - 1) as usize;
+        exposed[y * CAMERA_WIDTH + x]
+    };
+
+    // Edge enhancement sharpens by subtracting neighbours from the pixel - VH bit 0 is horizontal
+    // and bit 1 vertical, both together is the 2D mode the Game Boy Camera uses
+    let edge_mode = (registers[0x01] >> 5) & 0x03;
+    let edge_ratio = [0.5, 0.75, 1.0, 1.25, 2.0, 3.0, 4.0, 5.0][((registers[0x04] >> 4) & 0x07) as usize];
+    let invert = is_bit_set(&registers[0x04], 3);
+
+    let mut tiles = vec![0; CAMERA_IMAGE_SIZE];
+    for y in 0..CAMERA_HEIGHT {
+        for x in 0..CAMERA_WIDTH {
+            let (ix, iy) = (x as isize, y as isize);
+            let mut value = get_exposed(ix, iy);
+            if edge_mode & 0x01 != 0 {
+                value += edge_ratio * (2.0 * get_exposed(ix, iy) - get_exposed(ix - 1, iy) - get_exposed(ix + 1, iy));
+            }
+            if edge_mode & 0x02 != 0 {
+                value += edge_ratio * (2.0 * get_exposed(ix, iy) - get_exposed(ix, iy - 1) - get_exposed(ix, iy + 1));
+            }
+
+            let mut value = value.clamp(0.0, 255.0) as Byte;
+            if invert {
+                value = 255 - value;
+            }
+
+            // The matrix gives three thresholds for each position in a repeating 4x4 pattern,
+            // anything darker than the first is black (3), brighter than the last is white (0)
+            let thresholds = &registers[(0x06 + ((x & 3) + (y & 3) * 4) * 3)..];
+            let color: Byte = match value {
+                v if v < thresholds[0] => 3,
+                v if v < thresholds[1] => 2,
+                v if v < thresholds[2] => 1,
+                _ => 0,
+            };
+
+            let tile_offset = (((y / 8) * (CAMERA_WIDTH / 8)) + (x / 8)) * 16 + (y % 8) * 2;
+            let bit = 7 - (x % 8);
+            tiles[tile_offset] |= (color & 0x01) << bit;
+            tiles[tile_offset + 1] |= ((color >> 1) & 0x01) << bit;
+        }
+    }
+
+    tiles
+}
@@ -0,0 +1,287 @@
+use crate::header::*;
+use crate::utils::*;
+
+// Colours for shades 0 - 3 (as picked by BGP, OBP0 and OBP1) in RGB555, as the boot ROM stores them
+pub type Palette = [Word; 4];
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct CompatPalettes {
+    /**
+    * The colours a CGB shows a DMG game in, which the boot ROM loads into background palette 0
+    * and object palettes 0 and 1 before locking the palettes. BGP, OBP0 and OBP1 then pick
+    * from these instead of the usual greys
+    **/
+
+    pub background: Palette,
+    pub object_0: Palette,
+    pub object_1: Palette,
+}
+
+impl CompatPalettes {
+
+    const fn new(background: Palette, object_0: Palette, object_1: Palette) -> CompatPalettes {
+        CompatPalettes {
+            background: background,
+            object_0: object_0,
+            object_1: object_1,
+        }
+    }
+
+    const fn combine(object_0: usize, object_1: usize, background: usize) -> CompatPalettes {
+        // Built from 3 of the boot ROM's palettes, in the order its combination table lists them
+        CompatPalettes::combine_colours(object_0 * 4, object_1 * 4, background * 4)
+    }
+
+    const fn combine_colours(object_0: usize, object_1: usize, background: usize) -> CompatPalettes {
+        // The boot ROM stores combinations as offsets in colours rather than palettes, and a few
+        // of them start part way through one palette and run into the next
+        CompatPalettes::new(get_colours(background), get_colours(object_0), get_colours(object_1))
+    }
+}
+
+const fn get_colours(offset: usize) -> Palette {
+    let mut palette = [0; 4];
+    let mut i = 0;
+    while i < 4 {
+        palette[i] = PALETTES[(offset + i) / 4][(offset + i) % 4];
+        i += 1;
+    }
+    palette
+}
+
+// Every palette the boot ROM has, which its combinations pick from
+const PALETTES: [Palette; 30] = [
+    [0x7FFF, 0x32BF, 0x00D0, 0x0000],
+    [0x639F, 0x4279, 0x15B0, 0x04CB],
+    [0x7FFF, 0x6E31, 0x454A, 0x0000],
+    [0x7FFF, 0x1BEF, 0x0200, 0x0000],
+    [0x7FFF, 0x421F, 0x1CF2, 0x0000],
+    [0x7FFF, 0x5294, 0x294A, 0x0000],
+    [0x7FFF, 0x03FF, 0x012F, 0x0000],
+    [0x7FFF, 0x03EF, 0x01D6, 0x0000],
+    [0x7FFF, 0x42B5, 0x3DC8, 0x0000],
+    [0x7E74, 0x03FF, 0x0180, 0x0000],
+    [0x67FF, 0x77AC, 0x1A13, 0x2D6B],
+    [0x7ED6, 0x4BFF, 0x2175, 0x0000],
+    [0x53FF, 0x4A5F, 0x7E52, 0x0000],
+    [0x4FFF, 0x7ED2, 0x3A4C, 0x1CE0],
+    [0x03ED, 0x7FFF, 0x255F, 0x0000],
+    [0x036A, 0x021F, 0x03FF, 0x7FFF],
+    [0x7FFF, 0x01DF, 0x0112, 0x0000],
+    [0x231F, 0x035F, 0x00F2, 0x0009],
+    [0x7FFF, 0x03EA, 0x011F, 0x0000],
+    [0x299F, 0x001A, 0x000C, 0x0000],
+    [0x7FFF, 0x027F, 0x001F, 0x0000],
+    [0x7FFF, 0x03E0, 0x0206, 0x0120],
+    [0x7FFF, 0x7EEB, 0x001F, 0x7C00],
+    [0x7FFF, 0x3FFF, 0x7E00, 0x001F],
+    [0x7FFF, 0x03FF, 0x001F, 0x0000],
+    [0x03FF, 0x001F, 0x000C, 0x0000],
+    [0x7FFF, 0x033F, 0x0193, 0x0000],
+    [0x0000, 0x4200, 0x037F, 0x7FFF],
+    [0x7FFF, 0x7E8C, 0x7C00, 0x0000],
+    [0x7FFF, 0x1BEF, 0x6180, 0x0000],
+];
+
+// The combinations of palettes (objects 0, objects 1, background) that titles and button presses
+// pick by index
+const COMBINATIONS: [CompatPalettes; 51] = [
+    CompatPalettes::combine(4, 4, 29),
+    CompatPalettes::combine(18, 18, 18),
+    CompatPalettes::combine(20, 20, 20),
+    CompatPalettes::combine(24, 24, 24),
+    CompatPalettes::combine(9, 9, 9),
+    CompatPalettes::combine(0, 0, 0),
+    CompatPalettes::combine(27, 27, 27),
+    CompatPalettes::combine(5, 5, 5),
+    CompatPalettes::combine(12, 12, 12),
+    CompatPalettes::combine(26, 26, 26),
+    CompatPalettes::combine(16, 8, 8),
+    CompatPalettes::combine(4, 28, 28),
+    CompatPalettes::combine(4, 2, 2),
+    CompatPalettes::combine(3, 4, 4),
+    CompatPalettes::combine(4, 29, 29),
+    CompatPalettes::combine(28, 4, 28),
+    CompatPalettes::combine(2, 17, 2),
+    CompatPalettes::combine(16, 16, 8),
+    CompatPalettes::combine(4, 4, 7),
+    CompatPalettes::combine(4, 4, 18),
+    CompatPalettes::combine(4, 4, 20),
+    CompatPalettes::combine(19, 19, 9),
+    CompatPalettes::combine_colours((4 * 4) - 1, (4 * 4) - 1, 11 * 4),
+    CompatPalettes::combine(17, 17, 2),
+    CompatPalettes::combine(4, 4, 2),
+    CompatPalettes::combine(4, 4, 3),
+    CompatPalettes::combine(28, 28, 0),
+    CompatPalettes::combine(3, 3, 0),
+    CompatPalettes::combine(0, 0, 1),
+    CompatPalettes::combine(18, 22, 18),
+    CompatPalettes::combine(20, 22, 20),
+    CompatPalettes::combine(24, 22, 24),
+    CompatPalettes::combine(16, 22, 8),
+    CompatPalettes::combine(17, 4, 13),
+    CompatPalettes::combine_colours((28 * 4) - 1, 0, 14 * 4),
+    CompatPalettes::combine_colours((28 * 4) - 1, 4 * 4, 15 * 4),
+    CompatPalettes::combine(19, 22, 9),
+    CompatPalettes::combine(16, 28, 10),
+    CompatPalettes::combine(4, 23, 28),
+    CompatPalettes::combine(17, 22, 2),
+    CompatPalettes::combine(4, 0, 2),
+    CompatPalettes::combine(4, 28, 3),
+    CompatPalettes::combine(28, 3, 0),
+    CompatPalettes::combine(3, 28, 4),
+    CompatPalettes::combine(21, 28, 4),
+    CompatPalettes::combine(3, 28, 0),
+    CompatPalettes::combine(25, 3, 28),
+    CompatPalettes::combine(0, 28, 8),
+    CompatPalettes::combine(4, 3, 28),
+    CompatPalettes::combine(28, 3, 6),
+    CompatPalettes::combine(4, 28, 29),
+];
+
+// What every game the boot ROM doesn't recognise gets, the same as holding right and A
+pub const DEFAULT_COMPAT_PALETTES: CompatPalettes = COMBINATIONS[0];
+
+// Games the boot ROM has palettes for, by the checksum of their title (0x0134 - 0x0143) and the
+// combination they get. Titles that share a checksum are told apart by their 4th letter, and
+// any other game gets DEFAULT_COMPAT_PALETTES. Blank names are titles nobody has matched up yet
+const TITLE_PALETTES: [(Byte, Option<char>, usize); 93] = [
+    (0x88, None, 4), // ALLEY WAY
+    (0x16, None, 5), // YAKUMAN
+    (0x36, None, 35), // BASEBALL, GAME&WATCH 2
+    (0xD1, None, 34), // TENNIS
+    (0xDB, None, 3), // TETRIS
+    (0xF2, None, 31), // QIX
+    (0x3C, None, 15), // DR.MARIO
+    (0x8C, None, 10), // RADARMISSION
+    (0x92, None, 5), // F1RACE
+    (0x3D, None, 19), // YOSSY NO TAMAGO
+    (0x5C, None, 36),
+    (0x58, None, 7), // X
+    (0xC9, None, 37), // MARIOLAND2
+    (0x3E, None, 30), // YOSSY NO COOKIE
+    (0x70, None, 44), // ZELDA
+    (0x1D, None, 21),
+    (0x59, None, 32),
+    (0x69, None, 31), // TETRIS FLASH
+    (0x19, None, 20), // DONKEY KONG
+    (0x35, None, 5), // MARIO'S PICROSS
+    (0xA8, None, 33),
+    (0x14, None, 13), // POKEMON RED, GAMEBOYCAMERA G
+    (0xAA, None, 14), // POKEMON GREEN
+    (0x75, None, 5), // PICROSS 2
+    (0x95, None, 29), // YOSSY NO PANEPON
+    (0x99, None, 5), // KIRAKIRA KIDS
+    (0x34, None, 18), // GAMEBOY GALLERY
+    (0x6F, None, 9), // POCKETCAMERA
+    (0x15, None, 3),
+    (0xFF, None, 2), // BALLOON KID
+    (0x97, None, 26), // KINGOFTHEZOO
+    (0x4B, None, 25), // DMG FOOTBALL
+    (0x90, None, 25), // WORLD CUP
+    (0x17, None, 41), // OTHELLO
+    (0x10, None, 42), // SUPER RC PRO-AM
+    (0x39, None, 26), // DYNABLASTER
+    (0xF7, None, 45), // BOY AND BLOB GB2
+    (0xF6, None, 42), // MEGAMAN
+    (0xA2, None, 45), // STAR WARS-NOA
+    (0x49, None, 36),
+    (0x4E, None, 38), // WAVERACE
+    (0x43, None, 26),
+    (0x68, None, 42), // LOLO2
+    (0xE0, None, 30), // YOSHI'S COOKIE
+    (0x8B, None, 41), // MYSTIC QUEST
+    (0xF0, None, 34),
+    (0xCE, None, 34), // TOPRANKINGTENNIS
+    (0x0C, None, 5), // MANSELL
+    (0x29, None, 42), // MEGAMAN3
+    (0xE8, None, 6), // SPACE INVADERS
+    (0xB7, None, 5), // GAME&WATCH
+    (0x86, None, 33), // DONKEYKONGLAND95
+    (0x9A, None, 25), // ASTEROIDS/MISCMD
+    (0x52, None, 42), // STREET FIGHTER 2
+    (0x01, None, 42), // DEFENDER/JOUST
+    (0x9D, None, 40), // KILLERINSTINCT95
+    (0x71, None, 2), // TETRIS BLAST
+    (0x9C, None, 16), // PINOCCHIO
+    (0xBD, None, 25),
+    (0x5D, None, 42), // BA.TOSHINDEN
+    (0x6D, None, 42), // NETTOU KOF 95
+    (0x67, None, 5),
+    (0x3F, None, 0), // TETRIS PLUS
+    (0x6B, None, 39), // DONKEYKONGLAND 3
+    (0xB3, Some('B'), 36),
+    (0x46, Some('E'), 32), // SUPER MARIOLAND
+    (0x28, Some('F'), 25), // GOLF
+    (0xA5, Some('A'), 6), // SOLARSTRIKER
+    (0xC6, Some('A'), 32), // GBWARS
+    (0xD3, Some('R'), 12), // KAERUNOTAMENI
+    (0x27, Some('B'), 36),
+    (0x61, Some('E'), 11), // POKEMON BLUE
+    (0x18, Some('K'), 39), // DONKEYKONGLAND
+    (0x66, Some('E'), 18), // GAMEBOY GALLERY2
+    (0x6A, Some('K'), 39), // DONKEYKONGLAND 2
+    (0xBF, Some(' '), 24), // KID ICARUS
+    (0x0D, Some('R'), 31), // TETRIS2
+    (0xF4, Some('-'), 50),
+    (0xB3, Some('U'), 17), // MOGURANYA
+    (0x46, Some('R'), 46),
+    (0x28, Some('A'), 6), // GALAGA&GALAXIAN
+    (0xA5, Some('R'), 27), // BT2RAGNAROKWORLD
+    (0xC6, Some(' '), 0), // KEN GRIFFEY JR
+    (0xD3, Some('I'), 47),
+    (0x27, Some('N'), 41), // MAGNETIC SOCCER
+    (0x61, Some('A'), 41), // VEGAS STAKES
+    (0x18, Some('I'), 0),
+    (0x66, Some('L'), 0), // MILLI/CENTI/PEDE
+    (0x6A, Some('I'), 19), // MARIO & YOSHI
+    (0xBF, Some('C'), 34), // SOCCER
+    (0x0D, Some('E'), 23), // POKEBOM
+    (0xF4, Some(' '), 18), // G&W GALLERY
+    (0xB3, Some('R'), 29), // TETRIS ATTACK
+];
+
+pub fn get_title_palettes(header: &CartridgeHeader, title_checksum: Byte) -> CompatPalettes {
+    // Only games published by Nintendo are looked up, the same check the boot ROM leaves in B
+    if header.get_licensee_code() != "01" {
+        return DEFAULT_COMPAT_PALETTES;
+    }
+
+    let fourth_letter = header.title.chars().nth(3);
+    TITLE_PALETTES.iter()
+        .find(|(checksum, letter, _)| *checksum == title_checksum && (letter.is_none() || *letter == fourth_letter))
+        .map(|(_, _, combination)| COMBINATIONS[*combination])
+        .unwrap_or(DEFAULT_COMPAT_PALETTES)
+}
+
+pub fn get_button_palettes(direction: usize, button: Option<usize>) -> Option<CompatPalettes> {
+    // Holding a direction, optionally with A or B, while the logo scrolls picks one of 12
+    // combinations instead, whatever the game
+    let combination = match (direction, button) {
+        (RIGHT_BUTTON, None) => 1,
+        (LEFT_BUTTON, None) => 48,
+        (UP_BUTTON, None) => 5,
+        (DOWN_BUTTON, None) => 8,
+        (RIGHT_BUTTON, Some(A_BUTTON)) => 0,
+        (LEFT_BUTTON, Some(A_BUTTON)) => 40,
+        (UP_BUTTON, Some(A_BUTTON)) => 43,
+        (DOWN_BUTTON, Some(A_BUTTON)) => 3,
+        (RIGHT_BUTTON, Some(B_BUTTON)) => 6,
+        (LEFT_BUTTON, Some(B_BUTTON)) => 7,
+        (UP_BUTTON, Some(B_BUTTON)) => 28,
+        (DOWN_BUTTON, Some(B_BUTTON)) => 49,
+        _ => return None,
+    };
+
+    Some(COMBINATIONS[combination])
+}
+
+pub fn get_palette_data(palette: &Palette) -> [Byte; 8] {
+    // The palette as it sits in CGB palette memory - 4 little endian RGB555 colours
+    let mut data = [0; 8];
+    for (i, color) in palette.iter().enumerate() {
+        data[i * 2] = (color & 0xFF) as Byte;
+        data[(i * 2) + 1] = (color >> 8) as Byte;
+    }
+    data
+}
@@ -1,10 +1,18 @@
 use std::fs::OpenOptions;
 use std::io::prelude::*;
+use std::io;
 
+use crate::camera::*;
+use crate::error::*;
+use crate::header::*;
 use crate::interrupts::*;
 use crate::mmu::*;
+use crate::model::*;
 use crate::ops::*;
 use crate::ppu::*;
+use crate::rusty_boy::CpuRegisters;
+use crate::save_formats::*;
+use crate::state::*;
 use crate::timer::*;
 use crate::utils::*;
 
@@ -42,6 +50,9 @@ pub struct Cpu {
     will_enable_interrupts: bool,
     will_disable_interrupts: bool,
     halted: bool,
+
+    // STOP's low power mode, left when a button pulls one of the selected joypad lines low
+    stopped: bool,
     cycle_tracker: u8,
     last_op: Option<Operation>,
     debug_ctr: usize,
@@ -67,6 +78,7 @@ impl Cpu {
             will_enable_interrupts: false,
             will_disable_interrupts: false,
             halted: false,
+            stopped: false,
             cycle_tracker: 0,
             last_op: None,
             debug_ctr: 0,
@@ -84,103 +96,281 @@ impl Cpu {
         self.mmu.debug()
     }
 
+    pub fn debug_apu(&self) -> String {
+        self.mmu.debug_apu()
+    }
+
     pub fn debug_cpu(&self) -> String {
         unsafe {
             format!("PC: {:04X}\nSP: {:04X}\nAF: {:04X}\nBC: {:04X}\nDE: {:04X}\nHL: {:04X}\n", self.program_counter, self.stack_pointer, self.af.val, self.bc.val, self.de.val, self.hl.val)
         }
     }
 
+    pub fn get_registers(&self) -> CpuRegisters {
+        unsafe {
+            CpuRegisters {
+                af: self.af.val,
+                bc: self.bc.val,
+                de: self.de.val,
+                hl: self.hl.val,
+                sp: self.stack_pointer,
+                pc: self.program_counter,
+                interrupts_enabled: self.interrupts_enabled,
+                halted: self.halted,
+                stopped: self.stopped,
+            }
+        }
+    }
+
+    pub fn peek_memory(&self, addr: Word) -> Byte {
+        self.mmu.read_byte(addr)
+    }
+
     pub fn get_external_ram(&self) -> &[Byte] {
         self.mmu.get_external_ram()
     }
 
-    pub fn load_external_ram(&mut self, buffer: Vec<Byte>) {
-        self.mmu.load_external_ram(buffer);
+    pub fn set_camera_sensor(&mut self, sensor: CameraSensor) {
+        self.mmu.set_camera_sensor(sensor);
+    }
+
+    pub fn set_tilt(&mut self, x: f32, y: f32) {
+        self.mmu.set_tilt(x, y);
+    }
+
+    pub fn take_rumble_strength(&mut self) -> f32 {
+        self.mmu.take_rumble_strength()
+    }
+
+    pub fn get_save_data(&self) -> Vec<Byte> {
+        self.mmu.get_save_data()
+    }
+
+    pub fn take_external_ram_written(&mut self) -> bool {
+        self.mmu.take_external_ram_written()
+    }
+
+    pub fn export_save(&self, format: SaveFormat) -> (Vec<Byte>, Vec<String>) {
+        self.mmu.export_save(format)
+    }
+
+    pub fn load_external_ram(&mut self, buffer: Vec<Byte>) -> Vec<String> {
+        self.mmu.load_external_ram(buffer)
+    }
+
+    pub fn set_boot_rom(&mut self, boot_rom: Vec<Byte>) -> Result<(), RustyBoyError> {
+        self.mmu.set_boot_rom(boot_rom)?;
+        self.mmu.reset();
+        self.reset();
+        Ok(())
+    }
+
+    pub fn get_model(&self) -> Model {
+        self.mmu.get_model()
+    }
+
+    pub fn set_model(&mut self, model: Model) -> Result<(), RustyBoyError> {
+        self.mmu.set_model(model)?;
+        self.mmu.reset();
+        self.reset();
+        Ok(())
     }
 
     pub fn reset(&mut self) {
+        self.halted = false;
+        self.stopped = false;
+        self.will_disable_interrupts = false;
+        self.will_enable_interrupts = false;
+
+        if self.mmu.is_boot_rom_mapped() {
+            // Everything starts out clear, the boot ROM sets the registers to what games expect
+            self.program_counter = 0x0000;
+            self.stack_pointer = 0x0000;
+            self.af.val = 0x0000;
+            self.bc.val = 0x0000;
+            self.de.val = 0x0000;
+            self.hl.val = 0x0000;
+            self.interrupts_enabled = false;
+            self.timer.reset(0x0000);
+            return;
+        }
+
         self.program_counter = PROGRAM_COUNTER_INIT;
         self.stack_pointer = STACK_POINTER_INIT;
 
-        self.af.val = 0x01B0;
-        self.bc.val = 0x0013;
-        self.de.val = 0x00D8;
-        self.hl.val = 0x014D;
+        // Registers are left as the model's boot ROM would leave them - this is how games tell
+        // which Game Boy they are running on, i.e. A is 0x11 on a CGB to unlock its features
+        let model = self.mmu.get_model();
+        let header = self.mmu.get_cartridge_header();
+        let title_checksum = self.mmu.get_title_checksum();
+        let (af, bc, de, hl) = model.get_initial_registers(header, title_checksum);
+        let divider = model.get_initial_divider(header);
 
-        if self.mmu.is_cgb() {
-            // Set A to 0x11 to unlock Color Gameboy functionality
-            self.af.parts.hi = 0x11;
-        }
+        self.af.val = af;
+        self.bc.val = bc;
+        self.de.val = de;
+        self.hl.val = hl;
+        self.timer.reset(divider);
 
-        self.halted = false;
         self.interrupts_enabled = true;
-        self.will_disable_interrupts = false;
-        self.will_enable_interrupts = false;
     }
 
-    pub fn execute(&mut self) -> u8 {
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        unsafe {
+            writer.write_word(self.af.val);
+            writer.write_word(self.bc.val);
+            writer.write_word(self.de.val);
+            writer.write_word(self.hl.val);
+        }
+
+        writer.write_word(self.program_counter);
+        writer.write_word(self.stack_pointer);
+        writer.write_bool(self.interrupts_enabled);
+        writer.write_bool(self.will_enable_interrupts);
+        writer.write_bool(self.will_disable_interrupts);
+        writer.write_bool(self.halted);
+        writer.write_bool(self.stopped);
+
+        // The last operation is only used to delay the effect of DI and EI, so that's all we keep
+        writer.write_byte(match self.last_op {
+            None => 0,
+            Some(Operation::DI) => 1,
+            Some(Operation::EI) => 2,
+            Some(_) => 3,
+        });
+
+        self.mmu.save_state(writer);
+        self.timer.save_state(writer);
+        self.ppu.save_state(writer);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> io::Result<()> {
+        self.af.val = reader.read_word()?;
+        self.bc.val = reader.read_word()?;
+        self.de.val = reader.read_word()?;
+        self.hl.val = reader.read_word()?;
+
+        self.program_counter = reader.read_word()?;
+        self.stack_pointer = reader.read_word()?;
+        self.interrupts_enabled = reader.read_bool()?;
+        self.will_enable_interrupts = reader.read_bool()?;
+        self.will_disable_interrupts = reader.read_bool()?;
+        self.halted = reader.read_bool()?;
+        self.stopped = reader.read_bool()?;
+
+        self.last_op = match reader.read_byte()? {
+            0 => None,
+            1 => Some(Operation::DI),
+            2 => Some(Operation::EI),
+            _ => Some(Operation::NOP),
+        };
+
+        self.mmu.load_state(reader)?;
+        self.timer.load_state(reader)?;
+        self.ppu.load_state(reader)?;
+
+        Ok(())
+    }
+
+    pub fn get_rom_file_name(&self) -> Option<&str> {
+        self.mmu.get_rom_file_name()
+    }
+
+    pub fn get_cartridge_header(&self) -> &CartridgeHeader {
+        self.mmu.get_cartridge_header()
+    }
+
+    pub fn get_rom_problems(&self) -> Vec<String> {
+        self.mmu.get_rom_problems()
+    }
+
+    pub fn debug_rom_header(&self) -> String {
+        self.mmu.debug_rom_header()
+    }
+
+    pub fn get_rom_checksums(&self) -> (Byte, Word) {
+        self.mmu.get_rom_checksums()
+    }
+
+    pub fn execute(&mut self) -> Result<u8, RustyBoyError> {
+        // Returns the cycles taken at normal speed, which is what the rest of the system runs at
+        // even when the CPU is in double speed mode
+
         // Reset the cycle tracker for mid iteration cycle syncing
         self.cycle_tracker = 0;
 
-        let op = self.read_memory(self.program_counter);
-        let opcode = OPCODE_MAP
-            .get(&op)
-            .expect(&format!("OpCode 0x{:02x} is not recognized at PC - {:04X}", op, self.program_counter));
+        // While stopped everything is frozen, including the PPU and timer. Time still passes for
+        // the caller so frames keep being presented
+        if self.stopped {
+            if !self.mmu.is_joypad_line_low() {
+                return Ok(4);
+            }
+            self.stopped = false;
+        }
 
         if self.debug_log {
             // self.debug();
         }
 
-        // If in HALT mode, don't execute any instructions and incremeny by 1 T-cycle (4 M-cycles)
-        if self.halted {
+        // If in HALT mode, or waiting for a VRAM DMA transfer, don't execute any instructions and
+        // incremeny by 1 T-cycle (4 M-cycles)
+        if self.halted || self.mmu.stall_for_vram_dma(4) {
             self.sync_cycles(4);
-            return 4;
+            return Ok(self.get_normal_speed_cycles(4));
         }
 
+        // The unused opcodes (i.e. 0xD3, 0xDB, etc.) hang the CPU on real hardware, so report
+        // that rather than carry on. The PC is left where it is, so we stay locked up
+        let op = self.read_memory(self.program_counter);
+        let opcode = match OPCODE_MAP.get(&op) {
+            Some(opcode) => *opcode,
+            None => return Err(RustyBoyError::CpuLockup { opcode: op, program_counter: self.program_counter }),
+        };
+
         // println!("{:04X} - {}", self.program_counter, self.debug_ctr);
 
         self.debug_pc = self.program_counter;
         self.program_counter = self.program_counter.wrapping_add(1);
 
         let cycles = match opcode.operation {
-            Operation::ADC => self.do_add(&opcode, true),
-            Operation::ADD => self.do_add(&opcode, false),
-            Operation::ADD_16_BIT => self.do_add_16_bit(&opcode),
-            Operation::AND => self.do_and(&opcode),
-            Operation::CALL => self.do_call(&opcode),
+            Operation::ADC => self.do_add(&opcode, true)?,
+            Operation::ADD => self.do_add(&opcode, false)?,
+            Operation::ADD_16_BIT => self.do_add_16_bit(&opcode)?,
+            Operation::AND => self.do_and(&opcode)?,
+            Operation::CALL => self.do_call(&opcode)?,
             Operation::CCF => self.do_complement_carry(&opcode),
-            Operation::CP => self.do_compare(&opcode),
+            Operation::CP => self.do_compare(&opcode)?,
             Operation::CPL => self.do_complement(&opcode),
             Operation::DAA => self.do_daa(&opcode),
-            Operation::DEC => self.do_decrement(&opcode),
-            Operation::DEC_16_BIT => self.do_decrement_16_bit(&opcode),
+            Operation::DEC => self.do_decrement(&opcode)?,
+            Operation::DEC_16_BIT => self.do_decrement_16_bit(&opcode)?,
             Operation::DI => self.do_disable_interrupts(&opcode),
             Operation::EI => self.do_enable_interrupts(&opcode),
             Operation::HALT => self.do_halt(&opcode),
-            Operation::INC => self.do_increment(&opcode),
-            Operation::INC_16_BIT => self.do_increment_16_bit(&opcode),
-            Operation::JP => self.do_jump(&opcode),
-            Operation::JR => self.do_jump_relative(&opcode),
-            Operation::LD => self.do_load(&opcode),
-            Operation::LDH => self.do_load_h(&opcode),
+            Operation::INC => self.do_increment(&opcode)?,
+            Operation::INC_16_BIT => self.do_increment_16_bit(&opcode)?,
+            Operation::JP => self.do_jump(&opcode)?,
+            Operation::JR => self.do_jump_relative(&opcode)?,
+            Operation::LD => self.do_load(&opcode)?,
+            Operation::LDH => self.do_load_h(&opcode)?,
             Operation::NOP => opcode.cycles,
-            Operation::OR => self.do_or(&opcode),
-            Operation::POP => self.do_pop(&opcode),
-            Operation::PREFIX => self.do_prefix(),
-            Operation::PUSH => self.do_push(&opcode),
-            Operation::RET => self.do_return(&opcode),
-            Operation::RETI => self.do_return(&opcode),
+            Operation::OR => self.do_or(&opcode)?,
+            Operation::POP => self.do_pop(&opcode)?,
+            Operation::PREFIX => self.do_prefix()?,
+            Operation::PUSH => self.do_push(&opcode)?,
+            Operation::RET => self.do_return(&opcode)?,
+            Operation::RETI => self.do_return(&opcode)?,
             Operation::RLA => self.do_rla(&opcode),
             Operation::RLCA => self.do_rlca(&opcode),
             Operation::RRA => self.do_rra(&opcode),
             Operation::RRCA => self.do_rrca(&opcode),
-            Operation::RST => self.do_restart(&opcode),
-            Operation::SBC => self.do_sub(&opcode, true),
+            Operation::RST => self.do_restart(&opcode)?,
+            Operation::SBC => self.do_sub(&opcode, true)?,
             Operation::SCF => self.do_set_carry_flag(&opcode),
-            Operation::STOP => opcode.cycles,
-            Operation::SUB => self.do_sub(&opcode, false),
-            Operation::XOR => self.do_xor(&opcode),
-            _ => panic!("Operation not found - {}", opcode.operation)
+            Operation::STOP => self.do_stop(&opcode),
+            Operation::SUB => self.do_sub(&opcode, false)?,
+            Operation::XOR => self.do_xor(&opcode)?,
+            _ => return Err(RustyBoyError::CpuLockup { opcode: op, program_counter: self.debug_pc }),
         };
 
         // Deal with interrupt enabling/disabling
@@ -190,7 +380,7 @@ impl Cpu {
         // Sync remaining cycles for the instruction
         self.sync_cycles(cycles - self.cycle_tracker);
 
-        cycles
+        Ok(self.get_normal_speed_cycles(cycles))
     }
 
     pub fn handle_interrupts(&mut self) {
@@ -208,6 +398,14 @@ impl Cpu {
         self.ppu.get_tiles(&self.mmu)
     }
 
+    pub fn take_audio_samples(&mut self) -> Vec<f32> {
+        self.mmu.take_audio_samples()
+    }
+
+    pub fn set_audio_sample_rate(&mut self, sample_rate: usize) {
+        self.mmu.set_audio_sample_rate(sample_rate);
+    }
+
     pub fn set_button_state(&mut self, button: usize) {
         self.mmu.set_button_state(button);
     }
@@ -216,17 +414,34 @@ impl Cpu {
         self.mmu.reset_button_state(button);
     }
 
+    pub fn set_compat_palettes(&mut self, direction: usize, button: Option<usize>) -> bool {
+        self.mmu.set_compat_palettes(direction, button)
+    }
+
     fn sync_cycles(&mut self, cycles: u8) {
         // Instructions increment other components clock during execution
         // not all at once - this is used to be able to sync components
         // during execution
 
+        // Only the timer runs off the CPU clock, everything else (including the RTC and camera
+        // on the cartridge) carries on at normal speed when the CPU is in double speed mode
+        let normal_speed_cycles = self.get_normal_speed_cycles(cycles);
+
         self.timer.update(&mut self.mmu, cycles);
-        self.ppu.update_graphics(&mut self.mmu, cycles, self.debug_pc == 0x0B7A);
+        self.ppu.update_graphics(&mut self.mmu, normal_speed_cycles, self.debug_pc == 0x0B7A);
+        self.mmu.update_apu(normal_speed_cycles);
+        self.mmu.update_mbc(normal_speed_cycles);
 
         self.cycle_tracker += cycles;
     }
 
+    fn get_normal_speed_cycles(&self, cycles: u8) -> u8 {
+        match self.mmu.is_double_speed() {
+            true => cycles / 2,
+            false => cycles,
+        }
+    }
+
     fn service_interrupt(&mut self, interrupt: Interrupt) {
         // Unhalt the CPU
         self.halted = false;
@@ -395,7 +610,14 @@ impl Cpu {
         };
     }
 
-    fn do_add(&mut self, opcode: &OpCode, with_carry: bool) -> u8 {
+    fn get_unknown_operation_error(&self, opcode: &OpCode) -> RustyBoyError {
+        // A handler was given an opcode it doesn't implement, meaning the opcode tables and the
+        // handlers disagree. That's our bug rather than the game's, but stop emulating the same
+        // way as for an unused opcode rather than take the whole program down
+        RustyBoyError::CpuLockup { opcode: opcode.code, program_counter: self.debug_pc }
+    }
+
+    fn do_add(&mut self, opcode: &OpCode, with_carry: bool) -> Result<u8, RustyBoyError> {
         unsafe {
             let to_add = match opcode.code {
                 0x80 => self.bc.parts.hi,
@@ -416,7 +638,7 @@ impl Cpu {
                 0x8F => self.af.parts.hi,
                 0xC6 => self.get_next_byte(),
                 0xCE => self.get_next_byte(),
-                _ => panic!("Unknown operation encountered 0x{:02x} - {}", opcode.code, opcode.mnemonic),
+                _ => return Err(self.get_unknown_operation_error(opcode)),
             };
 
             let carry = if with_carry && self.is_carry_flag_set() {1} else {0};
@@ -431,11 +653,11 @@ impl Cpu {
 
             self.af.parts.hi = (res & 0xFF) as Byte;
 
-            opcode.cycles
+            Ok(opcode.cycles)
         }
     }
 
-    fn do_add_16_bit(&mut self, opcode: &OpCode) -> u8 {
+    fn do_add_16_bit(&mut self, opcode: &OpCode) -> Result<u8, RustyBoyError> {
         if opcode.code == 0xE8 {
             // 16 bit arithmetic but it doesn't follow the same flag conventions
             let offset = self.get_next_byte_signed();
@@ -458,7 +680,7 @@ impl Cpu {
                     0x19 => self.de.val,
                     0x29 => self.hl.val,
                     0x39 => self.stack_pointer,
-                    _ => panic!("Unknown operation encountered 0x{:02x} - {}", opcode.code, opcode.mnemonic),
+                    _ => return Err(self.get_unknown_operation_error(opcode)),
                 };
 
                 self.update_sub_flag(false);
@@ -468,10 +690,10 @@ impl Cpu {
             }
         }
 
-        opcode.cycles
+        Ok(opcode.cycles)
     }
 
-    fn do_and(&mut self, opcode: &OpCode) -> u8 {
+    fn do_and(&mut self, opcode: &OpCode) -> Result<u8, RustyBoyError> {
         unsafe {
             let to_and = match opcode.code {
                 0xA0 => self.bc.parts.hi,
@@ -483,7 +705,7 @@ impl Cpu {
                 0xA6 => self.read_memory(self.hl.val),
                 0xA7 => self.af.parts.hi,
                 0xE6 => self.get_next_byte(),
-                _ => panic!("Unknown operation encountered 0x{:02x} - {}", opcode.code, opcode.mnemonic),
+                _ => return Err(self.get_unknown_operation_error(opcode)),
             };
 
             self.af.parts.hi &= to_and;
@@ -493,11 +715,11 @@ impl Cpu {
             self.update_sub_flag(false);
             self.update_carry_flag(false);
 
-            opcode.cycles
+            Ok(opcode.cycles)
         }
     }
 
-    fn do_bit(&mut self, opcode: &OpCode) -> u8 {
+    fn do_bit(&mut self, opcode: &OpCode) -> Result<u8, RustyBoyError> {
         unsafe {
             match opcode.code {
                 0x40 => self.update_zero_flag(!is_bit_set(&self.bc.parts.hi, 0)),
@@ -596,19 +818,19 @@ impl Cpu {
                     self.update_zero_flag(!is_bit_set(&val, 7));
                 },
                 0x7F => self.update_zero_flag(!is_bit_set(&self.af.parts.hi, 7)),
-                _ => panic!("Unknown prefix operation encountered 0x{:02x} - {}", opcode.code, opcode.mnemonic),
+                _ => return Err(self.get_unknown_operation_error(opcode)),
             };
 
             self.update_half_carry_flag(true);
             self.update_sub_flag(false);
 
-            opcode.cycles
+            Ok(opcode.cycles)
         }
     }
 
-    fn do_call(&mut self, opcode: &OpCode) -> u8 {
+    fn do_call(&mut self, opcode: &OpCode) -> Result<u8, RustyBoyError> {
         unsafe {
-            match opcode.code {
+            Ok(match opcode.code {
                 0xC4 => {
                     if !self.is_zero_flag_set() {
                         let addr = self.get_next_word();
@@ -659,12 +881,12 @@ impl Cpu {
                         opcode.alt_cycles.unwrap_or(opcode.cycles)
                     }
                 },
-                _ => panic!("Unknown operation encountered 0x{:02x} - {}", opcode.code, opcode.mnemonic),
-            }
+                _ => return Err(self.get_unknown_operation_error(opcode)),
+            })
         }
     }
 
-    fn do_compare(&mut self, opcode: &OpCode) -> u8 {
+    fn do_compare(&mut self, opcode: &OpCode) -> Result<u8, RustyBoyError> {
         unsafe {
             let to_cp = match opcode.code {
                 0xB8 => self.bc.parts.hi,
@@ -676,7 +898,7 @@ impl Cpu {
                 0xBE => self.read_memory(self.hl.val),
                 0xBF => self.af.parts.hi,
                 0xFE => self.get_next_byte(),
-                _ => panic!("Unknown operation encountered 0x{:02x} - {}", opcode.code, opcode.mnemonic),
+                _ => return Err(self.get_unknown_operation_error(opcode)),
             };
 
             self.update_zero_flag(self.af.parts.hi == to_cp);
@@ -684,7 +906,7 @@ impl Cpu {
             self.update_carry_flag(self.af.parts.hi < to_cp);
             self.update_half_carry_flag(((self.af.parts.hi as SignedWord) & 0xF) - ((to_cp as SignedWord) & 0xF) < 0);
 
-            opcode.cycles
+            Ok(opcode.cycles)
         }
     }
 
@@ -743,7 +965,7 @@ impl Cpu {
         }
     }
 
-    fn do_decrement(&mut self, opcode: &OpCode) -> u8 {
+    fn do_decrement(&mut self, opcode: &OpCode) -> Result<u8, RustyBoyError> {
         unsafe {
             let result = match opcode.code {
                 0x05 => {
@@ -781,28 +1003,28 @@ impl Cpu {
                     self.af.parts.hi = self.af.parts.hi.wrapping_sub(1);
                     self.af.parts.hi
                 },
-                _ => panic!("Unknown operation encountered 0x{:02x} - {}", opcode.code, opcode.mnemonic),
+                _ => return Err(self.get_unknown_operation_error(opcode)),
             };
 
             self.update_zero_flag(result == 0);
             self.update_sub_flag(true);
             self.update_half_carry_flag(result & 0xF == 0xF);
 
-            opcode.cycles
+            Ok(opcode.cycles)
         }
     }
 
-    fn do_decrement_16_bit(&mut self, opcode: &OpCode) -> u8 {
+    fn do_decrement_16_bit(&mut self, opcode: &OpCode) -> Result<u8, RustyBoyError> {
         unsafe {
             match opcode.code {
                 0x0B => self.bc.val = self.bc.val.wrapping_sub(1),
                 0x1B => self.de.val = self.de.val.wrapping_sub(1),
                 0x2B => self.hl.val = self.hl.val.wrapping_sub(1),
                 0x3B => self.stack_pointer = self.stack_pointer.wrapping_sub(1),
-                _ => panic!("Unknown operation encountered 0x{:02x} - {}", opcode.code, opcode.mnemonic),
+                _ => return Err(self.get_unknown_operation_error(opcode)),
             };
 
-            opcode.cycles
+            Ok(opcode.cycles)
         }
     }
 
@@ -821,7 +1043,22 @@ impl Cpu {
         opcode.cycles
     }
 
-    fn do_increment(&mut self, opcode: &OpCode) -> u8 {
+    fn do_stop(&mut self, opcode: &OpCode) -> u8 {
+        // STOP is followed by a byte that is skipped over (usually 0x00)
+        self.program_counter = self.program_counter.wrapping_add(1);
+
+        // Both switching speed and stopping reset DIV
+        self.write_memory(DIVIDER_REGISTER_ADDR, 0);
+
+        // On a CGB with a speed switch prepared in KEY1, STOP switches speed instead of stopping
+        if !self.mmu.switch_speed() {
+            self.stopped = true;
+        }
+
+        opcode.cycles
+    }
+
+    fn do_increment(&mut self, opcode: &OpCode) -> Result<u8, RustyBoyError> {
         unsafe {
             let result = match opcode.code {
                 0x04 => {
@@ -859,33 +1096,33 @@ impl Cpu {
                     self.af.parts.hi = self.af.parts.hi.wrapping_add(1);
                     self.af.parts.hi
                 },
-                _ => panic!("Unknown operation encountered 0x{:02x} - {}", opcode.code, opcode.mnemonic),
+                _ => return Err(self.get_unknown_operation_error(opcode)),
             };
 
             self.update_zero_flag(result == 0);
             self.update_sub_flag(false);
             self.update_half_carry_flag(result & 0xF == 0);
 
-            opcode.cycles
+            Ok(opcode.cycles)
         }
     }
 
-    fn do_increment_16_bit(&mut self, opcode: &OpCode) -> u8 {
+    fn do_increment_16_bit(&mut self, opcode: &OpCode) -> Result<u8, RustyBoyError> {
         unsafe {
             match opcode.code {
                 0x03 => self.bc.val = self.bc.val.wrapping_add(1),
                 0x13 => self.de.val = self.de.val.wrapping_add(1),
                 0x23 => self.hl.val = self.hl.val.wrapping_add(1),
                 0x33 => self.stack_pointer = self.stack_pointer.wrapping_add(1),
-                _ => panic!("Unknown operation encountered 0x{:02x} - {}", opcode.code, opcode.mnemonic),
+                _ => return Err(self.get_unknown_operation_error(opcode)),
             };
 
-            opcode.cycles
+            Ok(opcode.cycles)
         }
     }
 
-    fn do_jump(&mut self, opcode: &OpCode) -> u8 {
-        match opcode.code {
+    fn do_jump(&mut self, opcode: &OpCode) -> Result<u8, RustyBoyError> {
+        Ok(match opcode.code {
             0xC2 => {
                 self.program_counter = if !self.is_zero_flag_set() { self.get_next_word() } else { self.program_counter.wrapping_add(2) };
                 if self.is_zero_flag_set() { opcode.alt_cycles.unwrap_or(opcode.cycles) } else { opcode.cycles }
@@ -912,12 +1149,12 @@ impl Cpu {
                     opcode.cycles
                 }
             },
-            _ => panic!("Unknown operation encountered 0x{:02x} - {}", opcode.code, opcode.mnemonic),
-        }
+            _ => return Err(self.get_unknown_operation_error(opcode)),
+        })
     }
 
-    fn do_jump_relative(&mut self, opcode: &OpCode) -> u8 {
-        match opcode.code {
+    fn do_jump_relative(&mut self, opcode: &OpCode) -> Result<u8, RustyBoyError> {
+        Ok(match opcode.code {
             0x18 => {
                 let offset = self.get_next_byte_signed() as SignedWord;
                 if offset > 0 {
@@ -976,11 +1213,11 @@ impl Cpu {
 
                 if !self.is_carry_flag_set() { opcode.alt_cycles.unwrap_or(opcode.cycles) } else { opcode.cycles }
             },
-            _ => panic!("Unknown operation encountered 0x{:02x} - {}", opcode.code, opcode.mnemonic),
-        }
+            _ => return Err(self.get_unknown_operation_error(opcode)),
+        })
     }
 
-    fn do_load(&mut self, opcode: &OpCode) -> u8 {
+    fn do_load(&mut self, opcode: &OpCode) -> Result<u8, RustyBoyError> {
         unsafe {
             match opcode.code {
                 0x01 => self.bc.val = self.get_next_word(),
@@ -1115,14 +1352,14 @@ impl Cpu {
                     self.sync_cycles(8);
                     self.af.parts.hi = self.read_memory(word);
                 },
-                _ => panic!("Unknown operation encountered 0x{:02x} - {}", opcode.code, opcode.mnemonic),
+                _ => return Err(self.get_unknown_operation_error(opcode)),
             };
 
-            opcode.cycles
+            Ok(opcode.cycles)
         }
     }
 
-    fn do_load_h(&mut self, opcode: &OpCode) -> u8 {
+    fn do_load_h(&mut self, opcode: &OpCode) -> Result<u8, RustyBoyError> {
         unsafe {
             match opcode.code {
                 0xE0 => {
@@ -1135,14 +1372,14 @@ impl Cpu {
                     self.sync_cycles(4);
                     self.af.parts.hi = self.read_memory(0xFF00 | addr as Word);
                 },
-                _ => panic!("Unknown operation encountered 0x{:02x} - {}", opcode.code, opcode.mnemonic),
+                _ => return Err(self.get_unknown_operation_error(opcode)),
             };
 
-            opcode.cycles
+            Ok(opcode.cycles)
         }
     }
 
-    fn do_or(&mut self, opcode: &OpCode) -> u8 {
+    fn do_or(&mut self, opcode: &OpCode) -> Result<u8, RustyBoyError> {
         unsafe {
             let to_or = match opcode.code {
                 0xB0 => self.bc.parts.hi,
@@ -1154,7 +1391,7 @@ impl Cpu {
                 0xB6 => self.read_memory(self.hl.val),
                 0xB7 => self.af.parts.hi,
                 0xF6 => self.get_next_byte(),
-                _ => panic!("Unknown operation encountered 0x{:02x} - {}", opcode.code, opcode.mnemonic),
+                _ => return Err(self.get_unknown_operation_error(opcode)),
             };
 
             self.af.parts.hi |= to_or;
@@ -1164,11 +1401,11 @@ impl Cpu {
             self.update_sub_flag(false);
             self.update_carry_flag(false);
 
-            opcode.cycles
+            Ok(opcode.cycles)
         }
     }
 
-    fn do_pop(&mut self, opcode: &OpCode) -> u8 {
+    fn do_pop(&mut self, opcode: &OpCode) -> Result<u8, RustyBoyError> {
         unsafe {
             match opcode.code {
                 0xC1 => self.bc.val = self.pop_word_from_stack(),
@@ -1178,53 +1415,56 @@ impl Cpu {
                     self.af.val = self.pop_word_from_stack();
                     self.af.parts.lo &= 0xF0;
                 },
-                _ => panic!("Unknown operation encountered 0x{:02x} - {}", opcode.code, opcode.mnemonic),
+                _ => return Err(self.get_unknown_operation_error(opcode)),
             };
 
-            opcode.cycles
+            Ok(opcode.cycles)
         }
     }
 
-    fn do_prefix(&mut self) -> u8 {
+    fn do_prefix(&mut self) -> Result<u8, RustyBoyError> {
         let op = self.read_memory(self.program_counter);
-        let opcode = PREFIX_OPCODE_MAP
-            .get(&op)
-            .expect(&format!("Prefix OpCode 0x{:02x} is not recognized", op));
+        let opcode = match PREFIX_OPCODE_MAP.get(&op) {
+            Some(opcode) => *opcode,
+            None => return Err(RustyBoyError::CpuLockup { opcode: op, program_counter: self.program_counter }),
+        };
 
         self.program_counter = self.program_counter.wrapping_add(1);
 
-        match opcode.operation {
-            Operation::BIT => self.do_bit(&opcode),
-            Operation::RES => self.do_res(&opcode),
-            Operation::RL => self.do_rotate_left(&opcode, true),
-            Operation::RLC => self.do_rotate_left(&opcode, false),
-            Operation::RR => self.do_rotate_right(&opcode, true),
-            Operation::RRC => self.do_rotate_right(&opcode, false),
-            Operation::SET => self.do_set(&opcode),
-            Operation::SLA => self.do_shift_left(&opcode),
-            Operation::SRA => self.do_shift_right(&opcode, true),
-            Operation::SRL => self.do_shift_right(&opcode, false),
-            Operation::SWAP => self.do_swap(&opcode),
-            _ => panic!("Operation not found - {}", opcode.operation)
-        }
+        let cycles = match opcode.operation {
+            Operation::BIT => self.do_bit(&opcode)?,
+            Operation::RES => self.do_res(&opcode)?,
+            Operation::RL => self.do_rotate_left(&opcode, true)?,
+            Operation::RLC => self.do_rotate_left(&opcode, false)?,
+            Operation::RR => self.do_rotate_right(&opcode, true)?,
+            Operation::RRC => self.do_rotate_right(&opcode, false)?,
+            Operation::SET => self.do_set(&opcode)?,
+            Operation::SLA => self.do_shift_left(&opcode)?,
+            Operation::SRA => self.do_shift_right(&opcode, true)?,
+            Operation::SRL => self.do_shift_right(&opcode, false)?,
+            Operation::SWAP => self.do_swap(&opcode)?,
+            _ => return Err(RustyBoyError::CpuLockup { opcode: op, program_counter: self.program_counter.wrapping_sub(1) }),
+        };
+
+        Ok(cycles)
     }
 
-    fn do_push(&mut self, opcode: &OpCode) -> u8 {
+    fn do_push(&mut self, opcode: &OpCode) -> Result<u8, RustyBoyError> {
         unsafe {
             match opcode.code {
                 0xC5 => self.push_word_to_stack(self.bc.val),
                 0xD5 => self.push_word_to_stack(self.de.val),
                 0xE5 => self.push_word_to_stack(self.hl.val),
                 0xF5 => self.push_word_to_stack(self.af.val),
-                _ => panic!("Unknown operation encountered 0x{:02x} - {}", opcode.code, opcode.mnemonic),
+                _ => return Err(self.get_unknown_operation_error(opcode)),
             };
 
-            opcode.cycles
+            Ok(opcode.cycles)
         }
     }
 
-    fn do_return(&mut self, opcode: &OpCode) -> u8 {
-        match opcode.code {
+    fn do_return(&mut self, opcode: &OpCode) -> Result<u8, RustyBoyError> {
+        Ok(match opcode.code {
             0xC0 => {
                 self.program_counter = if !self.is_zero_flag_set() { self.pop_word_from_stack() } else { self.program_counter };
                 if self.is_zero_flag_set() { opcode.alt_cycles.unwrap_or(opcode.cycles) } else { opcode.cycles }
@@ -1250,11 +1490,11 @@ impl Cpu {
                 self.interrupts_enabled = true;
                 opcode.cycles
             },
-            _ => panic!("Unknown operation encountered 0x{:02x} - {}", opcode.code, opcode.mnemonic),
-        }
+            _ => return Err(self.get_unknown_operation_error(opcode)),
+        })
     }
 
-    fn do_restart(&mut self, opcode: &OpCode) -> u8 {
+    fn do_restart(&mut self, opcode: &OpCode) -> Result<u8, RustyBoyError> {
         self.push_word_to_stack(self.program_counter);
 
         match opcode.code {
@@ -1266,13 +1506,13 @@ impl Cpu {
             0xEF => self.program_counter = 0x28,
             0xF7 => self.program_counter = 0x30,
             0xFF => self.program_counter = 0x38,
-            _ => panic!("Unknown operation encountered 0x{:02x} - {}", opcode.code, opcode.mnemonic),
+            _ => return Err(self.get_unknown_operation_error(opcode)),
         }
 
-        opcode.cycles
+        Ok(opcode.cycles)
     }
 
-    fn do_res(&mut self, opcode: &OpCode) -> u8 {
+    fn do_res(&mut self, opcode: &OpCode) -> Result<u8, RustyBoyError> {
 
         unsafe {
             match opcode.code {
@@ -1388,14 +1628,14 @@ impl Cpu {
                     self.write_memory(self.hl.val, val);
                 },
                 0xBF => reset_bit(&mut self.af.parts.hi, 7),
-                _ => panic!("Unknown operation encountered 0x{:02x} - {}", opcode.code, opcode.mnemonic),
+                _ => return Err(self.get_unknown_operation_error(opcode)),
             };
 
-            opcode.cycles
+            Ok(opcode.cycles)
         }
     }
 
-    fn do_rotate_left(&mut self, opcode: &OpCode, through_carry: bool) -> u8 {
+    fn do_rotate_left(&mut self, opcode: &OpCode, through_carry: bool) -> Result<u8, RustyBoyError> {
         unsafe {
             let do_rotate = |val: &mut Byte, carry_bit: u8| {
                 let most_significant_bit = get_bit_val(&val, 7);
@@ -1436,7 +1676,7 @@ impl Cpu {
                     (res, most_significant_bit)
                 },
                 0x17 => do_rotate(&mut self.af.parts.hi, carry_bit),
-                _ => panic!("Unknown prefix operation encountered 0x{:02x} - {}", opcode.code, opcode.mnemonic),
+                _ => return Err(self.get_unknown_operation_error(opcode)),
             };
 
             self.update_zero_flag(res == 0);
@@ -1444,11 +1684,11 @@ impl Cpu {
             self.update_half_carry_flag(false);
             self.update_sub_flag(false);
 
-            opcode.cycles
+            Ok(opcode.cycles)
         }
     }
 
-    fn do_rotate_right(&mut self, opcode: &OpCode, through_carry: bool) -> u8 {
+    fn do_rotate_right(&mut self, opcode: &OpCode, through_carry: bool) -> Result<u8, RustyBoyError> {
         unsafe {
             let do_rotate = |val: &mut Byte, carry_bit: u8| {
                 let least_significant_bit = get_bit_val(&val, 0);
@@ -1489,7 +1729,7 @@ impl Cpu {
                     (res, least_significant_bit)
                 },
                 0x1F => do_rotate(&mut self.af.parts.hi, carry_bit),
-                _ => panic!("Unknown prefix operation encountered 0x{:02x} - {}", opcode.code, opcode.mnemonic),
+                _ => return Err(self.get_unknown_operation_error(opcode)),
             };
 
             self.update_zero_flag(res == 0);
@@ -1497,7 +1737,7 @@ impl Cpu {
             self.update_half_carry_flag(false);
             self.update_sub_flag(false);
 
-            opcode.cycles
+            Ok(opcode.cycles)
         }
     }
 
@@ -1567,7 +1807,7 @@ impl Cpu {
         }
     }
 
-    fn do_set(&mut self, opcode: &OpCode) -> u8 {
+    fn do_set(&mut self, opcode: &OpCode) -> Result<u8, RustyBoyError> {
 
         unsafe {
             match opcode.code {
@@ -1683,10 +1923,10 @@ impl Cpu {
                     self.write_memory(self.hl.val, val);
                 },
                 0xFF => set_bit(&mut self.af.parts.hi, 7),
-                _ => panic!("Unknown prefix operation encountered 0x{:02x} - {}", opcode.code, opcode.mnemonic),
+                _ => return Err(self.get_unknown_operation_error(opcode)),
             };
 
-            opcode.cycles
+            Ok(opcode.cycles)
         }
     }
 
@@ -1697,7 +1937,7 @@ impl Cpu {
         opcode.cycles
     }
 
-    fn do_shift_left(&mut self, opcode: &OpCode) -> u8 {
+    fn do_shift_left(&mut self, opcode: &OpCode) -> Result<u8, RustyBoyError> {
         unsafe {
             let do_shift = |val: &mut Byte| {
                 let most_significant_bit = get_bit_val(&val, 7);
@@ -1723,7 +1963,7 @@ impl Cpu {
                     (res, most_significant_bit)
                 },
                 0x27 => do_shift(&mut self.af.parts.hi),
-                _ => panic!("Unknown prefix operation encountered 0x{:02x} - {}", opcode.code, opcode.mnemonic),
+                _ => return Err(self.get_unknown_operation_error(opcode)),
             };
 
             self.update_zero_flag(res == 0);
@@ -1731,11 +1971,11 @@ impl Cpu {
             self.update_half_carry_flag(false);
             self.update_sub_flag(false);
 
-            opcode.cycles
+            Ok(opcode.cycles)
         }
     }
 
-    fn do_shift_right(&mut self, opcode: &OpCode, maintain_msb: bool) -> u8 {
+    fn do_shift_right(&mut self, opcode: &OpCode, maintain_msb: bool) -> Result<u8, RustyBoyError> {
         unsafe {
             let do_shift = |val: &mut Byte| {
                 let most_significant_bit = get_bit_val(&val, 7);
@@ -1780,7 +2020,7 @@ impl Cpu {
                     (res, least_significant_bit)
                 },
                 0x3F => do_shift(&mut &mut self.af.parts.hi),
-                _ => panic!("Unknown prefix operation encountered 0x{:02x} - {}", opcode.code, opcode.mnemonic),
+                _ => return Err(self.get_unknown_operation_error(opcode)),
             };
 
             self.update_zero_flag(res == 0);
@@ -1788,11 +2028,11 @@ impl Cpu {
             self.update_half_carry_flag(false);
             self.update_sub_flag(false);
 
-            opcode.cycles
+            Ok(opcode.cycles)
         }
     }
 
-    fn do_sub(&mut self, opcode: &OpCode, with_carry: bool) -> u8 {
+    fn do_sub(&mut self, opcode: &OpCode, with_carry: bool) -> Result<u8, RustyBoyError> {
         unsafe {
             let to_sub = match opcode.code {
                 0x90 => self.bc.parts.hi,
@@ -1813,7 +2053,7 @@ impl Cpu {
                 0x9F => self.af.parts.hi,
                 0xD6 => self.get_next_byte(),
                 0xDE => self.get_next_byte(),
-                _ => panic!("Unknown operation encountered 0x{:02x} - {}", opcode.code, opcode.mnemonic),
+                _ => return Err(self.get_unknown_operation_error(opcode)),
             };
 
             let carry = if with_carry && self.is_carry_flag_set() {1} else {0};
@@ -1828,11 +2068,11 @@ impl Cpu {
 
             self.af.parts.hi = res;
 
-            opcode.cycles
+            Ok(opcode.cycles)
         }
     }
 
-    fn do_swap(&mut self, opcode: &OpCode) -> u8 {
+    fn do_swap(&mut self, opcode: &OpCode) -> Result<u8, RustyBoyError> {
         unsafe {
             let swap = |val: &mut Byte| {
                 let res = ((*val & 0xF) << 4) | (*val >> 4);
@@ -1856,7 +2096,7 @@ impl Cpu {
                     res
                 },
                 0x37 => swap(&mut self.af.parts.hi),
-                _ => panic!("Unknown prefix operation encountered 0x{:02x} - {}", opcode.code, opcode.mnemonic),
+                _ => return Err(self.get_unknown_operation_error(opcode)),
             };
 
             self.update_zero_flag(res == 0);
@@ -1864,11 +2104,11 @@ impl Cpu {
             self.update_half_carry_flag(false);
             self.update_sub_flag(false);
 
-            opcode.cycles
+            Ok(opcode.cycles)
         }
     }
 
-    fn do_xor(&mut self, opcode: &OpCode) -> u8 {
+    fn do_xor(&mut self, opcode: &OpCode) -> Result<u8, RustyBoyError> {
         unsafe {
             let to_xor = match opcode.code {
                 0xA8 => self.bc.parts.hi,
@@ -1880,7 +2120,7 @@ impl Cpu {
                 0xAE => self.read_memory(self.hl.val),
                 0xAF => self.af.parts.hi,
                 0xEE => self.get_next_byte(),
-                _ => panic!("Unknown operation encountered 0x{:02x} - {}", opcode.code, opcode.mnemonic),
+                _ => return Err(self.get_unknown_operation_error(opcode)),
             };
 
             self.af.parts.hi ^= to_xor;
@@ -1890,7 +2130,7 @@ impl Cpu {
             self.update_sub_flag(false);
             self.update_carry_flag(false);
 
-            opcode.cycles
+            Ok(opcode.cycles)
         }
     }
 
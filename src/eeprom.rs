@@ -0,0 +1,238 @@
+use std::io;
+
+use crate::state::*;
+use crate::utils::*;
+
+#[derive(Debug)]
+pub struct Eeprom {
+    /**
+    * The 93LC56 serial EEPROM used by MBC7 cartridges to hold save data - 128 words of 16 bits.
+    * The game bit bangs it through a single register, setting chip select (CS), the clock (CLK)
+    * and data in (DI), and reading data out (DO) back. DI is sampled on each rising edge of CLK
+    * while CS is high, and commands are a start bit (1) followed by 10 bits:
+    *    10 AAAAAAAA    READ  - DO outputs a dummy 0, then the word at A, MSB first, carrying on
+    *                           into the following words for as long as the clock keeps running
+    *    01 AAAAAAAA    WRITE - followed by 16 bits of data to write to A
+    *    11 AAAAAAAA    ERASE - set the word at A to 0xFFFF
+    *    00 11XXXXXX    EWEN  - enable writes and erases
+    *    00 00XXXXXX    EWDS  - disable writes and erases
+    *    00 10XXXXXX    ERAL  - erase every word
+    *    00 01XXXXXX    WRAL  - followed by 16 bits of data to write to every word
+    * Only the lower 7 bits of A are used. Words are stored big endian, so a save file matches
+    * what other emulators produce
+    **/
+
+    data: [Byte; EEPROM_SIZE],
+    write_enabled: bool,
+
+    chip_select: bool,
+    clock: bool,
+    data_in: bool,
+    data_out: bool,
+
+    command: Word,
+    command_bits: usize,
+    state: EepromState,
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+enum EepromState {
+    COMMAND,
+    READ { addr: usize, value: Word, bits_remaining: usize },
+    WRITE { addr: Option<usize>, value: Word, bits_remaining: usize },
+}
+
+impl Eeprom {
+
+    pub fn new() -> Eeprom {
+        Eeprom {
+            data: [0xFF; EEPROM_SIZE],
+            write_enabled: false,
+            chip_select: false,
+            clock: false,
+            data_in: false,
+            data_out: true,
+            command: 0,
+            command_bits: 0,
+            state: EepromState::COMMAND,
+        }
+    }
+
+    pub fn get_data(&self) -> &[Byte] {
+        &self.data
+    }
+
+    pub fn load_data(&mut self, buffer: &[Byte]) {
+        let len = std::cmp::min(self.data.len(), buffer.len());
+        self.data[..len].copy_from_slice(&buffer[..len]);
+    }
+
+    pub fn read(&self) -> Byte {
+        (self.chip_select as Byte) << 7
+            | (self.clock as Byte) << 6
+            | (self.data_in as Byte) << 1
+            | self.data_out as Byte
+    }
+
+    pub fn write(&mut self, data: Byte) {
+        let chip_select = is_bit_set(&data, 7);
+        let clock = is_bit_set(&data, 6);
+        self.data_in = is_bit_set(&data, 1);
+
+        if !chip_select {
+            // Dropping chip select abandons whatever command was in progress
+            self.command = 0;
+            self.command_bits = 0;
+            self.state = EepromState::COMMAND;
+            self.data_out = true;
+        } else if !self.clock && clock {
+            self.clock_rising_edge();
+        }
+
+        self.chip_select = chip_select;
+        self.clock = clock;
+    }
+
+    fn clock_rising_edge(&mut self) {
+        match self.state {
+            EepromState::COMMAND => self.shift_command_bit(),
+            EepromState::READ { addr, value, bits_remaining } => {
+                self.data_out = (value & 0x8000) != 0;
+
+                // Once a word has been read out, move straight on to the next one
+                self.state = match bits_remaining - 1 {
+                    0 => {
+                        let next_addr = (addr + 1) % EEPROM_WORDS;
+                        EepromState::READ { addr: next_addr, value: self.get_word(next_addr), bits_remaining: 16 }
+                    },
+                    bits_remaining => EepromState::READ { addr: addr, value: value << 1, bits_remaining: bits_remaining },
+                };
+            },
+            EepromState::WRITE { addr, value, bits_remaining } => {
+                let value = (value << 1) | self.data_in as Word;
+                if bits_remaining > 1 {
+                    self.state = EepromState::WRITE { addr: addr, value: value, bits_remaining: bits_remaining - 1 };
+                    return;
+                }
+
+                // A missing address means write all
+                if self.write_enabled {
+                    match addr {
+                        Some(addr) => self.set_word(addr, value),
+                        None => (0..EEPROM_WORDS).for_each(|addr| self.set_word(addr, value)),
+                    };
+                }
+                self.finish_command();
+            },
+        };
+    }
+
+    fn shift_command_bit(&mut self) {
+        // Ignore anything before the start bit
+        if self.command_bits == 0 && !self.data_in {
+            return;
+        }
+
+        self.command = (self.command << 1) | self.data_in as Word;
+        self.command_bits += 1;
+
+        // Start bit + 2 bit opcode + 8 bit address
+        if self.command_bits < 11 {
+            return;
+        }
+
+        let opcode = (self.command >> 8) & 0x03;
+        let addr = (self.command & 0x7F) as usize;
+        match opcode {
+            0b10 => {
+                self.data_out = false;
+                self.state = EepromState::READ { addr: addr, value: self.get_word(addr), bits_remaining: 16 };
+            },
+            0b01 => self.state = EepromState::WRITE { addr: Some(addr), value: 0, bits_remaining: 16 },
+            0b11 => {
+                if self.write_enabled {
+                    self.set_word(addr, 0xFFFF);
+                }
+                self.finish_command();
+            },
+            _ => {
+                match (self.command >> 6) & 0x03 {
+                    0b11 => self.write_enabled = true,
+                    0b00 => self.write_enabled = false,
+                    0b10 => {
+                        if self.write_enabled {
+                            self.data = [0xFF; EEPROM_SIZE];
+                        }
+                    },
+                    _ => self.state = EepromState::WRITE { addr: None, value: 0, bits_remaining: 16 },
+                };
+
+                if self.state == EepromState::COMMAND {
+                    self.finish_command();
+                }
+            },
+        };
+    }
+
+    fn finish_command(&mut self) {
+        // Writes happen instantly, so always report ready
+        self.command = 0;
+        self.command_bits = 0;
+        self.state = EepromState::COMMAND;
+        self.data_out = true;
+    }
+
+    fn get_word(&self, addr: usize) -> Word {
+        ((self.data[addr * 2] as Word) << 8) | self.data[addr * 2 + 1] as Word
+    }
+
+    fn set_word(&mut self, addr: usize, value: Word) {
+        self.data[addr * 2] = (value >> 8) as Byte;
+        self.data[addr * 2 + 1] = value as Byte;
+    }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.data);
+        writer.write_bool(self.write_enabled);
+        writer.write_bool(self.chip_select);
+        writer.write_bool(self.clock);
+        writer.write_bool(self.data_in);
+        writer.write_bool(self.data_out);
+        writer.write_word(self.command);
+        writer.write_usize(self.command_bits);
+
+        // State is written as kind, address (0xFF for all), value and bits remaining
+        let (kind, addr, value, bits_remaining) = match self.state {
+            EepromState::COMMAND => (0, 0, 0, 0),
+            EepromState::READ { addr, value, bits_remaining } => (1, addr, value, bits_remaining),
+            EepromState::WRITE { addr, value, bits_remaining } => (2, addr.unwrap_or(0xFF), value, bits_remaining),
+        };
+        writer.write_byte(kind);
+        writer.write_usize(addr);
+        writer.write_word(value);
+        writer.write_usize(bits_remaining);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> io::Result<()> {
+        reader.read_bytes_into(&mut self.data)?;
+        self.write_enabled = reader.read_bool()?;
+        self.chip_select = reader.read_bool()?;
+        self.clock = reader.read_bool()?;
+        self.data_in = reader.read_bool()?;
+        self.data_out = reader.read_bool()?;
+        self.command = reader.read_word()?;
+        self.command_bits = reader.read_usize()?;
+
+        let kind = reader.read_byte()?;
+        let addr = reader.read_usize()? % 0x100;
+        let value = reader.read_word()?;
+        let bits_remaining = std::cmp::min(reader.read_usize()?, 16);
+        self.state = match kind {
+            1 => EepromState::READ { addr: addr % EEPROM_WORDS, value: value, bits_remaining: std::cmp::max(bits_remaining, 1) },
+            2 if addr == 0xFF => EepromState::WRITE { addr: None, value: value, bits_remaining: std::cmp::max(bits_remaining, 1) },
+            2 => EepromState::WRITE { addr: Some(addr % EEPROM_WORDS), value: value, bits_remaining: std::cmp::max(bits_remaining, 1) },
+            _ => EepromState::COMMAND,
+        };
+        Ok(())
+    }
+}
@@ -0,0 +1,66 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+use crate::utils::*;
+
+#[derive(Debug)]
+pub enum RustyBoyError {
+    // Reading the ROM (or another file) failed
+    Io(io::Error),
+
+    // The ROM is too small or its header doesn't make sense
+    InvalidHeader(String),
+
+    // The cartridge type at 0x0147 isn't one we can emulate
+    UnsupportedCartridgeType(Byte),
+
+    // An image for the camera sensor couldn't be decoded
+    InvalidImage(String),
+
+    // A zip or gzip file couldn't be read, or has no ROM in it
+    InvalidArchive(String),
+
+    // An IPS, UPS or BPS patch is corrupt or was made for a different ROM
+    InvalidPatch(String),
+
+    // A boot ROM isn't the size of either the DMG or CGB one
+    InvalidBootRom(String),
+
+    // The CPU hit an opcode that locks up real hardware
+    CpuLockup { opcode: Byte, program_counter: Word },
+}
+
+impl fmt::Display for RustyBoyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RustyBoyError::Io(e) => write!(f, "I/O error - {}", e),
+            RustyBoyError::InvalidHeader(reason) => write!(f, "Invalid cartridge header - {}", reason),
+            RustyBoyError::UnsupportedCartridgeType(cartridge_type) => {
+                write!(f, "Unsupported cartridge type 0x{:02X}", cartridge_type)
+            },
+            RustyBoyError::InvalidImage(reason) => write!(f, "Invalid image - {}", reason),
+            RustyBoyError::InvalidArchive(reason) => write!(f, "Invalid archive - {}", reason),
+            RustyBoyError::InvalidPatch(reason) => write!(f, "Invalid patch - {}", reason),
+            RustyBoyError::InvalidBootRom(reason) => write!(f, "Invalid boot ROM - {}", reason),
+            RustyBoyError::CpuLockup { opcode, program_counter } => {
+                write!(f, "CPU locked up on opcode 0x{:02X} at PC - {:04X}", opcode, program_counter)
+            },
+        }
+    }
+}
+
+impl Error for RustyBoyError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            RustyBoyError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for RustyBoyError {
+    fn from(e: io::Error) -> RustyBoyError {
+        RustyBoyError::Io(e)
+    }
+}
@@ -0,0 +1,296 @@
+use std::fmt;
+
+use crate::mbc::*;
+use crate::utils::*;
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum CgbSupport {
+    NONE,
+    COMPATIBLE,
+    ONLY,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct CartridgeType {
+    /**
+    * Decoded form of the cartridge type byte at 0x0147 - which MBC the cartridge uses and what
+    * extra hardware sits alongside it. `mbc_type` is None for codes we don't recognise
+    **/
+
+    pub code: Byte,
+    pub mbc_type: Option<MbcType>,
+    pub has_ram: bool,
+    pub has_battery: bool,
+    pub has_rtc: bool,
+    pub has_rumble: bool,
+    pub has_sensor: bool,
+}
+
+impl CartridgeType {
+
+    pub fn from_code(code: Byte) -> CartridgeType {
+        // (MBC, RAM, Battery, RTC, Rumble, Sensor)
+        let (mbc_type, has_ram, has_battery, has_rtc, has_rumble, has_sensor) = match code {
+            0x00 => (Some(MbcType::NONE), false, false, false, false, false),
+            0x01 => (Some(MbcType::MBC1), false, false, false, false, false),
+            0x02 => (Some(MbcType::MBC1), true, false, false, false, false),
+            0x03 => (Some(MbcType::MBC1), true, true, false, false, false),
+
+            // MBC2 always has its 512 x 4 bits of RAM built into the MBC itself
+            0x05 => (Some(MbcType::MBC2), true, false, false, false, false),
+            0x06 => (Some(MbcType::MBC2), true, true, false, false, false),
+            0x08 => (Some(MbcType::NONE), true, false, false, false, false),
+            0x09 => (Some(MbcType::NONE), true, true, false, false, false),
+            0x0B => (Some(MbcType::MMM01), false, false, false, false, false),
+            0x0C => (Some(MbcType::MMM01), true, false, false, false, false),
+            0x0D => (Some(MbcType::MMM01), true, true, false, false, false),
+            0x0F => (Some(MbcType::MBC3), false, true, true, false, false),
+            0x10 => (Some(MbcType::MBC3), true, true, true, false, false),
+            0x11 => (Some(MbcType::MBC3), false, false, false, false, false),
+            0x12 => (Some(MbcType::MBC3), true, false, false, false, false),
+            0x13 => (Some(MbcType::MBC3), true, true, false, false, false),
+            0x19 => (Some(MbcType::MBC5), false, false, false, false, false),
+            0x1A => (Some(MbcType::MBC5), true, false, false, false, false),
+            0x1B => (Some(MbcType::MBC5), true, true, false, false, false),
+            0x1C => (Some(MbcType::MBC5), false, false, false, true, false),
+            0x1D => (Some(MbcType::MBC5), true, false, false, true, false),
+            0x1E => (Some(MbcType::MBC5), true, true, false, true, false),
+            0x20 => (Some(MbcType::MBC6), true, true, false, false, false),
+            0x22 => (Some(MbcType::MBC7), true, true, false, true, true),
+            0xFC => (Some(MbcType::POCKET_CAMERA), true, true, false, false, true),
+            0xFD => (Some(MbcType::TAMA5), true, true, true, false, false),
+            0xFE => (Some(MbcType::HUC3), true, true, true, false, false),
+            0xFF => (Some(MbcType::HUC1), true, true, false, false, false),
+            _ => (None, false, false, false, false, false),
+        };
+
+        CartridgeType {
+            code: code,
+            mbc_type: mbc_type,
+            has_ram: has_ram,
+            has_battery: has_battery,
+            has_rtc: has_rtc,
+            has_rumble: has_rumble,
+            has_sensor: has_sensor,
+        }
+    }
+}
+
+impl fmt::Display for CartridgeType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Formatted the same way as the Pan Docs table, i.e. MBC3+TIMER+RAM+BATTERY
+        let mut parts = match self.mbc_type {
+            Some(MbcType::NONE) => vec![String::from("ROM")],
+            Some(mbc_type) => vec![format!("{:?}", mbc_type)],
+            None => return write!(f, "UNKNOWN (0x{:02X})", self.code),
+        };
+
+        if self.has_rtc {
+            parts.push(String::from("TIMER"));
+        }
+        if self.has_sensor {
+            parts.push(String::from("SENSOR"));
+        }
+        if self.has_rumble {
+            parts.push(String::from("RUMBLE"));
+        }
+        if self.has_ram {
+            parts.push(String::from("RAM"));
+        }
+        if self.has_battery {
+            parts.push(String::from("BATTERY"));
+        }
+
+        write!(f, "{}", parts.join("+"))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CartridgeHeader {
+    /**
+    * Everything in the cartridge header at 0x0100 - 0x014F, decoded. The stored checksums are
+    * kept alongside the ones we compute from the ROM data so mismatches can be reported
+    **/
+
+    pub title: String,
+    pub manufacturer_code: Option<String>,
+    pub cgb_support: CgbSupport,
+    pub new_licensee_code: String,
+    pub sgb_supported: bool,
+    pub cartridge_type: CartridgeType,
+    pub rom_size_code: Byte,
+    pub rom_banks: usize,
+    pub ram_size_code: Byte,
+    pub ram_size: usize,
+    pub is_japanese: bool,
+    pub old_licensee_code: Byte,
+    pub version: Byte,
+    pub header_checksum: Byte,
+    pub computed_header_checksum: Byte,
+    pub global_checksum: Word,
+    pub computed_global_checksum: Word,
+    pub has_valid_logo: bool,
+}
+
+impl CartridgeHeader {
+
+    pub fn parse(data: &[u8]) -> CartridgeHeader {
+        // Callers must make sure the whole header is present
+        let cgb_support = match data[CGB_FLAG_ADDR] {
+            0xC0 => CgbSupport::ONLY,
+            0x80 => CgbSupport::COMPATIBLE,
+            _ => CgbSupport::NONE,
+        };
+
+        // Originally the title took up 16 bytes. CGB cartridges use the last byte as the CGB flag,
+        // and later ones also use the 4 bytes before it for the manufacturer code - which we can
+        // only spot by it looking like one, as nothing in the header says it is there
+        let old_licensee_code = data[OLD_LICENSEE_CODE_ADDR];
+        let manufacturer_bytes = &data[MANUFACTURER_CODE_ADDR..CGB_FLAG_ADDR];
+        let has_manufacturer_code = cgb_support != CgbSupport::NONE
+            && old_licensee_code == 0x33
+            && manufacturer_bytes.iter().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit());
+
+        let (title_end, manufacturer_code) = match (has_manufacturer_code, cgb_support) {
+            (true, _) => (MANUFACTURER_CODE_ADDR, Some(to_ascii(manufacturer_bytes))),
+            (false, CgbSupport::NONE) => (NEW_LICENSEE_CODE_ADDR, None),
+            (false, _) => (CGB_FLAG_ADDR, None),
+        };
+
+        let rom_size_code = data[ROM_SIZE_ADDR];
+        let rom_banks = match rom_size_code {
+            0x00..=0x08 => 2 << rom_size_code,
+            0x52 => 72,
+            0x53 => 80,
+            0x54 => 96,
+
+            // Fall back on however much data there actually is
+            _ => std::cmp::max(2, data.len() / ROM_BANK_SIZE),
+        };
+
+        let ram_size_code = data[RAM_SIZE_ADDR];
+        let ram_size = match ram_size_code {
+            0x01 => 0x800,  // Unofficial, only used by a handful of homebrew ROMs
+            0x02 => RAM_BANK_SIZE,
+            0x03 => RAM_BANK_SIZE * 4,
+            0x04 => RAM_BANK_SIZE * 16,
+            0x05 => RAM_BANK_SIZE * 8,
+            _ => 0,
+        };
+
+        let global_checksum = ((data[GLOBAL_CHECKSUM_ADDR] as Word) << 8) | data[GLOBAL_CHECKSUM_ADDR + 1] as Word;
+
+        CartridgeHeader {
+            title: to_ascii(&data[CARTRIDGE_TITLE_ADDR..title_end]),
+            manufacturer_code: manufacturer_code,
+            cgb_support: cgb_support,
+            new_licensee_code: to_ascii(&data[NEW_LICENSEE_CODE_ADDR..SGB_FLAG_ADDR]),
+            sgb_supported: data[SGB_FLAG_ADDR] == 0x03,
+            cartridge_type: CartridgeType::from_code(data[CARTRIDGE_TYPE_ADDR]),
+            rom_size_code: rom_size_code,
+            rom_banks: rom_banks,
+            ram_size_code: ram_size_code,
+            ram_size: ram_size,
+            is_japanese: data[DESTINATION_CODE_ADDR] == 0x00,
+            old_licensee_code: old_licensee_code,
+            version: data[ROM_VERSION_ADDR],
+            header_checksum: data[HEADER_CHECKSUM_ADDR],
+            computed_header_checksum: compute_header_checksum(data),
+            global_checksum: global_checksum,
+            computed_global_checksum: compute_global_checksum(data, 0),
+            has_valid_logo: data[CARTRIDGE_LOGO_ADDR..CARTRIDGE_TITLE_ADDR] == NINTENDO_LOGO,
+        }
+    }
+
+    pub fn is_cgb(&self) -> bool {
+        self.cgb_support != CgbSupport::NONE
+    }
+
+    pub fn get_licensee_code(&self) -> String {
+        // 0x33 in the old licensee code means look at the new one instead
+        match self.old_licensee_code {
+            0x33 => self.new_licensee_code.clone(),
+            code => format!("{:02X}", code),
+        }
+    }
+
+    pub fn get_rom_size(&self) -> usize {
+        self.rom_banks * ROM_BANK_SIZE
+    }
+
+    pub fn is_header_checksum_valid(&self) -> bool {
+        self.header_checksum == self.computed_header_checksum
+    }
+
+    pub fn is_global_checksum_valid(&self) -> bool {
+        self.global_checksum == self.computed_global_checksum
+    }
+
+    pub fn get_problems(&self, rom_length: usize) -> Vec<String> {
+        // Anything that looks wrong with the header. None of these stop us running the ROM, but
+        // real hardware would refuse to boot with a bad logo or header checksum
+        let mut problems = Vec::new();
+
+        if !self.has_valid_logo {
+            problems.push(String::from("Nintendo logo doesn't match"));
+        }
+
+        if !self.is_header_checksum_valid() {
+            problems.push(format!(
+                "Header checksum is 0x{:02X}, expected 0x{:02X}",
+                self.header_checksum, self.computed_header_checksum
+            ));
+        }
+
+        if !self.is_global_checksum_valid() {
+            problems.push(format!(
+                "Global checksum is 0x{:04X}, expected 0x{:04X}",
+                self.global_checksum, self.computed_global_checksum
+            ));
+        }
+
+        if self.cartridge_type.mbc_type.is_none() {
+            problems.push(format!("Unknown cartridge type 0x{:02X}", self.cartridge_type.code));
+        }
+
+        if rom_length != self.get_rom_size() {
+            problems.push(format!(
+                "Header says the ROM is {} bytes, but it is {} bytes",
+                self.get_rom_size(), rom_length
+            ));
+        }
+
+        if self.ram_size > 0 && !self.cartridge_type.has_ram {
+            problems.push(format!("Header says there is {} bytes of RAM, but the cartridge type has none", self.ram_size));
+        }
+
+        problems
+    }
+}
+
+pub fn compute_header_checksum(data: &[u8]) -> Byte {
+    // x = 0; for each byte in 0x0134 - 0x014C: x = x - byte - 1
+    data[CARTRIDGE_TITLE_ADDR..HEADER_CHECKSUM_ADDR]
+        .iter()
+        .fold(0 as Byte, |checksum, byte| checksum.wrapping_sub(*byte).wrapping_sub(1))
+}
+
+pub fn compute_global_checksum(data: &[u8], header_offset: usize) -> Word {
+    // Sum of every byte in the ROM, except the two holding the global checksum itself. The
+    // header is usually at the start of the ROM, but not always (see Rom::find_header)
+    let checksum_addr = header_offset + GLOBAL_CHECKSUM_ADDR;
+    data.iter()
+        .enumerate()
+        .filter(|(i, _)| *i != checksum_addr && *i != checksum_addr + 1)
+        .fold(0 as Word, |checksum, (_, byte)| checksum.wrapping_add(*byte as Word))
+}
+
+fn to_ascii(bytes: &[u8]) -> String {
+    // Header strings are padded with 0x00 (or occasionally spaces) and aren't always clean ASCII
+    bytes.iter()
+        .take_while(|c| **c != 0)
+        .map(|c| if c.is_ascii_graphic() || *c == b' ' { *c as char } else { '?' })
+        .collect::<String>()
+        .trim_end()
+        .to_string()
+}
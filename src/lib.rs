@@ -0,0 +1,43 @@
+//! Rusty Boy - a Game Boy and Game Boy Color emulator.
+//!
+//! The emulator core has no dependency on SDL, so it can be embedded in other tools (test runners,
+//! bots, other frontends). Construct a [`RustyBoy`] from ROM bytes, feed it input and call
+//! [`RustyBoy::run_frame`] once per frame to get the framebuffer back.
+
+#[macro_use]
+extern crate lazy_static;
+
+pub mod apu;
+pub mod archive;
+pub mod camera;
+pub mod compat_palettes;
+pub mod cpu;
+pub mod eeprom;
+pub mod error;
+pub mod header;
+pub mod interrupts;
+pub mod joypad;
+pub mod mbc;
+pub mod mmu;
+pub mod model;
+pub mod ops;
+pub mod patch;
+pub mod ppu;
+pub mod resampler;
+pub mod rewind;
+pub mod rom;
+pub mod rtc;
+pub mod rusty_boy;
+pub mod save_formats;
+pub mod save_manager;
+pub mod state;
+pub mod timer;
+pub mod utils;
+
+pub use crate::camera::CameraSensor;
+pub use crate::error::RustyBoyError;
+pub use crate::header::{CartridgeHeader, CartridgeType, CgbSupport};
+pub use crate::model::Model;
+pub use crate::rusty_boy::{CpuRegisters, RustyBoy};
+pub use crate::save_formats::SaveFormat;
+pub use crate::save_manager::SaveManager;
@@ -1,70 +1,72 @@
-#[macro_use]
-extern crate lazy_static;
 extern crate sdl2;
 
-pub mod cpu;
-pub mod interrupts;
-pub mod joypad;
-pub mod mbc;
-pub mod mmu;
-pub mod ops;
-pub mod ppu;
-pub mod rom;
-pub mod rusty_boy;
-pub mod timer;
-pub mod utils;
-
 use std::env;
-use std::fs::File;
-use std::io::prelude::*;
+use std::fs;
 use std::collections::HashMap;
+use std::path::Path;
 use std::time::Duration;
 
+use sdl2::audio::{AudioQueue, AudioSpecDesired};
+use sdl2::controller::{Axis, GameController};
 use sdl2::event::Event;
 use sdl2::EventPump;
-use sdl2::keyboard::Keycode;
+use sdl2::haptic::Haptic;
+use sdl2::keyboard::{Keycode, Mod};
 use sdl2::pixels::Color;
 use sdl2::pixels::PixelFormatEnum;
 use sdl2::render::TextureCreator;
 
-use crate::utils::*;
-use crate::rusty_boy::RustyBoy;
-
-// TODO THis isn't the neatest - can refactor i'm sure
-fn save(rom_file: &str, rusty_boy: &RustyBoy) -> std::io::Result<()> {
-    let mut parts = rom_file.rsplit(".");
-    let extension = parts.next();
-    let filename_part = parts.next();
-    if let Some(filename) = filename_part {
-        let mut full_filename = String::from(filename);
-        full_filename.push_str(".sav");
+use rusty_boy::utils::*;
+use rusty_boy::{CameraSensor, Model, RustyBoy, RustyBoyError, SaveFormat, SaveManager};
 
-        let ram = rusty_boy.get_external_ram();
+fn get_state_file(rom_file: &str, slot: usize) -> String {
+    // Save states live next to the ROM, i.e. game.gb -> game.ss1
+    Path::new(rom_file)
+        .with_extension(format!("ss{}", slot))
+        .to_string_lossy()
+        .into_owned()
+}
 
-        // concat for Windows - need cross platform workaroudn
-        let mut file = File::create(".".to_owned() + &full_filename)?;
-        file.write_all(ram)?;
-    }
+fn save_state(rom_file: &str, slot: usize, rusty_boy: &RustyBoy) -> std::io::Result<()> {
+    fs::write(get_state_file(rom_file, slot), rusty_boy.save_state())
+}
 
-    Ok(())
+fn load_state(rom_file: &str, slot: usize, rusty_boy: &mut RustyBoy) -> std::io::Result<()> {
+    let data = fs::read(get_state_file(rom_file, slot))?;
+    rusty_boy.load_state(&data)
 }
 
-fn load(rom_file: &str, rusty_boy: &mut RustyBoy) -> std::io::Result<()> {
-    let mut parts = rom_file.rsplit(".");
-    let extension = parts.next();
-    let filename_part = parts.next();
-    if let Some(filename) = filename_part {
-        let mut full_filename = String::from(filename);
-        full_filename.push_str(".sav");
+fn get_option<'a>(args: &'a [String], name: &str) -> Option<&'a String> {
+    // Options are given as --name value
+    args.iter()
+        .position(|arg| arg == name)
+        .and_then(|i| args.get(i + 1))
+}
 
-        let mut file = File::open(".".to_owned() + &full_filename)?;
-        let mut buffer = Vec::<u8>::new();
-        file.read_to_end(&mut buffer)?;
+fn get_palette_buttons(name: &str) -> Option<(usize, Option<usize>)> {
+    // Compatibility palettes are named after the buttons that pick them, i.e. left+b
+    let mut parts = name.split('+');
+    let direction = match parts.next()?.to_ascii_lowercase().as_str() {
+        "up" => UP_BUTTON,
+        "down" => DOWN_BUTTON,
+        "left" => LEFT_BUTTON,
+        "right" => RIGHT_BUTTON,
+        _ => return None,
+    };
+    let button = match parts.next().map(|part| part.to_ascii_lowercase()) {
+        Some(part) if part == "a" => Some(A_BUTTON),
+        Some(part) if part == "b" => Some(B_BUTTON),
+        Some(_) => return None,
+        None => None,
+    };
+    Some((direction, button))
+}
 
-        rusty_boy.load_external_ram(buffer)
+fn print_warnings(warnings: &[String]) {
+    // The emulator carries on past these, they are only worth telling the player about
+    for warning in warnings {
+        println!("Warning: {}", warning);
     }
-
-    Ok(())
 }
 
 fn main() {
@@ -78,6 +80,18 @@ fn main() {
     key_map.insert(Keycode::A, A_BUTTON);
     key_map.insert(Keycode::S, B_BUTTON);
 
+    // F1 - F8 load from the matching save state slot, or save to it with Shift held
+    let state_slots = HashMap::from([
+        (Keycode::F1, 1),
+        (Keycode::F2, 2),
+        (Keycode::F3, 3),
+        (Keycode::F4, 4),
+        (Keycode::F5, 5),
+        (Keycode::F6, 6),
+        (Keycode::F7, 7),
+        (Keycode::F8, 8),
+    ]);
+
     // Initialize SDL
     let sdl_context = sdl2::init().unwrap();
     let video_subsystem = sdl_context.video().unwrap();
@@ -86,7 +100,55 @@ fn main() {
         .position_centered()
         .build().unwrap();
 
-    let mut canvas = window.into_canvas().present_vsync().build().unwrap();
+    // Audio output - if we can get an audio device, the emulator is paced by how full the audio
+    // queue is. Otherwise fall back to pacing by the display's vsync
+    let audio_subsystem = sdl_context.audio().unwrap();
+    let desired_spec = AudioSpecDesired {
+        freq: Some(AUDIO_SAMPLE_RATE as i32),
+        channels: Some(2),
+        samples: Some(1024),
+    };
+
+    let audio_queue: Option<AudioQueue<f32>> = match audio_subsystem.open_queue(None, &desired_spec) {
+        Ok(queue) => {
+            queue.resume();
+            Some(queue)
+        },
+        Err(e) => {
+            println!("Unable to open audio device, audio is disabled - {}", e);
+            None
+        }
+    };
+
+    let mut canvas = match audio_queue {
+        Some(_) => window.into_canvas().build().unwrap(),
+        None => window.into_canvas().present_vsync().build().unwrap(),
+    };
+
+    // Rumble cartridges drive the haptics of the first controller that has any. Not every
+    // platform supports joysticks or haptics, in which case there is just no rumble
+    let joystick_subsystem = sdl_context.joystick();
+    let haptic_subsystem = sdl_context.haptic();
+    let mut haptic: Option<Haptic> = match (&joystick_subsystem, &haptic_subsystem) {
+        (Ok(joystick_subsystem), Ok(haptic_subsystem)) => (0..joystick_subsystem.num_joysticks().unwrap_or(0))
+            .find_map(|joystick_id| haptic_subsystem.open_from_joystick_id(joystick_id).ok()),
+        (Err(e), _) | (_, Err(e)) => {
+            println!("Unable to open haptics, rumble is disabled - {}", e);
+            None
+        }
+    };
+
+    // Tilt cartridges (MBC7) are controlled by the left stick of the first game controller, or
+    // by moving the mouse around the window when there isn't one (or SDL can't open any)
+    let controller: Option<GameController> = match sdl_context.game_controller() {
+        Ok(controller_subsystem) => (0..controller_subsystem.num_joysticks().unwrap_or(0))
+            .filter(|joystick_id| controller_subsystem.is_game_controller(*joystick_id))
+            .find_map(|joystick_id| controller_subsystem.open(joystick_id).ok()),
+        Err(e) => {
+            println!("Unable to open game controllers, tilt uses the mouse - {}", e);
+            None
+        }
+    };
 
     let mut event_pump = sdl_context.event_pump().unwrap();
     canvas.set_scale(DISPLAY_FACTOR as f32, DISPLAY_FACTOR as f32).unwrap();
@@ -109,13 +171,137 @@ fn main() {
     // Setup emulator
     let args: Vec<String> = env::args().collect();
     let rom_file = &args[1];
-    let mut rusty_boy = RustyBoy::new(rom_file);
+    // --entry picks which ROM to use from an archive holding several, --patch which patch to apply
+    let entry = get_option(&args, "--entry").map(|entry| entry.as_str());
+    let patch_file = get_option(&args, "--patch").map(|patch_file| patch_file.as_str());
+    let mut rusty_boy = match RustyBoy::open(rom_file, entry, patch_file) {
+        Ok(rusty_boy) => rusty_boy,
+        Err(e) => {
+            eprintln!("Unable to load {} - {}", rom_file, e);
+            std::process::exit(1);
+        }
+    };
+
+    println!("\n---------------------------------\n");
+    println!("{}", rusty_boy.debug_rom_header());
+    println!("\n---------------------------------\n");
+    print_warnings(&rusty_boy.get_rom_problems());
+
+    // Options come after the ROM. The model goes first, as the boot ROM has to match it
+    if let Some(model_name) = get_option(&args, "--model") {
+        let model = Model::from_name(model_name)
+            .ok_or_else(|| format!("Unknown model {}, expected DMG0, DMG, MGB, SGB, SGB2, CGB or AGB", model_name))
+            .and_then(|model| rusty_boy.set_model(model).map_err(|e| e.to_string()));
+        if let Err(e) = model {
+            eprintln!("Unable to use model {} - {}", model_name, e);
+        }
+    }
+
+    if let Some(boot_rom_file) = get_option(&args, "--boot-rom") {
+        let boot_rom = fs::read(boot_rom_file).map_err(RustyBoyError::from)
+            .and_then(|boot_rom| rusty_boy.set_boot_rom(boot_rom));
+        if let Err(e) = boot_rom {
+            eprintln!("Unable to load boot ROM {} - {}", boot_rom_file, e);
+        }
+    }
+
+    // --palette recolours a DMG game on a CGB, named after the buttons held at boot to pick it
+    if let Some(palette_name) = get_option(&args, "--palette") {
+        let is_set = get_palette_buttons(palette_name)
+            .is_some_and(|(direction, button)| rusty_boy.set_compat_palettes(direction, button));
+        if !is_set {
+            eprintln!("Unable to use palette {} - expected up, down, left or right with an optional +a or +b, on a CGB running a DMG game", palette_name);
+        }
+    }
+
+    let save_dir = get_option(&args, "--save-dir").map(|save_dir| save_dir.as_str());
+    let save_backups = match get_option(&args, "--save-backups") {
+        Some(save_backups) => save_backups.parse().unwrap_or(SAVE_BACKUPS),
+        None => SAVE_BACKUPS,
+    };
 
     // Load save file into RAM
-    load(rom_file, &mut rusty_boy);
+    let mut save_manager = SaveManager::new(&rusty_boy, rom_file, save_dir, save_backups);
+    match save_manager.load(&mut rusty_boy) {
+        Ok(warnings) => print_warnings(&warnings),
+        Err(e) => eprintln!("Unable to load {} - {}", save_manager.get_save_file().display(), e),
+    };
+
+    // --import-save replaces the save with one from another emulator, which gets written back
+    // as our own on the next save. --export-save writes the save out in --save-format
+    if let Some(import_file) = get_option(&args, "--import-save") {
+        match fs::read(import_file) {
+            Ok(data) => print_warnings(&rusty_boy.load_external_ram(data)),
+            Err(e) => eprintln!("Unable to import {} - {}", import_file, e),
+        };
+    }
+
+    if let Some(export_file) = get_option(&args, "--export-save") {
+        let format = get_option(&args, "--save-format")
+            .and_then(|format| SaveFormat::from_name(format))
+            .unwrap_or(SaveFormat::NATIVE);
+        let (data, warnings) = rusty_boy.export_save(format);
+        print_warnings(&warnings);
+        match fs::write(export_file, data) {
+            Ok(_) => println!("Exported save to {} as {:?}", export_file, format),
+            Err(e) => eprintln!("Unable to export {} - {}", export_file, e),
+        };
+    }
+
+    if let Some(camera_path) = get_option(&args, "--camera") {
+        match CameraSensor::from_path(camera_path) {
+            Ok(sensor) => rusty_boy.set_camera_sensor(sensor),
+            Err(e) => eprintln!("Unable to load camera images from {} - {}", camera_path, e),
+        };
+    }
+
+    // The audio device may not support the requested rate, so produce samples at whatever we got.
+    // We aim to keep a few frames worth of audio queued up - enough to not run dry, but small enough
+    // to keep latency low
+    let mut max_queued_bytes = 0;
+    if let Some(queue) = &audio_queue {
+        let spec = queue.spec();
+        rusty_boy.set_audio_sample_rate(spec.freq as usize);
+
+        let bytes_per_frame = (spec.freq as f32 / 59.7275) * (spec.channels as f32) * (std::mem::size_of::<f32>() as f32);
+        max_queued_bytes = (bytes_per_frame * AUDIO_QUEUED_FRAMES as f32) as u32;
+    }
+
+    // Holding Backspace plays the game backwards
+    let mut rewinding = false;
 
     'running: loop {
-        rusty_boy.run();
+        if rewinding {
+            if let Err(e) = rusty_boy.rewind() {
+                eprintln!("Unable to rewind, history has been cleared - {}", e);
+            }
+        } else if let Err(e) = rusty_boy.run() {
+            // Stop emulating, but keep the window open on the last frame so the error can be seen
+            eprintln!("Emulation stopped - {}", e);
+            if !rusty_boy.is_paused() {
+                rusty_boy.toggle_pause();
+            }
+        }
+
+        if let Err(e) = save_manager.update(&mut rusty_boy) {
+            eprintln!("Unable to save {} - {}", save_manager.get_save_file().display(), e);
+        }
+
+        // Keep topping the rumble up while the motor is on, so it stops by itself shortly after
+        // the game turns it off (or the emulator is paused)
+        let rumble_strength = rusty_boy.take_rumble_strength();
+        if let Some(haptic) = &mut haptic {
+            match rumble_strength > 0.0 {
+                true => haptic.rumble_play(rumble_strength, RUMBLE_DURATION_MS),
+                false => haptic.rumble_stop(),
+            }
+        }
+
+        let samples = rusty_boy.take_audio_samples();
+        if let Some(queue) = &audio_queue {
+            queue.queue(&samples);
+        }
+
         texture.update(None, rusty_boy.get_screen(), 160 * 3).unwrap();
         // vram_texture.update(None, &rusty_boy.get_vram_tiles(), 128 * 3).unwrap();
 
@@ -129,7 +315,9 @@ fn main() {
             match event {
                 Event::Quit {..} |
                 Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
-                    save(rom_file, &rusty_boy);
+                    if let Err(e) = save_manager.save(&rusty_boy) {
+                        eprintln!("Unable to save {} - {}", save_manager.get_save_file().display(), e);
+                    }
                     break 'running;
                 },
                 Event::KeyDown { keycode: Some(Keycode::P), .. } => {
@@ -138,6 +326,39 @@ fn main() {
                 Event::KeyDown { keycode: Some(Keycode::D), .. } => {
                     rusty_boy.debug();
                 },
+                Event::KeyDown { keycode: Some(Keycode::Backspace), .. } => {
+                    rewinding = true;
+                },
+                Event::KeyUp { keycode: Some(Keycode::Backspace), .. } => {
+                    rewinding = false;
+                },
+                Event::MouseMotion { x, y, .. } if controller.is_none() => {
+                    let tilt_x = (x as f32 / (SCREEN_WIDTH * DISPLAY_FACTOR) as f32) * 2.0 - 1.0;
+                    let tilt_y = (y as f32 / (SCREEN_HEIGHT * DISPLAY_FACTOR) as f32) * 2.0 - 1.0;
+                    rusty_boy.set_tilt(tilt_x, tilt_y);
+                },
+                Event::ControllerAxisMotion { axis: Axis::LeftX, .. } |
+                Event::ControllerAxisMotion { axis: Axis::LeftY, .. } => {
+                    if let Some(controller) = &controller {
+                        let tilt_x = controller.axis(Axis::LeftX) as f32 / i16::MAX as f32;
+                        let tilt_y = controller.axis(Axis::LeftY) as f32 / i16::MAX as f32;
+                        rusty_boy.set_tilt(tilt_x, tilt_y);
+                    }
+                },
+                Event::KeyDown { keycode: Some(keycode), keymod, .. } if state_slots.contains_key(&keycode) => {
+                    let slot = state_slots[&keycode];
+                    if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+                        match save_state(rom_file, slot, &rusty_boy) {
+                            Ok(_) => println!("Saved state to slot {}", slot),
+                            Err(e) => println!("Unable to save state to slot {} - {}", slot, e),
+                        };
+                    } else {
+                        match load_state(rom_file, slot, &mut rusty_boy) {
+                            Ok(_) => println!("Loaded state from slot {}", slot),
+                            Err(e) => println!("Unable to load state from slot {} - {}", slot, e),
+                        };
+                    }
+                },
                 Event::KeyDown { keycode, .. } => {
                     if let Some(key) = key_map.get(&keycode.unwrap_or(Keycode::Ampersand)) {
                         rusty_boy.set_button_state(*key);
@@ -152,8 +373,20 @@ fn main() {
             }
         }
 
-        // Run at Gameboy desired Frame rate
-        // Framerate should be ~60 FPS but this seems to be closer to reality
-        // ::std::thread::sleep(Duration::new(0, (1_000_000_000.0 / 120f32).floor() as u32));
+        // Run at Gameboy desired Frame rate - the audio device plays back at exactly the rate we
+        // produce samples at, so wait for it to drain before running the next frame
+        if rewinding {
+            // No audio is produced while rewinding, so pace by time instead
+            ::std::thread::sleep(Duration::from_secs_f32(1.0 / 59.7275));
+        } else if let Some(queue) = &audio_queue {
+            // Nor while paused (including after an error) or while the CPU is stopped, when the
+            // queue would never fill and we'd spin
+            if rusty_boy.is_paused() || samples.is_empty() {
+                ::std::thread::sleep(Duration::from_secs_f32(1.0 / 59.7275));
+            }
+            while queue.size() > max_queued_bytes {
+                ::std::thread::sleep(Duration::from_millis(1));
+            }
+        }
     }
 }
@@ -1,26 +1,66 @@
 use std::cmp;
 use std::fmt;
+use std::io;
+use std::time::{SystemTime, UNIX_EPOCH};
 
+use crate::camera::*;
+use crate::eeprom::*;
+use crate::error::*;
 use crate::rom::*;
+use crate::rtc::*;
+use crate::state::*;
 use crate::utils::*;
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
 pub enum MbcType {
+    NONE,
     MBC1,
     MBC2,
     MBC3,
     MBC5,
+    MBC6,
+    MBC7,
+    MMM01,
+    POCKET_CAMERA,
+    TAMA5,
+    HUC1,
+    HUC3,
 }
 
 pub trait Mbc {
     fn get_mbc_type(&self) -> MbcType;
+    fn read_fixed_rom(&self, addr: Word) -> Byte;
     fn read_rom(&self, addr: Word) -> Byte;
     fn read_ram(&self, addr: Word) -> Byte;
     fn write_ram(&mut self, addr: Word, data: Byte);
     fn handle_banking(&mut self, addr: Word, data: Byte);
     fn get_external_ram(&self) -> &[Byte];
-    fn load_external_ram(&mut self, buffer: Vec<Byte>);
+    fn load_external_ram(&mut self, buffer: Vec<Byte>) -> Vec<String>;
+    fn save_state(&self, writer: &mut StateWriter);
+    fn load_state(&mut self, reader: &mut StateReader) -> io::Result<()>;
     fn debug(&self) -> String;
+
+    // Only needed by cartridges with hardware that runs on its own, like a clock
+    fn update(&mut self, _cycles: u8) {}
+
+    // What goes in the save file - usually just the external RAM, but some cartridges add extra
+    // data after it (e.g. the RTC). load_external_ram must accept whatever this returns, and
+    // returns warnings about anything it was given that it couldn't use
+    fn get_save_data(&self) -> Vec<Byte> {
+        self.get_external_ram().to_vec()
+    }
+
+    // How much of the time since the last call the cartridge's rumble motor was switched on,
+    // from 0.0 to 1.0
+    fn take_rumble_strength(&mut self) -> f32 {
+        0.0
+    }
+
+    // Only used by cartridges with an accelerometer, see RustyBoy::set_tilt
+    fn set_tilt(&mut self, _x: f32, _y: f32) {}
+
+    // Only used by the Pocket Camera, see RustyBoy::set_camera_sensor
+    fn set_camera_sensor(&mut self, _sensor: CameraSensor) {}
 }
 
 impl fmt::Debug for dyn Mbc {
@@ -31,30 +71,91 @@ impl fmt::Debug for dyn Mbc {
     }
 }
 
-pub fn get_mbc(rom: &Rom) -> Option<Box<dyn Mbc>> {
-    let rom_mode = rom.get_cartridge_type();
-    match rom_mode {
-        0x01 | 0x02 | 0x03 => Some(Box::new(Mbc1::new(rom))),
-        0x05 | 0x06 => Some(Box::new(Mbc2::new(rom))),
-        0x0F | 0x10 | 0x11 | 0x12 | 0x13 => Some(Box::new(Mbc3::new(rom))), 
-        0x19 | 0x1A | 0x1B | 0x1C | 0x1D | 0x1E => Some(Box::new(Mbc5::new(rom))),
-        _ => None
+pub fn get_mbc(rom: &Rom) -> Result<Box<dyn Mbc>, RustyBoyError> {
+    let cartridge_type = rom.get_header().cartridge_type;
+    match cartridge_type.mbc_type {
+        Some(MbcType::NONE) => Ok(Box::new(NoMbc::new(rom))),
+        Some(MbcType::MBC1) => Ok(Box::new(Mbc1::new(rom))),
+        Some(MbcType::MBC2) => Ok(Box::new(Mbc2::new(rom))),
+        Some(MbcType::MBC3) => Ok(Box::new(Mbc3::new(rom, cartridge_type.has_rtc))),
+        Some(MbcType::MBC5) => Ok(Box::new(Mbc5::new(rom, cartridge_type.has_rumble))),
+        Some(MbcType::MMM01) => Ok(Box::new(Mmm01::new(rom))),
+        Some(MbcType::MBC7) => Ok(Box::new(Mbc7::new(rom))),
+        Some(MbcType::POCKET_CAMERA) => Ok(Box::new(PocketCamera::new(rom))),
+        Some(MbcType::HUC1) => Ok(Box::new(HuC1::new(rom))),
+        Some(MbcType::HUC3) => Ok(Box::new(HuC3::new(rom))),
+        _ => Err(RustyBoyError::UnsupportedCartridgeType(cartridge_type.code))
+    }
+}
+
+fn load_rom_banks(rom: &Rom) -> (Vec<Byte>, usize) {
+    // Copy the ROM into memory padded out to a whole number of banks, returning the memory and
+    // the number of banks. Bank numbers past the end of the ROM wrap around like real hardware,
+    // where the upper bank bits simply aren't connected, so this is the real bank count rather
+    // than what the header claims
+    let number_of_rom_banks = cmp::max(2, (rom.length() + ROM_BANK_SIZE - 1) / ROM_BANK_SIZE);
+
+    let mut memory = Vec::with_capacity(number_of_rom_banks * ROM_BANK_SIZE);
+    for i in 0..(number_of_rom_banks * ROM_BANK_SIZE) {
+        memory.push(rom.get_byte(i));
+    }
+
+    (memory, number_of_rom_banks)
+}
+
+fn create_external_ram(rom: &Rom) -> Vec<Byte> {
+    // Sized from the RAM size in the header (0x0149) - cartridges without RAM get none at all
+    let header = rom.get_header();
+    match header.cartridge_type.has_ram {
+        true => vec![0; header.ram_size],
+        false => Vec::new(),
+    }
+}
+
+fn resolve_ram_addr(external_ram: &[Byte], ram_bank: usize, addr: Word) -> Option<usize> {
+    // Bank numbers and addresses beyond the size of RAM wrap around, as the unused address
+    // lines aren't connected. A 2 KiB RAM for example is mirrored 4 times across 0xA000 - 0xBFFF
+    match external_ram.len() {
+        0 => None,
+        ram_len => Some(((ram_bank * RAM_BANK_SIZE) + (addr as usize)) % ram_len),
+    }
+}
+
+fn load_into_external_ram(external_ram: &mut [Byte], buffer: Vec<Byte>) {
+    let ram_len = external_ram.len();
+    for i in 0..cmp::min(ram_len, buffer.len()) {
+        external_ram[i] = buffer[i];
     }
 }
 
+pub struct NoMbc {
+    memory: Vec<Byte>,
+
+    // Without an MBC there is nothing to bank RAM, so at most 8 KiB can be used
+    external_ram: Vec<Byte>,
+}
+
 pub struct Mbc1 {
     memory: Vec<Byte>,
+
+    // BANK1 - the lower 5 bits of the ROM bank number
     rom_bank: usize,
+
+    // BANK2 - 2 bits used as either the RAM bank or the upper bits of the ROM bank number
     ram_bank: usize,
-    external_ram: [Byte; MAXIMUM_RAM_BANKS * RAM_BANK_SIZE],
+    external_ram: Vec<Byte>,
     enable_ram: bool,
-    number_of_rom_banks: u8,
+    number_of_rom_banks: usize,
     banking_mode: BankingMode,
+
+    // MBC1M multicarts only connect 4 bits of BANK1, so BANK2 starts at bit 4 instead of bit 5
+    is_multicart: bool,
 }
 
 pub struct Mbc2 {
     memory: Vec<Byte>,
     rom_bank: usize,
+    number_of_rom_banks: usize,
 
     // MBC2 doesn't really support external ram, rather just 512 bytes of RAM in the MBC
     external_ram: [Byte; 0x200], 
@@ -65,43 +166,168 @@ pub struct Mbc3 {
     memory: Vec<Byte>,
     rom_bank: usize,
     ram_bank_or_rtc: usize,
-    external_ram: [Byte; MAXIMUM_RAM_BANKS * RAM_BANK_SIZE],
+    external_ram: Vec<Byte>,
     enable_ram_and_rtc: bool,
-    number_of_rom_banks: u8,
+    number_of_rom_banks: usize,
 
-    // RTC (Real Time Clock) Registers
-    rtc_seconds: Byte,
-    rtc_minutes: Byte,
-    rtc_hours: Byte,
-    rtc_dl: Byte,
-    rtc_dh: Byte,
+    // RTC (Real Time Clock), only present on some MBC3 cartridges
+    has_rtc: bool,
+    rtc: Rtc,
 }
 
 pub struct Mbc5 {
     memory: Vec<Byte>,
     rom_bank: usize,
     ram_bank: usize,
-    external_ram: [Byte; 16 * RAM_BANK_SIZE],
+    external_ram: Vec<Byte>,
     enable_ram: bool,
-    number_of_rom_banks: u8,
+    number_of_rom_banks: usize,
+
+    // Rumble cartridges wire the motor to bit 3 of the RAM bank register. Games set the strength
+    // by switching it on and off many times a frame, so count the cycles it spends on
+    has_rumble: bool,
+    rumble: bool,
+    rumble_cycles: usize,
+    total_cycles: usize,
+}
+
+impl NoMbc {
+
+    pub fn new(rom: &Rom) -> NoMbc {
+        let (memory, _) = load_rom_banks(rom);
+        let mut external_ram = create_external_ram(rom);
+        external_ram.truncate(RAM_BANK_SIZE);
+
+        NoMbc {
+            memory: memory,
+            external_ram: external_ram,
+        }
+    }
+}
+
+impl Mbc for NoMbc {
+    fn get_mbc_type(&self) -> MbcType {
+        MbcType::NONE
+    }
+
+    fn read_fixed_rom(&self, addr: Word) -> Byte {
+        self.memory[addr as usize]
+    }
+
+    fn read_rom(&self, addr: Word) -> Byte {
+        // Always bank 1, the 32 KiB ROM is mapped straight in
+        self.memory[(addr as usize) + ROM_BANK_SIZE]
+    }
+
+    fn read_ram(&self, addr: Word) -> Byte {
+        // Nothing drives the data bus when there is no RAM, so it reads as 0xFF
+        match resolve_ram_addr(&self.external_ram, 0, addr) {
+            Some(resolved_addr) => self.external_ram[resolved_addr],
+            None => 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, addr: Word, data: Byte) {
+        // There is no enable register, RAM is always writable
+        if let Some(resolved_addr) = resolve_ram_addr(&self.external_ram, 0, addr) {
+            self.external_ram[resolved_addr] = data;
+        }
+    }
+
+    fn handle_banking(&mut self, _addr: Word, _data: Byte) {
+        // Writes to ROM go nowhere
+    }
+
+    fn get_external_ram(&self) -> &[Byte] {
+        &self.external_ram
+    }
+
+    fn load_external_ram(&mut self, buffer: Vec<Byte>) -> Vec<String> {
+        load_into_external_ram(&mut self.external_ram, buffer);
+        Vec::new()
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.external_ram);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> io::Result<()> {
+        reader.read_bytes_into(&mut self.external_ram)?;
+        Ok(())
+    }
+
+    fn debug(&self) -> String {
+        format!("MBC Type: {:?}\nRAM: {} bytes", self.get_mbc_type(), self.external_ram.len())
+    }
 }
 
 impl Mbc1 {
 
     pub fn new(rom: &Rom) -> Mbc1 {
-        let mut memory = Vec::new();
-        for i in 0..rom.length() {
-            memory.push(rom.get_byte(i));
-        }
+        let (memory, number_of_rom_banks) = load_rom_banks(rom);
+        let is_multicart = Mbc1::is_multicart(&memory);
 
         Mbc1 {
             memory: memory,
             rom_bank: 1,
             ram_bank: 0,
-            external_ram: [0; MAXIMUM_RAM_BANKS * RAM_BANK_SIZE],
+            external_ram: create_external_ram(rom),
             enable_ram: false,
-            number_of_rom_banks: rom.get_number_of_banks() as u8,
+            number_of_rom_banks: number_of_rom_banks,
             banking_mode: BankingMode::ROM,
+            is_multicart: is_multicart,
+        }
+    }
+
+    fn is_multicart(memory: &[Byte]) -> bool {
+        // Nothing in the header says a cartridge is an MBC1M multicart. They are all 1 MiB and
+        // made up of 256 KiB games, each with its own header, so look for the Nintendo logo at
+        // the start of each game (banks 0x00, 0x10, 0x20 and 0x30)
+        if memory.len() != 64 * ROM_BANK_SIZE {
+            return false;
+        }
+
+        let logo_count = (0..4)
+            .map(|game| (game * 0x10 * ROM_BANK_SIZE) + CARTRIDGE_LOGO_ADDR)
+            .filter(|logo_addr| memory[*logo_addr..(*logo_addr + NINTENDO_LOGO.len())] == NINTENDO_LOGO)
+            .count();
+
+        logo_count > 1
+    }
+
+    fn get_bank2_shift(&self) -> usize {
+        match self.is_multicart {
+            true => 4,
+            false => 5,
+        }
+    }
+
+    fn get_lower_rom_bank(&self) -> usize {
+        // 0x0000 - 0x3FFF is normally bank 0, but in mode 1 BANK2 applies here too, so large ROMs
+        // can map banks 0x20, 0x40 and 0x60 in this area
+        match self.banking_mode {
+            BankingMode::ROM => 0,
+            BankingMode::RAM => self.ram_bank << self.get_bank2_shift(),
+        }
+    }
+
+    fn get_upper_rom_bank(&self) -> usize {
+        // The zero check in BANK1 only looks at its own 5 bits, so banks 0x20, 0x40 and 0x60 can never
+        // be mapped here and get 0x21, 0x41 and 0x61 instead. Multicarts lose the top bit of BANK1
+        // entirely, but it still takes part in the zero check
+        let rom_bank = match self.is_multicart {
+            true => self.rom_bank & 0x0F,
+            false => self.rom_bank,
+        };
+
+        (self.ram_bank << self.get_bank2_shift()) | rom_bank
+    }
+
+    fn get_ram_bank(&self) -> usize {
+        // BANK2 only selects the RAM bank in mode 1, otherwise bank 0 is always used
+        match self.banking_mode {
+            BankingMode::ROM => 0,
+            BankingMode::RAM => self.ram_bank,
         }
     }
 }
@@ -111,68 +337,57 @@ impl Mbc for Mbc1 {
         MbcType::MBC1
     }
 
+    fn read_fixed_rom(&self, addr: Word) -> Byte {
+        let resolved_addr = (addr as usize) + ((self.get_lower_rom_bank() % self.number_of_rom_banks) * ROM_BANK_SIZE);
+        self.memory[resolved_addr]
+    }
+
     fn read_rom(&self, addr: Word) -> Byte {
-        let resolved_addr = (addr as usize) + (self.rom_bank * 0x4000);
+        let resolved_addr = (addr as usize) + ((self.get_upper_rom_bank() % self.number_of_rom_banks) * ROM_BANK_SIZE);
         self.memory[resolved_addr]
     }
 
     fn read_ram(&self, addr: Word) -> Byte {
-        self.external_ram[(addr as usize) + (self.ram_bank * RAM_BANK_SIZE) as usize]
+        if !self.enable_ram {
+            return 0xFF;
+        }
+
+        match resolve_ram_addr(&self.external_ram, self.get_ram_bank(), addr) {
+            Some(resolved_addr) => self.external_ram[resolved_addr],
+            None => 0xFF,
+        }
     }
 
     fn write_ram(&mut self, addr: Word, data: Byte) {
-        self.external_ram[(addr as usize) + (self.ram_bank * RAM_BANK_SIZE)] = data;
+        if !self.enable_ram {
+            return;
+        }
+
+        if let Some(resolved_addr) = resolve_ram_addr(&self.external_ram, self.get_ram_bank(), addr) {
+            self.external_ram[resolved_addr] = data;
+        }
     }
 
     fn handle_banking(&mut self, addr: Word, data: Byte) {
         match addr {
-            0x0000..=0x1FFF => if (data & 0xF) == 0xA {self.enable_ram = true} else {self.enable_ram = false},
+            // Any value with 0xA in the lower 4 bits enables RAM, anything else disables it
+            0x0000..=0x1FFF => self.enable_ram = (data & 0xF) == 0xA,
             0x2000..=0x3FFF => {
-                let new_rom_bank = data & 0x1F;
-
-                // Preserve the high bits and set the lower 5 bits
-                self.rom_bank = (self.rom_bank & 0b11100000) | (new_rom_bank as usize);
-
-                // I don't know why for sure, but because ROM Bank 0 is written directly to memory and
-                // we'll always read from there, setting bank to 0 doesn't make sense so incrememnt it
+                // BANK1 can't be 0, writing 0 selects bank 1 instead
+                self.rom_bank = (data & 0x1F) as usize;
                 if self.rom_bank == 0 {
-                    self.rom_bank += 1;
-                }
-
-                if self.rom_bank > self.number_of_rom_banks as usize {
-                    // If we request a bank greater than what the ROM has, we need to mask
-                    // TODO see pandocs for details
-                    println!("TODO TOO MANY BANK");
+                    self.rom_bank = 1;
                 }
             },
             0x4000..=0x5FFF => {
-                // Set RAM Bank or ROM bank hi bits depending on banking mode
-                // RAM Bank is set to bottom 3 bits and ROM bank sets the hi bits of its number
-                match self.banking_mode {
-                    BankingMode::RAM => self.ram_bank = (data & 0x03) as usize,
-                    BankingMode::ROM => {
-                        let new_rom_bank = data & 0xE0; // Top 3 bits
-
-                        // Preserve the lo bits and set the higher 3 bits
-                        self.rom_bank = (new_rom_bank | ((self.rom_bank as u8) & 0b00011111)) as usize;
-                        if self.rom_bank == 0 {
-                            self.rom_bank += 1;
-                        }
-
-                        if self.rom_bank > self.number_of_rom_banks as usize {
-                            // If we request a bank greater than what the ROM has, we need to mask
-                            // TODO see pandocs for details
-                            println!("TOO MANY BANK");
-                        }
-                    },
-                };
+                // BANK2 is always written, what it is used for depends on the banking mode
+                self.ram_bank = (data & 0x03) as usize;
             },
             0x6000..=0x7FFF => {
-                // For MBC1 Change the Banking mode to either RAM or ROM so we can decide
-                // which bank number to adjust when writing to addr 0x4000 - 0x5FFF
-                // To do this, we check the least signifcant bit of the data being written
-                //   0 = ROM Banking Mode (Default)
-                //   1 = RAM Banking Mode
+                // Change the banking mode, which decides what BANK2 applies to. To do this, we check
+                // the least signifcant bit of the data being written
+                //   0 = ROM Banking Mode (Default) - BANK2 only applies to 0x4000 - 0x7FFF
+                //   1 = RAM Banking Mode - BANK2 also applies to 0x0000 - 0x3FFF and RAM
                 self.banking_mode = match is_bit_set(&data, 0) {
                     true => BankingMode::RAM,
                     false => BankingMode::ROM,
@@ -186,30 +401,49 @@ impl Mbc for Mbc1 {
         &self.external_ram
     }
 
-    fn load_external_ram(&mut self, buffer: Vec<Byte>) {
-        let ram_len = self.external_ram.len();
-        for i in 0..cmp::min(ram_len, buffer.len()) {
-            self.external_ram[i] = buffer[i];
-        }
+    fn load_external_ram(&mut self, buffer: Vec<Byte>) -> Vec<String> {
+        load_into_external_ram(&mut self.external_ram, buffer);
+        Vec::new()
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_usize(self.rom_bank);
+        writer.write_usize(self.ram_bank);
+        writer.write_bool(self.enable_ram);
+        writer.write_bool(matches!(self.banking_mode, BankingMode::RAM));
+        writer.write_bytes(&self.external_ram);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> io::Result<()> {
+        self.rom_bank = reader.read_usize()? & 0x1F;
+        self.ram_bank = reader.read_usize()? & 0x03;
+        self.enable_ram = reader.read_bool()?;
+        self.banking_mode = match reader.read_bool()? {
+            true => BankingMode::RAM,
+            false => BankingMode::ROM,
+        };
+        reader.read_bytes_into(&mut self.external_ram)?;
+        Ok(())
     }
 
     fn debug(&self) -> String {
-        format!("MBC Type: {:?}\nROM BANK: {}", self.get_mbc_type(), self.rom_bank)
+        format!(
+            "MBC Type: {:?}\nROM BANK: {}\nRAM BANK: {}\nMulticart: {}",
+            self.get_mbc_type(), self.get_upper_rom_bank(), self.get_ram_bank(), self.is_multicart
+        )
     }
 }
 
 impl Mbc2 {
 
     pub fn new(rom: &Rom) -> Mbc2 {
-        let mut memory = Vec::new();
-        for i in 0..rom.length() {
-            memory.push(rom.get_byte(i));
-        }
+        let (memory, number_of_rom_banks) = load_rom_banks(rom);
 
         Mbc2 {
             memory: memory,
             rom_bank: 1,
-            external_ram: [0; 0x200], 
+            number_of_rom_banks: number_of_rom_banks,
+            external_ram: [0; 0x200],
             enable_ram: false,
         }
     }
@@ -220,8 +454,12 @@ impl Mbc for Mbc2 {
         MbcType::MBC2
     }
 
+    fn read_fixed_rom(&self, addr: Word) -> Byte {
+        self.memory[addr as usize]
+    }
+
     fn read_rom(&self, addr: Word) -> Byte {
-        let resolved_addr = (addr as usize) + (self.rom_bank * 0x4000);
+        let resolved_addr = (addr as usize) + ((self.rom_bank % self.number_of_rom_banks) * ROM_BANK_SIZE);
         self.memory[resolved_addr]
     }
 
@@ -269,11 +507,22 @@ impl Mbc for Mbc2 {
         &self.external_ram
     }
 
-    fn load_external_ram(&mut self, buffer: Vec<Byte>) {
-        let ram_len = self.external_ram.len();
-        for i in 0..cmp::min(ram_len, buffer.len()) {
-            self.external_ram[i] = buffer[i];
-        }
+    fn load_external_ram(&mut self, buffer: Vec<Byte>) -> Vec<String> {
+        load_into_external_ram(&mut self.external_ram, buffer);
+        Vec::new()
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_usize(self.rom_bank);
+        writer.write_bool(self.enable_ram);
+        writer.write_bytes(&self.external_ram);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> io::Result<()> {
+        self.rom_bank = reader.read_usize()?;
+        self.enable_ram = reader.read_bool()?;
+        reader.read_bytes_into(&mut self.external_ram)?;
+        Ok(())
     }
 
     fn debug(&self) -> String {
@@ -282,24 +531,18 @@ impl Mbc for Mbc2 {
 }
 
 impl Mbc3 {
-    pub fn new(rom: &Rom) -> Mbc3 {
-        let mut memory = Vec::new();
-        for i in 0..rom.length() {
-            memory.push(rom.get_byte(i));
-        }
+    pub fn new(rom: &Rom, has_rtc: bool) -> Mbc3 {
+        let (memory, number_of_rom_banks) = load_rom_banks(rom);
 
         Mbc3 {
             memory: memory,
             rom_bank: 1,
             ram_bank_or_rtc: 0,
-            external_ram: [0; MAXIMUM_RAM_BANKS * RAM_BANK_SIZE],
+            external_ram: create_external_ram(rom),
             enable_ram_and_rtc: false,
-            number_of_rom_banks: rom.get_number_of_banks() as u8,
-            rtc_seconds: 0,
-            rtc_minutes: 0,
-            rtc_hours: 0,
-            rtc_dl: 0,
-            rtc_dh: 0,
+            number_of_rom_banks: number_of_rom_banks,
+            has_rtc: has_rtc,
+            rtc: Rtc::new(),
         }
     }
 }
@@ -309,22 +552,31 @@ impl Mbc for Mbc3 {
         MbcType::MBC3
     }
 
+    fn read_fixed_rom(&self, addr: Word) -> Byte {
+        self.memory[addr as usize]
+    }
+
     fn read_rom(&self, addr: Word) -> Byte {
-        let resolved_addr = (addr as usize) + (self.rom_bank * 0x4000);
+        let resolved_addr = (addr as usize) + ((self.rom_bank % self.number_of_rom_banks) * ROM_BANK_SIZE);
         self.memory[resolved_addr]
     }
 
     fn read_ram(&self, addr: Word) -> Byte {
+        if !self.enable_ram_and_rtc {
+            return 0xFF;
+        }
+
         match self.ram_bank_or_rtc {
-            0x00..=0x03 => self.external_ram[(addr as usize) + (self.ram_bank_or_rtc * RAM_BANK_SIZE) as usize],
-            0x08 => self.rtc_seconds,
-            0x09 => self.rtc_minutes,
-            0x0A => self.rtc_hours,
-            0x0B => self.rtc_dl,
-            0x0C => self.rtc_dh,
+            // MBC3 has 4 RAM banks, but the MBC30 variant used by Pokemon Crystal (JP) has 8
+            0x00..=0x07 => match resolve_ram_addr(&self.external_ram, self.ram_bank_or_rtc, addr) {
+                Some(resolved_addr) => self.external_ram[resolved_addr],
+                None => 0xFF,
+            },
+            0x08..=0x0C if !self.has_rtc => 0xFF,
+            0x08..=0x0C => self.rtc.read_register(self.ram_bank_or_rtc),
             _ => {
                 println!("Invalid value for RAM/RTC bank [{:02X}] for read in MBC3", self.ram_bank_or_rtc);
-                0
+                0xFF
             }
         }
     }
@@ -332,12 +584,13 @@ impl Mbc for Mbc3 {
     fn write_ram(&mut self, addr: Word, data: Byte) {
         if self.enable_ram_and_rtc {
             match self.ram_bank_or_rtc {
-                0x00..=0x03 => self.external_ram[(addr as usize) + (self.ram_bank_or_rtc * RAM_BANK_SIZE)] = data,
-                0x08 => self.rtc_seconds = data,
-                0x09 => self.rtc_minutes = data,
-                0x0A => self.rtc_hours = data,
-                0x0B => self.rtc_dl = data,
-                0x0C => self.rtc_dh = data,
+                0x00..=0x07 => {
+                    if let Some(resolved_addr) = resolve_ram_addr(&self.external_ram, self.ram_bank_or_rtc, addr) {
+                        self.external_ram[resolved_addr] = data;
+                    }
+                },
+                0x08..=0x0C if !self.has_rtc => {},
+                0x08..=0x0C => self.rtc.write_register(self.ram_bank_or_rtc, data),
                 _ => println!("Invalid value for RAM/RTC bank [{:02X}] for write in MBC3", self.ram_bank_or_rtc)
             };
         }
@@ -353,7 +606,11 @@ impl Mbc for Mbc3 {
                 }
             },
             0x4000..=0x5FFF => self.ram_bank_or_rtc = data as usize,
-            0x6000..=0x7FFF => println!("TODO Latch data"),
+            0x6000..=0x7FFF => {
+                if self.has_rtc {
+                    self.rtc.latch(data);
+                }
+            },
             _ => println!("Invalid address {}", addr)
         };
     }
@@ -362,33 +619,73 @@ impl Mbc for Mbc3 {
         &self.external_ram
     }
 
-    fn load_external_ram(&mut self, buffer: Vec<Byte>) {
+    fn load_external_ram(&mut self, buffer: Vec<Byte>) -> Vec<String> {
+        // Anything after the RAM is the RTC footer
         let ram_len = self.external_ram.len();
-        for i in 0..cmp::min(ram_len, buffer.len()) {
-            self.external_ram[i] = buffer[i];
+        let mut warnings = Vec::new();
+        if self.has_rtc && buffer.len() > ram_len {
+            warnings.extend(self.rtc.load_footer(&buffer[ram_len..]));
+        }
+
+        load_into_external_ram(&mut self.external_ram, buffer);
+        warnings
+    }
+
+    fn get_save_data(&self) -> Vec<Byte> {
+        let mut save_data = self.external_ram.clone();
+        if self.has_rtc {
+            save_data.extend(self.rtc.get_footer());
         }
+        save_data
+    }
+
+    fn update(&mut self, cycles: u8) {
+        if self.has_rtc {
+            self.rtc.update(cycles);
+        }
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_usize(self.rom_bank);
+        writer.write_usize(self.ram_bank_or_rtc);
+        writer.write_bool(self.enable_ram_and_rtc);
+        writer.write_bytes(&self.external_ram);
+        self.rtc.save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> io::Result<()> {
+        self.rom_bank = reader.read_usize()?;
+        self.ram_bank_or_rtc = reader.read_usize()?;
+        self.enable_ram_and_rtc = reader.read_bool()?;
+        reader.read_bytes_into(&mut self.external_ram)?;
+        self.rtc.load_state(reader)
+
     }
 
     fn debug(&self) -> String {
-        format!("MBC Type: {:?}\nROM BANK: {}", self.get_mbc_type(), self.rom_bank)
+        match self.has_rtc {
+            true => format!("MBC Type: {:?}\nROM BANK: {}\n{}", self.get_mbc_type(), self.rom_bank, self.rtc.debug()),
+            false => format!("MBC Type: {:?}\nROM BANK: {}", self.get_mbc_type(), self.rom_bank),
+        }
     }
 }
 
 impl Mbc5 {
 
-    pub fn new(rom: &Rom) -> Mbc5 {
-        let mut memory = Vec::new();
-        for i in 0..rom.length() {
-            memory.push(rom.get_byte(i));
-        }
+    pub fn new(rom: &Rom, has_rumble: bool) -> Mbc5 {
+        let (memory, number_of_rom_banks) = load_rom_banks(rom);
 
         Mbc5 {
             memory: memory,
             rom_bank: 1,
             ram_bank: 0,
-            external_ram: [0; 16 * RAM_BANK_SIZE],
+            external_ram: create_external_ram(rom),
             enable_ram: false,
-            number_of_rom_banks: rom.get_number_of_banks() as u8,
+            number_of_rom_banks: number_of_rom_banks,
+            has_rumble: has_rumble,
+            rumble: false,
+            rumble_cycles: 0,
+            total_cycles: 0,
         }
     }
 }
@@ -398,17 +695,34 @@ impl Mbc for Mbc5 {
         MbcType::MBC5
     }
 
+    fn read_fixed_rom(&self, addr: Word) -> Byte {
+        self.memory[addr as usize]
+    }
+
     fn read_rom(&self, addr: Word) -> Byte {
-        let resolved_addr = (addr as usize) + (self.rom_bank * 0x4000);
+        let resolved_addr = (addr as usize) + ((self.rom_bank % self.number_of_rom_banks) * ROM_BANK_SIZE);
         self.memory[resolved_addr]
     }
 
     fn read_ram(&self, addr: Word) -> Byte {
-        self.external_ram[(addr as usize) + (self.ram_bank * RAM_BANK_SIZE) as usize]
+        if !self.enable_ram {
+            return 0xFF;
+        }
+
+        match resolve_ram_addr(&self.external_ram, self.ram_bank, addr) {
+            Some(resolved_addr) => self.external_ram[resolved_addr],
+            None => 0xFF,
+        }
     }
 
     fn write_ram(&mut self, addr: Word, data: Byte) {
-        self.external_ram[(addr as usize) + (self.ram_bank * RAM_BANK_SIZE)] = data;
+        if !self.enable_ram {
+            return;
+        }
+
+        if let Some(resolved_addr) = resolve_ram_addr(&self.external_ram, self.ram_bank, addr) {
+            self.external_ram[resolved_addr] = data;
+        }
     }
 
     fn handle_banking(&mut self, addr: Word, data: Byte) {
@@ -421,11 +735,19 @@ impl Mbc for Mbc5 {
                 self.rom_bank = (bit_9 << 8) | (data as usize);
             },
             0x3000..=0x3FFF => {
-                // Bit 9 of rom_bank
-                let bit = if data > 0 { 1 } else { 0 };
-                self.rom_bank = bit << 8 | self.rom_bank;
+                // Bit 9 of rom_bank comes from bit 0 of the data
+                let bit_9 = (data & 0x01) as usize;
+                self.rom_bank = (bit_9 << 8) | (self.rom_bank & 0xFF);
+            },
+            0x4000..=0x5FFF => {
+                // On rumble cartridges bit 3 turns the motor on and off instead of selecting a RAM bank
+                if self.has_rumble {
+                    self.rumble = is_bit_set(&data, 3);
+                    self.ram_bank = (data & 0x07) as usize;
+                } else {
+                    self.ram_bank = (data & 0x0F) as usize;
+                }
             },
-            0x4000..=0x5FFF => self.ram_bank = data as usize,
             _ => println!("Invalid address {}", addr)
         }
     }
@@ -434,15 +756,944 @@ impl Mbc for Mbc5 {
         &self.external_ram
     }
 
-    fn load_external_ram(&mut self, buffer: Vec<Byte>) {
-        let ram_len = self.external_ram.len();
-        for i in 0..cmp::min(ram_len, buffer.len()) {
-            self.external_ram[i] = buffer[i];
+    fn load_external_ram(&mut self, buffer: Vec<Byte>) -> Vec<String> {
+        load_into_external_ram(&mut self.external_ram, buffer);
+        Vec::new()
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_usize(self.rom_bank);
+        writer.write_usize(self.ram_bank);
+        writer.write_bool(self.enable_ram);
+        writer.write_bytes(&self.external_ram);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> io::Result<()> {
+        self.rom_bank = reader.read_usize()?;
+        self.ram_bank = reader.read_usize()?;
+        self.enable_ram = reader.read_bool()?;
+        reader.read_bytes_into(&mut self.external_ram)?;
+        Ok(())
+    }
+
+    fn debug(&self) -> String {
+        match self.has_rumble {
+            true => format!("MBC Type: {:?}\nROM BANK: {}\nRumble: {}", self.get_mbc_type(), self.rom_bank, self.rumble),
+            false => format!("MBC Type: {:?}\nROM BANK: {}", self.get_mbc_type(), self.rom_bank),
+        }
+    }
+
+    fn update(&mut self, cycles: u8) {
+        if self.has_rumble {
+            self.total_cycles += cycles as usize;
+            if self.rumble {
+                self.rumble_cycles += cycles as usize;
+            }
+        }
+    }
+
+    fn take_rumble_strength(&mut self) -> f32 {
+        // Nothing has run (e.g. while paused) so there is nothing to rumble for
+        if self.total_cycles == 0 {
+            return 0.0;
+        }
+
+        let strength = self.rumble_cycles as f32 / self.total_cycles as f32;
+        self.rumble_cycles = 0;
+        self.total_cycles = 0;
+        strength
+    }
+}
+
+pub struct Mbc7 {
+    memory: Vec<Byte>,
+    rom_bank: usize,
+    number_of_rom_banks: usize,
+
+    // RAM (the accelerometer and EEPROM registers) needs enabling twice, with 0x0A written to
+    // 0x0000 - 0x1FFF and 0x40 written to 0x4000 - 0x5FFF
+    enable_ram_1: bool,
+    enable_ram_2: bool,
+
+    // Accelerometer - the tilt is what the player is doing now, which the game latches into
+    // the x and y registers when it wants a reading
+    tilt_x: f32,
+    tilt_y: f32,
+    accelerometer_x: Word,
+    accelerometer_y: Word,
+    accelerometer_erased: bool,
+
+    eeprom: Eeprom,
+}
+
+impl Mbc7 {
+
+    pub fn new(rom: &Rom) -> Mbc7 {
+        let (memory, number_of_rom_banks) = load_rom_banks(rom);
+
+        Mbc7 {
+            memory: memory,
+            rom_bank: 1,
+            number_of_rom_banks: number_of_rom_banks,
+            enable_ram_1: false,
+            enable_ram_2: false,
+            tilt_x: 0.0,
+            tilt_y: 0.0,
+            accelerometer_x: ACCELEROMETER_CENTER,
+            accelerometer_y: ACCELEROMETER_CENTER,
+            accelerometer_erased: false,
+            eeprom: Eeprom::new(),
+        }
+    }
+
+    fn latch_accelerometer(&mut self) {
+        // Latching only works after the previous reading has been erased. Tilting right makes x
+        // smaller, tilting towards the player makes y bigger
+        if !self.accelerometer_erased {
+            return;
         }
+
+        self.accelerometer_x = (ACCELEROMETER_CENTER as f32 - (self.tilt_x * ACCELEROMETER_ONE_G as f32)) as Word;
+        self.accelerometer_y = (ACCELEROMETER_CENTER as f32 + (self.tilt_y * ACCELEROMETER_ONE_G as f32)) as Word;
+        self.accelerometer_erased = false;
+    }
+}
+
+impl Mbc for Mbc7 {
+    fn get_mbc_type(&self) -> MbcType {
+        MbcType::MBC7
+    }
+
+    fn read_fixed_rom(&self, addr: Word) -> Byte {
+        self.memory[addr as usize]
+    }
+
+    fn read_rom(&self, addr: Word) -> Byte {
+        let resolved_addr = (addr as usize) + ((self.rom_bank % self.number_of_rom_banks) * ROM_BANK_SIZE);
+        self.memory[resolved_addr]
+    }
+
+    fn read_ram(&self, addr: Word) -> Byte {
+        // Registers are only in 0xA000 - 0xAFFF, selected by bits 4 - 7 of the address
+        if !self.enable_ram_1 || !self.enable_ram_2 || addr >= 0x1000 {
+            return 0xFF;
+        }
+
+        match (addr >> 4) & 0x0F {
+            0x2 => self.accelerometer_x as Byte,
+            0x3 => (self.accelerometer_x >> 8) as Byte,
+            0x4 => self.accelerometer_y as Byte,
+            0x5 => (self.accelerometer_y >> 8) as Byte,
+            0x6 => 0x00,
+            0x8 => self.eeprom.read(),
+            _ => 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, addr: Word, data: Byte) {
+        if !self.enable_ram_1 || !self.enable_ram_2 || addr >= 0x1000 {
+            return;
+        }
+
+        match (addr >> 4) & 0x0F {
+            0x0 => {
+                if data == 0x55 {
+                    self.accelerometer_x = ACCELEROMETER_ERASED;
+                    self.accelerometer_y = ACCELEROMETER_ERASED;
+                    self.accelerometer_erased = true;
+                }
+            },
+            0x1 => {
+                if data == 0xAA {
+                    self.latch_accelerometer();
+                }
+            },
+            0x8 => self.eeprom.write(data),
+            _ => {},
+        };
+    }
+
+    fn handle_banking(&mut self, addr: Word, data: Byte) {
+        match addr {
+            0x0000..=0x1FFF => self.enable_ram_1 = data == 0x0A,
+            0x2000..=0x3FFF => self.rom_bank = data as usize,
+            0x4000..=0x5FFF => self.enable_ram_2 = data == 0x40,
+            _ => {},
+        };
+    }
+
+    fn get_external_ram(&self) -> &[Byte] {
+        // The EEPROM is the save file
+        self.eeprom.get_data()
+    }
+
+    fn load_external_ram(&mut self, buffer: Vec<Byte>) -> Vec<String> {
+        self.eeprom.load_data(&buffer);
+        Vec::new()
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_usize(self.rom_bank);
+        writer.write_bool(self.enable_ram_1);
+        writer.write_bool(self.enable_ram_2);
+        writer.write_word(self.accelerometer_x);
+        writer.write_word(self.accelerometer_y);
+        writer.write_bool(self.accelerometer_erased);
+        self.eeprom.save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> io::Result<()> {
+        self.rom_bank = reader.read_usize()?;
+        self.enable_ram_1 = reader.read_bool()?;
+        self.enable_ram_2 = reader.read_bool()?;
+        self.accelerometer_x = reader.read_word()?;
+        self.accelerometer_y = reader.read_word()?;
+        self.accelerometer_erased = reader.read_bool()?;
+        self.eeprom.load_state(reader)
     }
 
     fn debug(&self) -> String {
-        format!("MBC Type: {:?}\nROM BANK: {}", self.get_mbc_type(), self.rom_bank)
+        format!(
+            "MBC Type: {:?}\nROM BANK: {}\nTilt: {:.2}, {:.2}",
+            self.get_mbc_type(), self.rom_bank, self.tilt_x, self.tilt_y
+        )
+    }
+
+    fn set_tilt(&mut self, x: f32, y: f32) {
+        self.tilt_x = x.clamp(-1.0, 1.0);
+        self.tilt_y = y.clamp(-1.0, 1.0);
     }
 }
 
+pub struct PocketCamera {
+    memory: Vec<Byte>,
+    rom_bank: usize,
+    ram_bank: usize,
+    external_ram: Vec<Byte>,
+    enable_ram: bool,
+    number_of_rom_banks: usize,
+
+    // A000 - A035 when RAM bank 0x10 is selected, see camera::process_image
+    camera_registers: [Byte; CAMERA_REGISTER_COUNT],
+    capture_cycles_remaining: usize,
+    sensor: CameraSensor,
+}
+
+impl PocketCamera {
+
+    pub fn new(rom: &Rom) -> PocketCamera {
+        let (memory, number_of_rom_banks) = load_rom_banks(rom);
+
+        PocketCamera {
+            memory: memory,
+            rom_bank: 1,
+            ram_bank: 0,
+            external_ram: vec![0; POCKET_CAMERA_RAM_SIZE],
+            enable_ram: false,
+            number_of_rom_banks: number_of_rom_banks,
+            camera_registers: [0; CAMERA_REGISTER_COUNT],
+            capture_cycles_remaining: 0,
+            sensor: CameraSensor::new(),
+        }
+    }
+
+    fn is_capturing(&self) -> bool {
+        self.capture_cycles_remaining > 0
+    }
+
+    fn finish_capture(&mut self) {
+        let frame = self.sensor.capture();
+        let image = process_image(&self.camera_registers, &frame);
+        self.external_ram[CAMERA_IMAGE_ADDR..(CAMERA_IMAGE_ADDR + CAMERA_IMAGE_SIZE)].copy_from_slice(&image);
+        reset_bit(&mut self.camera_registers[0], 0);
+    }
+}
+
+impl Mbc for PocketCamera {
+    fn get_mbc_type(&self) -> MbcType {
+        MbcType::POCKET_CAMERA
+    }
+
+    fn read_fixed_rom(&self, addr: Word) -> Byte {
+        self.memory[addr as usize]
+    }
+
+    fn read_rom(&self, addr: Word) -> Byte {
+        let resolved_addr = (addr as usize) + ((self.rom_bank % self.number_of_rom_banks) * ROM_BANK_SIZE);
+        self.memory[resolved_addr]
+    }
+
+    fn read_ram(&self, addr: Word) -> Byte {
+        // Only A000 can be read back from the camera, bit 0 being set while a capture is running
+        if self.ram_bank & CAMERA_REGISTERS_BANK != 0 {
+            return match addr & 0x7F {
+                0x00 => (self.camera_registers[0] & 0x06) | (self.is_capturing() as Byte),
+                _ => 0x00,
+            };
+        }
+
+        // RAM can always be read, it only needs enabling for writes
+        match resolve_ram_addr(&self.external_ram, self.ram_bank, addr) {
+            Some(resolved_addr) => self.external_ram[resolved_addr],
+            None => 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, addr: Word, data: Byte) {
+        if self.ram_bank & CAMERA_REGISTERS_BANK != 0 {
+            let register = (addr & 0x7F) as usize;
+            match register {
+                0x00 => {
+                    // Setting bit 0 starts a capture, which can't be stopped once started
+                    self.camera_registers[0] = (data & 0x07) | (self.is_capturing() as Byte);
+                    if is_bit_set(&data, 0) && !self.is_capturing() {
+                        self.capture_cycles_remaining = get_capture_cycles(&self.camera_registers);
+                    }
+                },
+                0x01..=0x35 => self.camera_registers[register] = data,
+                _ => {},
+            };
+            return;
+        }
+
+        if !self.enable_ram {
+            return;
+        }
+
+        if let Some(resolved_addr) = resolve_ram_addr(&self.external_ram, self.ram_bank, addr) {
+            self.external_ram[resolved_addr] = data;
+        }
+    }
+
+    fn handle_banking(&mut self, addr: Word, data: Byte) {
+        match addr {
+            0x0000..=0x1FFF => self.enable_ram = (data & 0x0F) == 0x0A,
+            0x2000..=0x3FFF => self.rom_bank = (data & 0x3F) as usize,
+            0x4000..=0x5FFF => self.ram_bank = (data & 0x1F) as usize,
+            _ => {},
+        };
+    }
+
+    fn get_external_ram(&self) -> &[Byte] {
+        &self.external_ram
+    }
+
+    fn load_external_ram(&mut self, buffer: Vec<Byte>) -> Vec<String> {
+        load_into_external_ram(&mut self.external_ram, buffer);
+        Vec::new()
+    }
+
+    fn update(&mut self, cycles: u8) {
+        if self.is_capturing() {
+            self.capture_cycles_remaining = self.capture_cycles_remaining.saturating_sub(cycles as usize);
+            if !self.is_capturing() {
+                self.finish_capture();
+            }
+        }
+    }
+
+    fn set_camera_sensor(&mut self, sensor: CameraSensor) {
+        self.sensor = sensor;
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_usize(self.rom_bank);
+        writer.write_usize(self.ram_bank);
+        writer.write_bool(self.enable_ram);
+        writer.write_bytes(&self.external_ram);
+        writer.write_bytes(&self.camera_registers);
+        writer.write_usize(self.capture_cycles_remaining);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> io::Result<()> {
+        self.rom_bank = reader.read_usize()?;
+        self.ram_bank = reader.read_usize()?;
+        self.enable_ram = reader.read_bool()?;
+        reader.read_bytes_into(&mut self.external_ram)?;
+        reader.read_bytes_into(&mut self.camera_registers)?;
+        self.capture_cycles_remaining = reader.read_usize()?;
+        Ok(())
+    }
+
+    fn debug(&self) -> String {
+        format!(
+            "MBC Type: {:?}\nROM BANK: {}\nRAM BANK: {}\nCapturing: {}",
+            self.get_mbc_type(), self.rom_bank, self.ram_bank, self.is_capturing()
+        )
+    }
+}
+
+pub struct HuC1 {
+    memory: Vec<Byte>,
+    rom_bank: usize,
+    ram_bank: usize,
+    external_ram: Vec<Byte>,
+    number_of_rom_banks: usize,
+
+    // Writing 0x0E to 0x0000 - 0x1FFF maps the IR port to 0xA000 - 0xBFFF instead of RAM
+    ir_mode: bool,
+    ir_led: bool,
+}
+
+impl HuC1 {
+
+    pub fn new(rom: &Rom) -> HuC1 {
+        let (memory, number_of_rom_banks) = load_rom_banks(rom);
+
+        HuC1 {
+            memory: memory,
+            rom_bank: 1,
+            ram_bank: 0,
+            external_ram: create_external_ram(rom),
+            number_of_rom_banks: number_of_rom_banks,
+            ir_mode: false,
+            ir_led: false,
+        }
+    }
+}
+
+impl Mbc for HuC1 {
+    fn get_mbc_type(&self) -> MbcType {
+        MbcType::HUC1
+    }
+
+    fn read_fixed_rom(&self, addr: Word) -> Byte {
+        self.memory[addr as usize]
+    }
+
+    fn read_rom(&self, addr: Word) -> Byte {
+        let resolved_addr = (addr as usize) + ((self.rom_bank % self.number_of_rom_banks) * ROM_BANK_SIZE);
+        self.memory[resolved_addr]
+    }
+
+    fn read_ram(&self, addr: Word) -> Byte {
+        // There is nobody on the other end of the IR port, so bit 0 (light received) is never set
+        if self.ir_mode {
+            return IR_NO_LIGHT;
+        }
+
+        match resolve_ram_addr(&self.external_ram, self.ram_bank, addr) {
+            Some(resolved_addr) => self.external_ram[resolved_addr],
+            None => 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, addr: Word, data: Byte) {
+        if self.ir_mode {
+            self.ir_led = is_bit_set(&data, 0);
+            return;
+        }
+
+        if let Some(resolved_addr) = resolve_ram_addr(&self.external_ram, self.ram_bank, addr) {
+            self.external_ram[resolved_addr] = data;
+        }
+    }
+
+    fn handle_banking(&mut self, addr: Word, data: Byte) {
+        match addr {
+            // HuC1 has no RAM enable - anything other than 0x0E just selects RAM
+            0x0000..=0x1FFF => self.ir_mode = (data & 0x0F) == 0x0E,
+            0x2000..=0x3FFF => {
+                self.rom_bank = (data & 0x3F) as usize;
+                if self.rom_bank == 0 {
+                    self.rom_bank = 1;
+                }
+            },
+            0x4000..=0x5FFF => self.ram_bank = (data & 0x03) as usize,
+            _ => {},
+        };
+    }
+
+    fn get_external_ram(&self) -> &[Byte] {
+        &self.external_ram
+    }
+
+    fn load_external_ram(&mut self, buffer: Vec<Byte>) -> Vec<String> {
+        load_into_external_ram(&mut self.external_ram, buffer);
+        Vec::new()
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_usize(self.rom_bank);
+        writer.write_usize(self.ram_bank);
+        writer.write_bool(self.ir_mode);
+        writer.write_bool(self.ir_led);
+        writer.write_bytes(&self.external_ram);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> io::Result<()> {
+        self.rom_bank = reader.read_usize()?;
+        self.ram_bank = reader.read_usize()?;
+        self.ir_mode = reader.read_bool()?;
+        self.ir_led = reader.read_bool()?;
+        reader.read_bytes_into(&mut self.external_ram)
+    }
+
+    fn debug(&self) -> String {
+        format!("MBC Type: {:?}\nROM BANK: {}\nIR Mode: {}", self.get_mbc_type(), self.rom_bank, self.ir_mode)
+    }
+}
+
+pub struct HuC3 {
+    /**
+    * Hudson's HuC3 has RAM, an IR port, a speaker and a clock. Writes to 0x0000 - 0x1FFF choose
+    * what 0xA000 - 0xBFFF is connected to:
+    *    0x0    RAM, read only
+    *    0xA    RAM, read / write
+    *    0xB    RTC command, bits 4 - 6 are the command and bits 0 - 3 its argument
+    *    0xC    RTC response, bits 4 - 6 are the last command and bits 0 - 3 the result
+    *    0xD    RTC semaphore, bit 0 reads as 1 when the RTC is ready for another command
+    *    0xE    IR port
+    *
+    * The clock's memory is read and written a nibble at a time through an access index:
+    *    0x1    Read the nibble at the access index into the response, then increment the index
+    *    0x3    Write the argument to the nibble at the access index, then increment the index
+    *    0x4    Set the lower nibble of the access index
+    *    0x5    Set the upper nibble of the access index
+    *    0x6    Extended command - 0xE plays a tone through the cartridge speaker
+    * Nibbles 0x00 - 0x02 hold the minute of the day (0 - 1439), and 0x03 - 0x06 the day counter.
+    * 0x58 - 0x5F hold an alarm which we store but never sound
+    **/
+
+    memory: Vec<Byte>,
+    rom_bank: usize,
+    ram_bank: usize,
+    external_ram: Vec<Byte>,
+    number_of_rom_banks: usize,
+    mode: Byte,
+
+    rtc_minutes: Word,
+    rtc_days: Word,
+    rtc_cycles: usize,
+    rtc_alarm: [Byte; 8],
+    rtc_access_index: usize,
+    rtc_command: Byte,
+    rtc_response: Byte,
+    tone_count: usize,
+    ir_led: bool,
+}
+
+impl HuC3 {
+
+    pub fn new(rom: &Rom) -> HuC3 {
+        let (memory, number_of_rom_banks) = load_rom_banks(rom);
+
+        HuC3 {
+            memory: memory,
+            rom_bank: 1,
+            ram_bank: 0,
+            external_ram: create_external_ram(rom),
+            number_of_rom_banks: number_of_rom_banks,
+            mode: 0,
+            rtc_minutes: 0,
+            rtc_days: 0,
+            rtc_cycles: 0,
+            rtc_alarm: [0; 8],
+            rtc_access_index: 0,
+            rtc_command: 0,
+            rtc_response: 0,
+            tone_count: 0,
+            ir_led: false,
+        }
+    }
+
+    fn add_rtc_minutes(&mut self, minutes: u64) {
+        let total = self.rtc_minutes as u64 + minutes;
+        self.rtc_minutes = (total % HUC3_MINUTES_PER_DAY) as Word;
+        self.rtc_days = self.rtc_days.wrapping_add((total / HUC3_MINUTES_PER_DAY) as Word);
+    }
+
+    fn read_rtc_nibble(&self, index: usize) -> Byte {
+        match index {
+            0x00..=0x02 => ((self.rtc_minutes >> (index * 4)) & 0x0F) as Byte,
+            0x03..=0x06 => ((self.rtc_days >> ((index - 0x03) * 4)) & 0x0F) as Byte,
+            0x58..=0x5F => self.rtc_alarm[index - 0x58],
+            _ => 0,
+        }
+    }
+
+    fn write_rtc_nibble(&mut self, index: usize, data: Byte) {
+        let data = (data & 0x0F) as Word;
+        match index {
+            0x00..=0x02 => {
+                let shift = index * 4;
+                self.rtc_minutes = (self.rtc_minutes & !(0x0F << shift)) | (data << shift);
+            },
+            0x03..=0x06 => {
+                let shift = (index - 0x03) * 4;
+                self.rtc_days = (self.rtc_days & !(0x0F << shift)) | (data << shift);
+            },
+            0x58..=0x5F => self.rtc_alarm[index - 0x58] = data as Byte,
+            _ => {},
+        };
+    }
+
+    fn handle_rtc_command(&mut self, data: Byte) {
+        let command = (data >> 4) & 0x07;
+        let argument = data & 0x0F;
+        self.rtc_command = command;
+
+        match command {
+            0x1 => {
+                self.rtc_response = self.read_rtc_nibble(self.rtc_access_index);
+                self.rtc_access_index = (self.rtc_access_index + 1) & 0xFF;
+            },
+            0x3 => {
+                self.write_rtc_nibble(self.rtc_access_index, argument);
+                self.rtc_access_index = (self.rtc_access_index + 1) & 0xFF;
+            },
+            0x4 => self.rtc_access_index = (self.rtc_access_index & 0xF0) | argument as usize,
+            0x5 => self.rtc_access_index = (self.rtc_access_index & 0x0F) | ((argument as usize) << 4),
+            0x6 => {
+                // We don't emulate the speaker, just count how often the game asks for a tone
+                if argument == 0x0E {
+                    self.tone_count += 1;
+                }
+            },
+            _ => {},
+        };
+    }
+}
+
+impl Mbc for HuC3 {
+    fn get_mbc_type(&self) -> MbcType {
+        MbcType::HUC3
+    }
+
+    fn read_fixed_rom(&self, addr: Word) -> Byte {
+        self.memory[addr as usize]
+    }
+
+    fn read_rom(&self, addr: Word) -> Byte {
+        let resolved_addr = (addr as usize) + ((self.rom_bank % self.number_of_rom_banks) * ROM_BANK_SIZE);
+        self.memory[resolved_addr]
+    }
+
+    fn read_ram(&self, addr: Word) -> Byte {
+        match self.mode {
+            0x0 | 0xA => match resolve_ram_addr(&self.external_ram, self.ram_bank, addr) {
+                Some(resolved_addr) => self.external_ram[resolved_addr],
+                None => 0xFF,
+            },
+            0xC => 0x80 | (self.rtc_command << 4) | self.rtc_response,
+            0xD => 0xFF,
+            0xE => IR_NO_LIGHT,
+            _ => 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, addr: Word, data: Byte) {
+        match self.mode {
+            0xA => {
+                if let Some(resolved_addr) = resolve_ram_addr(&self.external_ram, self.ram_bank, addr) {
+                    self.external_ram[resolved_addr] = data;
+                }
+            },
+            0xB => self.handle_rtc_command(data),
+            0xE => self.ir_led = is_bit_set(&data, 0),
+            _ => {},
+        };
+    }
+
+    fn handle_banking(&mut self, addr: Word, data: Byte) {
+        match addr {
+            0x0000..=0x1FFF => self.mode = data & 0x0F,
+            0x2000..=0x3FFF => {
+                self.rom_bank = (data & 0x7F) as usize;
+                if self.rom_bank == 0 {
+                    self.rom_bank = 1;
+                }
+            },
+            0x4000..=0x5FFF => self.ram_bank = (data & 0x03) as usize,
+            _ => {},
+        };
+    }
+
+    fn get_external_ram(&self) -> &[Byte] {
+        &self.external_ram
+    }
+
+    fn load_external_ram(&mut self, buffer: Vec<Byte>) -> Vec<String> {
+        // Anything after the RAM is the RTC footer, laid out the same way as SameBoy's:
+        //     64 bit UNIX timestamp, 16 bit minutes, 16 bit days, 16 bit alarm minutes,
+        //     16 bit alarm days and an alarm enabled byte, all little endian
+        let ram_len = self.external_ram.len();
+        if buffer.len() >= ram_len + HUC3_RTC_FOOTER_SIZE {
+            let footer = &buffer[ram_len..];
+            let timestamp = u64::from_le_bytes(footer[0..8].try_into().unwrap());
+            self.rtc_minutes = Word::from_le_bytes([footer[8], footer[9]]) % HUC3_MINUTES_PER_DAY as Word;
+            self.rtc_days = Word::from_le_bytes([footer[10], footer[11]]);
+            for i in 0..3 {
+                self.rtc_alarm[i] = (footer[12 + i / 2] >> ((i % 2) * 4)) & 0x0F;
+            }
+            for i in 0..4 {
+                self.rtc_alarm[3 + i] = (footer[14 + i / 2] >> ((i % 2) * 4)) & 0x0F;
+            }
+            self.rtc_alarm[7] = footer[16] & 0x01;
+
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+            self.add_rtc_minutes(now.saturating_sub(timestamp) / 60);
+        }
+
+        load_into_external_ram(&mut self.external_ram, buffer);
+        Vec::new()
+    }
+
+    fn get_save_data(&self) -> Vec<Byte> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        let alarm_minutes = (0..3).fold(0 as Word, |minutes, i| minutes | ((self.rtc_alarm[i] as Word) << (i * 4)));
+        let alarm_days = (0..4).fold(0 as Word, |days, i| days | ((self.rtc_alarm[3 + i] as Word) << (i * 4)));
+
+        let mut save_data = self.external_ram.clone();
+        save_data.extend_from_slice(&now.to_le_bytes());
+        save_data.extend_from_slice(&self.rtc_minutes.to_le_bytes());
+        save_data.extend_from_slice(&self.rtc_days.to_le_bytes());
+        save_data.extend_from_slice(&alarm_minutes.to_le_bytes());
+        save_data.extend_from_slice(&alarm_days.to_le_bytes());
+        save_data.push(self.rtc_alarm[7] & 0x01);
+        save_data
+    }
+
+    fn update(&mut self, cycles: u8) {
+        self.rtc_cycles += cycles as usize;
+        if self.rtc_cycles >= CLOCK_SPEED * 60 {
+            self.rtc_cycles -= CLOCK_SPEED * 60;
+            self.add_rtc_minutes(1);
+        }
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_usize(self.rom_bank);
+        writer.write_usize(self.ram_bank);
+        writer.write_byte(self.mode);
+        writer.write_bytes(&self.external_ram);
+        writer.write_word(self.rtc_minutes);
+        writer.write_word(self.rtc_days);
+        writer.write_usize(self.rtc_cycles);
+        writer.write_bytes(&self.rtc_alarm);
+        writer.write_usize(self.rtc_access_index);
+        writer.write_byte(self.rtc_command);
+        writer.write_byte(self.rtc_response);
+        writer.write_bool(self.ir_led);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> io::Result<()> {
+        self.rom_bank = reader.read_usize()?;
+        self.ram_bank = reader.read_usize()?;
+        self.mode = reader.read_byte()?;
+        reader.read_bytes_into(&mut self.external_ram)?;
+        self.rtc_minutes = reader.read_word()?;
+        self.rtc_days = reader.read_word()?;
+        self.rtc_cycles = reader.read_usize()?;
+        reader.read_bytes_into(&mut self.rtc_alarm)?;
+        self.rtc_access_index = reader.read_usize()? & 0xFF;
+        self.rtc_command = reader.read_byte()? & 0x07;
+        self.rtc_response = reader.read_byte()? & 0x0F;
+        self.ir_led = reader.read_bool()?;
+        Ok(())
+    }
+
+    fn debug(&self) -> String {
+        format!(
+            "MBC Type: {:?}\nROM BANK: {}\nMode: 0x{:X}\nRTC: Day {} {:02}:{:02}\nTones: {}",
+            self.get_mbc_type(), self.rom_bank, self.mode, self.rtc_days,
+            self.rtc_minutes / 60, self.rtc_minutes % 60, self.tone_count
+        )
+    }
+}
+
+pub struct Mmm01 {
+    /**
+    * MMM01 multi game cartridges. These power on in an unmapped mode, with the last 32 KiB of
+    * the ROM (holding the menu and the cartridge header) mapped to 0x0000 - 0x7FFF. The menu then
+    * picks the game by setting the outer bank bits and masks, and locks the mapping by setting
+    * bit 6 of 0x0000 - 0x1FFF. From then on it behaves much like an MBC1 confined to the game's
+    * slice of the ROM, with the masked bank bits fixed to what the menu chose.
+    *
+    * While unlocked, the registers hold more than they do afterwards:
+    *    0000 - 1FFF    Bits 0 - 3 RAM enable (0xA), bits 4 - 5 RAM bank mask, bit 6 lock
+    *    2000 - 3FFF    Bits 0 - 4 ROM bank, bits 5 - 6 ROM bank bits 5 - 6
+    *    4000 - 5FFF    Bits 0 - 1 RAM bank, bits 2 - 3 RAM bank bits 2 - 3, bits 4 - 5 ROM bank bits 7 - 8
+    *    6000 - 7FFF    Bit 0 banking mode, bits 2 - 5 ROM bank mask (for ROM bank bits 1 - 4)
+    * Once locked only the unmasked ROM and RAM bank bits, RAM enable and banking mode can change
+    **/
+
+    memory: Vec<Byte>,
+    rom_bank: usize,
+    rom_bank_mask: usize,
+    ram_bank: usize,
+    ram_bank_mask: usize,
+    external_ram: Vec<Byte>,
+    enable_ram: bool,
+    number_of_rom_banks: usize,
+    banking_mode: BankingMode,
+    locked: bool,
+}
+
+impl Mmm01 {
+
+    pub fn new(rom: &Rom) -> Mmm01 {
+        let (memory, number_of_rom_banks) = load_rom_banks(rom);
+
+        Mmm01 {
+            memory: memory,
+            rom_bank: 0,
+            rom_bank_mask: 0,
+            ram_bank: 0,
+            ram_bank_mask: 0,
+            external_ram: create_external_ram(rom),
+            enable_ram: false,
+            number_of_rom_banks: number_of_rom_banks,
+            banking_mode: BankingMode::ROM,
+            locked: false,
+        }
+    }
+
+    fn get_lower_rom_bank(&self) -> usize {
+        // Unmapped, this is the second to last bank. Once locked it is the first bank of the
+        // game - the outer bits and masked bits the menu chose, with everything else 0
+        if !self.locked {
+            return self.number_of_rom_banks - 2;
+        }
+
+        (self.rom_bank & !0x1F) | (self.rom_bank & self.rom_bank_mask)
+    }
+
+    fn get_upper_rom_bank(&self) -> usize {
+        if !self.locked {
+            return self.number_of_rom_banks - 1;
+        }
+
+        // Like MBC1, the game can't select its own bank 0 here and gets bank 1 instead
+        let mut rom_bank = self.rom_bank;
+        if rom_bank & 0x1F & !self.rom_bank_mask == 0 {
+            rom_bank |= 0x01;
+        }
+        rom_bank
+    }
+
+    fn get_ram_bank(&self) -> usize {
+        match (self.locked, &self.banking_mode) {
+            (true, BankingMode::ROM) => self.ram_bank & (0x0C | self.ram_bank_mask),
+            _ => self.ram_bank,
+        }
+    }
+}
+
+impl Mbc for Mmm01 {
+    fn get_mbc_type(&self) -> MbcType {
+        MbcType::MMM01
+    }
+
+    fn read_fixed_rom(&self, addr: Word) -> Byte {
+        let resolved_addr = (addr as usize) + ((self.get_lower_rom_bank() % self.number_of_rom_banks) * ROM_BANK_SIZE);
+        self.memory[resolved_addr]
+    }
+
+    fn read_rom(&self, addr: Word) -> Byte {
+        let resolved_addr = (addr as usize) + ((self.get_upper_rom_bank() % self.number_of_rom_banks) * ROM_BANK_SIZE);
+        self.memory[resolved_addr]
+    }
+
+    fn read_ram(&self, addr: Word) -> Byte {
+        if !self.enable_ram {
+            return 0xFF;
+        }
+
+        match resolve_ram_addr(&self.external_ram, self.get_ram_bank(), addr) {
+            Some(resolved_addr) => self.external_ram[resolved_addr],
+            None => 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, addr: Word, data: Byte) {
+        if !self.enable_ram {
+            return;
+        }
+
+        if let Some(resolved_addr) = resolve_ram_addr(&self.external_ram, self.get_ram_bank(), addr) {
+            self.external_ram[resolved_addr] = data;
+        }
+    }
+
+    fn handle_banking(&mut self, addr: Word, data: Byte) {
+        let data = data as usize;
+        match (addr, self.locked) {
+            (0x0000..=0x1FFF, locked) => {
+                self.enable_ram = (data & 0x0F) == 0x0A;
+                if !locked {
+                    self.ram_bank_mask = (data >> 4) & 0x03;
+                    self.locked = is_bit_set(&(data as Byte), 6);
+                }
+            },
+            (0x2000..=0x3FFF, false) => {
+                self.rom_bank = (self.rom_bank & !0x7F) | (data & 0x7F);
+            },
+            (0x2000..=0x3FFF, true) => {
+                // Only the unmasked lower 5 bits can change
+                let writable_bits = 0x1F & !self.rom_bank_mask;
+                self.rom_bank = (self.rom_bank & !writable_bits) | (data & writable_bits);
+            },
+            (0x4000..=0x5FFF, false) => {
+                self.ram_bank = data & 0x0F;
+                self.rom_bank = (self.rom_bank & 0x7F) | (((data >> 4) & 0x03) << 7);
+            },
+            (0x4000..=0x5FFF, true) => {
+                let writable_bits = 0x03 & !self.ram_bank_mask;
+                self.ram_bank = (self.ram_bank & !writable_bits) | (data & writable_bits);
+            },
+            (0x6000..=0x7FFF, locked) => {
+                self.banking_mode = match is_bit_set(&(data as Byte), 0) {
+                    true => BankingMode::RAM,
+                    false => BankingMode::ROM,
+                };
+                if !locked {
+                    self.rom_bank_mask = ((data >> 2) & 0x0F) << 1;
+                }
+            },
+            // The MMU only passes on writes to 0x0000 - 0x7FFF, which are all covered above
+            _ => (),
+        };
+    }
+
+    fn get_external_ram(&self) -> &[Byte] {
+        &self.external_ram
+    }
+
+    fn load_external_ram(&mut self, buffer: Vec<Byte>) -> Vec<String> {
+        load_into_external_ram(&mut self.external_ram, buffer);
+        Vec::new()
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_usize(self.rom_bank);
+        writer.write_usize(self.rom_bank_mask);
+        writer.write_usize(self.ram_bank);
+        writer.write_usize(self.ram_bank_mask);
+        writer.write_bool(self.enable_ram);
+        writer.write_bool(matches!(self.banking_mode, BankingMode::RAM));
+        writer.write_bool(self.locked);
+        writer.write_bytes(&self.external_ram);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> io::Result<()> {
+        self.rom_bank = reader.read_usize()? & 0x1FF;
+        self.rom_bank_mask = reader.read_usize()? & 0x1E;
+        self.ram_bank = reader.read_usize()? & 0x0F;
+        self.ram_bank_mask = reader.read_usize()? & 0x03;
+        self.enable_ram = reader.read_bool()?;
+        self.banking_mode = match reader.read_bool()? {
+            true => BankingMode::RAM,
+            false => BankingMode::ROM,
+        };
+        self.locked = reader.read_bool()?;
+        reader.read_bytes_into(&mut self.external_ram)
+    }
+
+    fn debug(&self) -> String {
+        format!(
+            "MBC Type: {:?}\nROM BANK: {}\nLocked: {}",
+            self.get_mbc_type(), self.get_upper_rom_bank(), self.locked
+        )
+    }
+}
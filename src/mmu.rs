@@ -1,8 +1,17 @@
 use std::cmp;
+use std::io;
 
+use crate::apu::*;
+use crate::camera::*;
+use crate::compat_palettes::*;
+use crate::error::*;
+use crate::header::*;
 use crate::joypad::*;
 use crate::mbc::*;
+use crate::model::*;
 use crate::rom::*;
+use crate::save_formats::*;
+use crate::state::*;
 use crate::utils::*;
 
 #[derive(Debug)]
@@ -30,7 +39,23 @@ pub struct Mmu {
     timer_frequency_changed: bool,
     rom: Rom,
     joypad: Joypad,
-    mbc: Option<Box<dyn Mbc>>,
+    apu: Apu,
+    mbc: Box<dyn Mbc>,
+
+    // The Game Boy we are emulating, which decides the power on state and whether CGB features
+    // are available
+    model: Model,
+
+    // Whether CGB features are enabled. A CGB runs DMG games with them switched off, only
+    // keeping the colour palettes (see compat_palettes)
+    cgb_mode: bool,
+
+    // Set whenever the game writes to cartridge RAM, so the save file can be kept up to date
+    external_ram_written: bool,
+
+    // Optional boot ROM, overlaid on the cartridge until the game writes to 0xFF50
+    boot_rom: Option<Vec<Byte>>,
+    boot_rom_mapped: bool,
 
     // CGB Specifics
     // There are 2 VRAM banks, each of size 0x2000
@@ -42,12 +67,24 @@ pub struct Mmu {
 
     // Although there are 8 banks, we will use 0xC000 - 0xCFFF in memory as bank 0 
     cgb_wram: [Byte; 0x1000 * 7], 
+
+    // VRAM DMA (HDMA). The destination is an offset into the current VRAM bank, and the CPU is
+    // held for stall cycles after each block is copied
+    vram_dma_source: Word,
+    vram_dma_destination: Word,
+    vram_dma_blocks_remaining: usize,
+    vram_dma_hblank_active: bool,
+    vram_dma_stall_cycles: usize,
 }
 
 impl Mmu {
 
-    pub fn new(rom: Rom, joypad: Joypad) -> Mmu {
-        Mmu {
+    pub fn new(rom: Rom, joypad: Joypad) -> Result<Mmu, RustyBoyError> {
+        let mbc = get_mbc(&rom)?;
+        let model = Model::get_default(rom.get_header());
+        let cgb_mode = model.is_cgb() && rom.is_cgb();
+
+        Ok(Mmu {
             memory: [0; MEMORY_SIZE],
             oam_access: true,
             color_pallette_access: true,
@@ -55,64 +92,166 @@ impl Mmu {
             timer_frequency_changed: false,
             rom: rom,
             joypad: joypad,
-            mbc: None,
+            apu: Apu::new(),
+            mbc: mbc,
+            model: model,
+            cgb_mode: cgb_mode,
+            external_ram_written: false,
+            boot_rom: None,
+            boot_rom_mapped: false,
             cgb_vram: [0; 0x2000 * 2],
             cgb_vram_bank: 0,
             cgb_background_palettes: [0; 64],
             cgb_object_palettes: [0; 64],
             cgb_wram_bank: 1,
             cgb_wram: [0; 0x1000 * 7],
-        }
+            vram_dma_source: 0,
+            vram_dma_destination: 0,
+            vram_dma_blocks_remaining: 0,
+            vram_dma_hblank_active: false,
+            vram_dma_stall_cycles: 0,
+        })
     }
 
     pub fn debug(&self) -> String {
-        match &self.mbc {
-            Some(mbc) => mbc.debug(),
-            None => format!("MBC Type: None"),
-        }
+        self.mbc.debug()
     }
 
     pub fn get_external_ram(&self) -> &[Byte] {
-        match &self.mbc {
-            Some(mbc) => mbc.get_external_ram() ,
-            None => &self.memory[0xA000..0xC000]
-        }
+        self.mbc.get_external_ram()
     }
 
-    pub fn load_external_ram(&mut self, buffer: Vec<Byte>) {
-        match &mut self.mbc {
-            Some(mbc) => mbc.load_external_ram(buffer),
-            None => {
-                let ram_len = 0xC000 - 0xA000;
-                for i in 0..cmp::min(ram_len, buffer.len()) {
-                    self.memory[0xA000 + i] = buffer[i];
-                }
-            }
+    pub fn get_save_data(&self) -> Vec<Byte> {
+        self.mbc.get_save_data()
+    }
+
+    pub fn take_external_ram_written(&mut self) -> bool {
+        std::mem::replace(&mut self.external_ram_written, false)
+    }
+
+    pub fn load_external_ram(&mut self, buffer: Vec<Byte>) -> Vec<String> {
+        // Saves from other emulators are converted first, see save_formats::import_save
+        let has_rtc = self.rom.get_header().cartridge_type.has_rtc;
+        let ram_size = self.mbc.get_external_ram().len();
+        let (buffer, mut warnings) = import_save(buffer, self.mbc.get_mbc_type(), ram_size, has_rtc);
+        warnings.extend(self.mbc.load_external_ram(buffer));
+        warnings
+    }
+
+    pub fn export_save(&self, format: SaveFormat) -> (Vec<Byte>, Vec<String>) {
+        export_save(self.get_save_data(), self.mbc.get_mbc_type(), self.mbc.get_external_ram().len(), format)
+    }
+
+    pub fn get_rom_file_name(&self) -> Option<&str> {
+        self.rom.get_file_name()
+    }
+
+    pub fn get_cartridge_header(&self) -> &CartridgeHeader {
+        self.rom.get_header()
+    }
+
+    pub fn get_rom_problems(&self) -> Vec<String> {
+        self.rom.get_problems()
+    }
+
+    pub fn debug_rom_header(&self) -> String {
+        self.rom.debug_header()
+    }
+
+    pub fn get_rom_checksums(&self) -> (Byte, Word) {
+        (self.rom.get_header_checksum(), self.rom.get_global_checksum())
+    }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.memory);
+        writer.write_bool(self.oam_access);
+        writer.write_bool(self.color_pallette_access);
+        writer.write_bool(self.vram_access);
+        writer.write_bool(self.timer_frequency_changed);
+        writer.write_bool(self.boot_rom_mapped);
+        writer.write_bool(self.cgb_mode);
+        writer.write_bytes(&self.cgb_vram);
+        writer.write_usize(self.cgb_vram_bank);
+        writer.write_bytes(&self.cgb_background_palettes);
+        writer.write_bytes(&self.cgb_object_palettes);
+        writer.write_usize(self.cgb_wram_bank);
+        writer.write_bytes(&self.cgb_wram);
+        writer.write_word(self.vram_dma_source);
+        writer.write_word(self.vram_dma_destination);
+        writer.write_usize(self.vram_dma_blocks_remaining);
+        writer.write_bool(self.vram_dma_hblank_active);
+        writer.write_usize(self.vram_dma_stall_cycles);
+
+        self.apu.save_state(writer);
+
+        self.mbc.save_state(writer);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> io::Result<()> {
+        reader.read_bytes_into(&mut self.memory)?;
+        self.oam_access = reader.read_bool()?;
+        self.color_pallette_access = reader.read_bool()?;
+        self.vram_access = reader.read_bool()?;
+        self.timer_frequency_changed = reader.read_bool()?;
+        self.boot_rom_mapped = reader.read_bool()? && self.boot_rom.is_some();
+        self.cgb_mode = reader.read_bool()? && self.model.is_cgb();
+        reader.read_bytes_into(&mut self.cgb_vram)?;
+        self.cgb_vram_bank = reader.read_usize()? & 0x1;
+        reader.read_bytes_into(&mut self.cgb_background_palettes)?;
+        reader.read_bytes_into(&mut self.cgb_object_palettes)?;
+        self.cgb_wram_bank = cmp::max(reader.read_usize()? & 0x7, 1);
+        reader.read_bytes_into(&mut self.cgb_wram)?;
+        self.vram_dma_source = reader.read_word()? & 0xFFF0;
+        self.vram_dma_destination = reader.read_word()? & 0x1FF0;
+        self.vram_dma_blocks_remaining = reader.read_usize()?;
+        self.vram_dma_hblank_active = reader.read_bool()?;
+        self.vram_dma_stall_cycles = reader.read_usize()?;
+
+        self.apu.load_state(reader)?;
+
+        // Cartridge RAM has most likely changed, so it needs saving
+        self.external_ram_written = true;
+        self.mbc.load_state(reader)
+    }
+
+    pub fn set_boot_rom(&mut self, boot_rom: Vec<Byte>) -> Result<(), RustyBoyError> {
+        // Takes effect from the next reset
+        check_boot_rom_size(self.model, boot_rom.len())?;
+        self.boot_rom = Some(boot_rom);
+        Ok(())
+    }
+
+    pub fn get_model(&self) -> Model {
+        self.model
+    }
+
+    pub fn set_model(&mut self, model: Model) -> Result<(), RustyBoyError> {
+        // Takes effect from the next reset. A boot ROM only runs on the model it was made for
+        if let Some(boot_rom) = self.boot_rom.as_ref() {
+            check_boot_rom_size(model, boot_rom.len())?;
         }
+
+        self.model = model;
+        Ok(())
+    }
+
+    pub fn is_boot_rom_mapped(&self) -> bool {
+        self.boot_rom_mapped
     }
 
     pub fn reset(&mut self) {
+        // With a boot ROM we start from power on instead, and leave it to set everything up
+        // The CGB boot ROM always starts in CGB mode, and switches to DMG mode itself if needed
+        self.boot_rom_mapped = self.boot_rom.is_some();
+        self.cgb_mode = self.model.is_cgb() && (self.boot_rom_mapped || self.rom.is_cgb());
+        if self.boot_rom_mapped {
+            self.reset_to_power_on();
+            return;
+        }
+
         // Initial MMU state
         self.memory[0xFF05] = 0x00;
         self.memory[0xFF06] = 0x00;
-        self.memory[0xFF07] = 0x00;
-        self.memory[0xFF10] = 0x80;
-        self.memory[0xFF11] = 0xBF;
-        self.memory[0xFF12] = 0xF3;
-        self.memory[0xFF14] = 0xBF;
-        self.memory[0xFF16] = 0x3F;
-        self.memory[0xFF17] = 0x00;
-        self.memory[0xFF19] = 0xBF;
-        self.memory[0xFF1A] = 0x7F;
-        self.memory[0xFF1B] = 0xFF;
-        self.memory[0xFF1E] = 0xBF;
-        self.memory[0xFF20] = 0xFF;
-        self.memory[0xFF21] = 0x00;
-        self.memory[0xFF22] = 0x00;
-        self.memory[0xFF23] = 0xBF;
-        self.memory[0xFF24] = 0x77;
-        self.memory[0xFF25] = 0xF3;
-        self.memory[0xFF26] = 0xF1;
         self.memory[0xFF40] = 0x91;
         self.memory[0xFF42] = 0x00;
         self.memory[0xFF43] = 0x00;
@@ -124,13 +263,37 @@ impl Mmu {
         self.memory[0xFF4B] = 0x00;
         self.memory[0xFFFF] = 0x00;
 
+        // Audio registers (0xFF10 - 0xFF26) are owned by the APU
+        self.apu.reset();
+
         // This iniital state of the joypad is all unpressed
         self.memory[JOYPAD_REGISTER_ADDR as usize] = 0xFF;
 
+        // What differs between models, including how far the boot ROM got through DIV's count
+        for (addr, data) in self.model.get_initial_io_registers() {
+            self.memory[addr as usize] = data;
+        }
+        self.memory[DIVIDER_REGISTER_ADDR as usize] = (self.model.get_initial_divider(self.rom.get_header()) >> 8) as Byte;
+
+        // Without a boot ROM to do it, pick the colours for a DMG game on a CGB here. Buttons held
+        // at reset override them just like holding them during the boot animation
+        if self.is_dmg_compat() {
+            let palettes = self.get_held_button_palettes()
+                .unwrap_or_else(|| get_title_palettes(self.rom.get_header(), self.get_title_checksum()));
+            self.load_compat_palettes(&palettes);
+        }
+
         // TEMP
         // self.memory[0xFF44] = 0x90;
+    }
 
-        self.load_rom();
+    fn reset_to_power_on(&mut self) {
+        // IO registers are mostly clear at power on, with the LCD and the APU switched off
+        for addr in 0xFF00..=0xFFFF {
+            self.memory[addr] = 0x00;
+        }
+        self.apu.write_register(NR52_ADDR, 0x00);
+        self.memory[JOYPAD_REGISTER_ADDR as usize] = 0xFF;
     }
 
     pub fn read_byte(&self, addr: Word) -> Byte {
@@ -140,6 +303,10 @@ impl Mmu {
         if is_reading_restricted_oam || is_reading_restricted_vram {
             // Reading something currently restricted, return garbage (0xFF)
             0xFF
+        } else if addr < 0x4000 {
+            // Usually bank 0, but some MBCs can map other banks here too
+            self.read_fixed_rom_bank(addr)
+
         } else if addr >= 0x4000 && addr < 0x8000 {
             // First ROM bank will always be mapped into memory, but anything in this range might
             // use a different bank, so let's find the appropriate bank to read from
@@ -158,6 +325,9 @@ impl Mmu {
             // This should work for DMG as well as CGB because in DMG, the bank number will never change
             self.cgb_wram[((addr - 0xD000) as usize) + (0x1000 * (self.cgb_wram_bank - 1))]
 
+        } else if addr >= AUDIO_REGISTERS_START_ADDR && addr <= AUDIO_REGISTERS_END_ADDR {
+            self.apu.read_register(addr)
+
         } else {
             self.memory[addr as usize]
         }
@@ -180,26 +350,205 @@ impl Mmu {
                 },
                 0xFEA0..=0xFEFF => (),
                 JOYPAD_REGISTER_ADDR => self.handle_joypad(addr, data),
+                AUDIO_REGISTERS_START_ADDR..=AUDIO_REGISTERS_END_ADDR => self.apu.write_register(addr, data),
                 DIVIDER_REGISTER_ADDR | CURRENT_SCANLINE_ADDR => self.memory[addr as usize] = 0,
-                0xFF46 => self.do_dma_transfer(data),
+                DMA_ADDR => self.do_dma_transfer(data),
                 0xFF4F => self.do_vram_bank_switch(addr, data),
                 TIMER_CONTROL_ADDR => self.do_timer_control_update(data),
-                VRAM_DMA_TRANSFER_ADDR => {
-                    if self.is_cgb() {
-                        println!("DMA TRANSFER FOR VRAM");
+                CGB_MODE_SELECT_ADDR => self.do_cgb_mode_select(addr, data),
+                SPEED_SWITCH_ADDR => self.do_speed_switch_prepare(addr, data),
+                BOOT_ROM_DISABLE_ADDR => {
+                    // Once unmapped, the boot ROM can't be mapped back in
+                    if data != 0 {
+                        self.boot_rom_mapped = false;
                     }
-                    self.memory[addr as usize] = data;
+                    self.memory[addr as usize] = 0xFF;
                 },
+                VRAM_DMA_START_ADDR..=VRAM_DMA_DESTINATION_LOW_ADDR => self.do_vram_dma_address_update(addr, data),
+                VRAM_DMA_TRANSFER_ADDR => self.do_vram_dma_transfer(data),
                 WRAM_BANK_SELECT_ADDR => self.do_wram_bank_switch(addr, data),
-                BACKGROUND_PALETTE_DATA_ADDR => self.handle_cgb_palette_write(addr, data),
-                OBJECT_PALETTE_DATA_ADDR => self.handle_cgb_palette_write(addr, data),
+                BACKGROUND_PALETTE_DATA_ADDR => self.handle_cgb_palette_write(false, data),
+                OBJECT_PALETTE_DATA_ADDR => self.handle_cgb_palette_write(true, data),
                 _ => self.memory[addr as usize] = data
             };
         }
     }
 
     pub fn is_cgb(&self) -> bool {
-        self.rom.is_cgb()
+        // CGB only games check for a CGB themselves, and show their own message when there isn't one
+        self.cgb_mode
+    }
+
+    pub fn is_dmg_compat(&self) -> bool {
+        // A DMG game on a CGB, drawn using the CGB palettes
+        self.model.is_cgb() && !self.cgb_mode
+    }
+
+    pub fn get_title_checksum(&self) -> Byte {
+        // Sum of the title bytes (0x0134 - 0x0143), used by the CGB boot ROM to recognise games
+        (CARTRIDGE_TITLE_ADDR..=CGB_FLAG_ADDR)
+            .fold(0, |checksum: Byte, addr| checksum.wrapping_add(self.read_fixed_rom_bank(addr as Word)))
+    }
+
+    pub fn set_compat_palettes(&mut self, direction: usize, button: Option<usize>) -> bool {
+        // Pick the colours for a DMG game on a CGB by button combination, returning false if
+        // we aren't in compatibility mode or it isn't a combination the boot ROM knows
+        if !self.is_dmg_compat() {
+            return false;
+        }
+
+        match get_button_palettes(direction, button) {
+            Some(palettes) => {
+                self.load_compat_palettes(&palettes);
+                true
+            },
+            None => false,
+        }
+    }
+
+    fn get_held_button_palettes(&self) -> Option<CompatPalettes> {
+        let is_held = |button: usize| self.joypad.get_button_state(button) == 0;
+        let direction = [UP_BUTTON, LEFT_BUTTON, DOWN_BUTTON, RIGHT_BUTTON].into_iter().find(|button| is_held(*button))?;
+        let button = [A_BUTTON, B_BUTTON].into_iter().find(|button| is_held(*button));
+        get_button_palettes(direction, button)
+    }
+
+    fn load_compat_palettes(&mut self, palettes: &CompatPalettes) {
+        // Background palette 0 is used with BGP, object palettes 0 and 1 with OBP0 and OBP1
+        self.cgb_background_palettes[0..8].copy_from_slice(&get_palette_data(&palettes.background));
+        self.cgb_object_palettes[0..8].copy_from_slice(&get_palette_data(&palettes.object_0));
+        self.cgb_object_palettes[8..16].copy_from_slice(&get_palette_data(&palettes.object_1));
+    }
+
+    pub fn is_double_speed(&self) -> bool {
+        // Bit 7 of KEY1 is the current speed
+        self.is_cgb() && is_bit_set(&self.memory[SPEED_SWITCH_ADDR as usize], 7)
+    }
+
+    pub fn switch_speed(&mut self) -> bool {
+        // Called on STOP, which switches speed if bit 0 of KEY1 was set to prepare for it
+        let key1 = self.memory[SPEED_SWITCH_ADDR as usize];
+        if !self.is_cgb() || !is_bit_set(&key1, 0) {
+            return false;
+        }
+
+        self.memory[SPEED_SWITCH_ADDR as usize] = (!key1 & 0x80) | 0x7E;
+        true
+    }
+
+    pub fn is_joypad_line_low(&mut self) -> bool {
+        // Whether a held button is pulling down one of the joypad lines currently selected, which
+        // is what wakes the CPU from STOP
+        let select = self.memory[JOYPAD_REGISTER_ADDR as usize];
+        let is_action_low = !is_bit_set(&select, 5) && self.joypad.get_buttons_for_mode(JoypadMode::ACTION) != 0xF;
+        let is_direction_low = !is_bit_set(&select, 4) && self.joypad.get_buttons_for_mode(JoypadMode::DIRECTION) != 0xF;
+        is_action_low || is_direction_low
+    }
+
+    pub fn do_hblank_vram_dma(&mut self) {
+        // Called by the PPU as each visible line enters H-Blank
+        if !self.vram_dma_hblank_active {
+            return;
+        }
+
+        self.copy_vram_dma_block();
+        self.vram_dma_hblank_active = self.vram_dma_blocks_remaining > 0;
+        self.memory[VRAM_DMA_TRANSFER_ADDR as usize] = match self.vram_dma_hblank_active {
+            true => (self.vram_dma_blocks_remaining - 1) as Byte,
+            false => 0xFF,
+        };
+    }
+
+    pub fn stall_for_vram_dma(&mut self, cycles: usize) -> bool {
+        // The CPU can't run while a VRAM DMA transfer is copying, returns true if it has to wait
+        if self.vram_dma_stall_cycles == 0 {
+            return false;
+        }
+
+        self.vram_dma_stall_cycles = self.vram_dma_stall_cycles.saturating_sub(cycles);
+        true
+    }
+
+    fn do_vram_dma_address_update(&mut self, addr: Word, data: Byte) {
+        // The lower 4 bits are ignored, and the destination is always in VRAM (0x8000 - 0x9FF0).
+        // These registers can't be read back
+        match addr {
+            VRAM_DMA_START_ADDR => self.vram_dma_source = ((data as Word) << 8) | (self.vram_dma_source & 0x00FF),
+            VRAM_DMA_SOURCE_LOW_ADDR => self.vram_dma_source = (self.vram_dma_source & 0xFF00) | (data & 0xF0) as Word,
+            VRAM_DMA_DESTINATION_HIGH_ADDR => self.vram_dma_destination = (((data & 0x1F) as Word) << 8) | (self.vram_dma_destination & 0x00FF),
+            _ => self.vram_dma_destination = (self.vram_dma_destination & 0x1F00) | (data & 0xF0) as Word,
+        };
+        self.memory[addr as usize] = 0xFF;
+    }
+
+    fn do_vram_dma_transfer(&mut self, data: Byte) {
+        if !self.is_cgb() {
+            self.memory[VRAM_DMA_TRANSFER_ADDR as usize] = data;
+            return;
+        }
+
+        // Clearing bit 7 while an H-Blank transfer is running cancels it, leaving how much was
+        // left to copy readable with bit 7 set
+        if self.vram_dma_hblank_active && !is_bit_set(&data, 7) {
+            self.vram_dma_hblank_active = false;
+            self.memory[VRAM_DMA_TRANSFER_ADDR as usize] = 0x80 | (self.vram_dma_blocks_remaining - 1) as Byte;
+            return;
+        }
+
+        self.vram_dma_blocks_remaining = (data & 0x7F) as usize + 1;
+        if is_bit_set(&data, 7) {
+            // Bit 7 reads back clear while the transfer is running
+            self.vram_dma_hblank_active = true;
+            self.memory[VRAM_DMA_TRANSFER_ADDR as usize] = data & 0x7F;
+        } else {
+            // General purpose DMA copies everything now, the CPU then waits for it to finish
+            while self.vram_dma_blocks_remaining > 0 {
+                self.copy_vram_dma_block();
+            }
+            self.memory[VRAM_DMA_TRANSFER_ADDR as usize] = 0xFF;
+        }
+    }
+
+    fn copy_vram_dma_block(&mut self) {
+        let bank_start = 0x2000 * self.cgb_vram_bank;
+        for i in 0..VRAM_DMA_BLOCK_SIZE {
+            let data = self.read_byte(self.vram_dma_source.wrapping_add(i as Word));
+            let destination = (self.vram_dma_destination as usize + i) & 0x1FFF;
+            self.cgb_vram[bank_start + destination] = data;
+        }
+
+        self.vram_dma_source = self.vram_dma_source.wrapping_add(VRAM_DMA_BLOCK_SIZE as Word);
+        self.vram_dma_destination = (self.vram_dma_destination + VRAM_DMA_BLOCK_SIZE as Word) & 0x1FF0;
+        self.vram_dma_blocks_remaining -= 1;
+
+        // Each block takes the same time whatever the CPU speed, so twice the CPU cycles in double speed
+        self.vram_dma_stall_cycles += match self.is_double_speed() {
+            true => VRAM_DMA_CYCLES_PER_BLOCK * 2,
+            false => VRAM_DMA_CYCLES_PER_BLOCK,
+        };
+    }
+
+    fn do_speed_switch_prepare(&mut self, addr: Word, data: Byte) {
+        // Only bit 0 can be written, the current speed in bit 7 changes on STOP
+        if self.is_cgb() {
+            self.memory[addr as usize] = (self.memory[addr as usize] & 0x80) | 0x7E | (data & 0x01);
+        }
+    }
+
+    fn do_cgb_mode_select(&mut self, addr: Word, data: Byte) {
+        // Only the boot ROM can switch modes, after that KEY0 is locked
+        if !self.boot_rom_mapped || !self.model.is_cgb() {
+            return;
+        }
+
+        if is_bit_set(&data, 2) && self.cgb_mode {
+            // Whatever the boot ROM drew (i.e. the logo) stays in VRAM, which DMG mode reads
+            // from memory rather than the banks
+            self.cgb_mode = false;
+            self.memory[0x8000..0xA000].copy_from_slice(&self.cgb_vram[0..0x2000]);
+        }
+
+        self.memory[addr as usize] = data;
     }
 
     pub fn update_timer_frequency_changed(&mut self, val: bool) {
@@ -250,6 +599,38 @@ impl Mmu {
         self.memory[DIVIDER_REGISTER_ADDR as usize] = self.memory[DIVIDER_REGISTER_ADDR as usize].wrapping_add(1);
     }
 
+    pub fn take_rumble_strength(&mut self) -> f32 {
+        self.mbc.take_rumble_strength()
+    }
+
+    pub fn set_camera_sensor(&mut self, sensor: CameraSensor) {
+        self.mbc.set_camera_sensor(sensor);
+    }
+
+    pub fn set_tilt(&mut self, x: f32, y: f32) {
+        self.mbc.set_tilt(x, y);
+    }
+
+    pub fn update_mbc(&mut self, cycles: u8) {
+        self.mbc.update(cycles);
+    }
+
+    pub fn update_apu(&mut self, cycles: u8) {
+        self.apu.update(cycles);
+    }
+
+    pub fn take_audio_samples(&mut self) -> Vec<f32> {
+        self.apu.take_samples()
+    }
+
+    pub fn set_audio_sample_rate(&mut self, sample_rate: usize) {
+        self.apu.set_sample_rate(sample_rate);
+    }
+
+    pub fn debug_apu(&self) -> String {
+        self.apu.debug()
+    }
+
     pub fn set_button_state(&mut self, button: usize) {
         self.joypad.set_button_state(button);
     }
@@ -270,41 +651,32 @@ impl Mmu {
         &self.cgb_object_palettes
     }
 
-    fn load_rom(&mut self) {
-        let end_addr = 0x8000;
-        for i in 0..cmp::min(end_addr, self.rom.length()) {
-            self.memory[i] = self.rom.get_byte(i);
+    fn read_fixed_rom_bank(&self, addr: Word) -> Byte {
+        if let Some(boot_rom) = self.boot_rom.as_ref().filter(|_| self.boot_rom_mapped) {
+            let is_boot_rom_addr = addr < 0x100 || (addr >= 0x200 && (addr as usize) < boot_rom.len());
+            if is_boot_rom_addr {
+                return boot_rom[addr as usize];
+            }
         }
 
-        self.mbc = get_mbc(&self.rom)
+        self.mbc.read_fixed_rom(addr)
     }
 
     fn read_rom_bank(&self, addr: Word) -> Byte {
-        match &self.mbc {
-            Some(mbc) => mbc.read_rom(addr - 0x4000),
-            None => self.rom.get_byte(addr as usize),
-        }
+        self.mbc.read_rom(addr - 0x4000)
     }
 
     fn read_ram_bank(&self, addr: Word) -> Byte {
-        match &self.mbc {
-            Some(mbc) => mbc.read_ram(addr - 0xA000),
-            None => self.memory[addr as usize],
-        }
+        self.mbc.read_ram(addr - 0xA000)
     }
 
     fn write_ram_bank(&mut self, addr: Word, data: Byte) {
-        match &mut self.mbc {
-            Some(mbc) => mbc.write_ram(addr - 0xA000, data),
-            None => self.memory[addr as usize] = data,
-        }
+        self.mbc.write_ram(addr - 0xA000, data);
+        self.external_ram_written = true;
     }
 
     fn handle_banking(&mut self, addr: Word, data: Byte) {
-        match &mut self.mbc {
-            Some(mbc) => mbc.handle_banking(addr, data),
-            None => {},
-        }
+        self.mbc.handle_banking(addr, data);
     }
 
     fn handle_vram_write(&mut self, addr: Word, data: Byte) {
@@ -337,15 +709,16 @@ impl Mmu {
         self.memory[addr as usize] = data;
     }
 
-    fn handle_cgb_palette_write(&mut self, addr: Word, data: Byte) {
-        let palette_index_addr = match addr {
-            BACKGROUND_PALETTE_DATA_ADDR => BACKGROUND_PALETTE_INDEX_ADDR,
-            OBJECT_PALETTE_DATA_ADDR => OBJECT_PALETTE_INDEX_ADDR,
-            _ => panic!("Invalid address used for palette data. Did you call this function by mistake?")
+    fn handle_cgb_palette_write(&mut self, is_object_palette: bool, data: Byte) {
+        // Background and object palettes each have their own index and data registers
+        let palette_index_addr = match is_object_palette {
+            true => OBJECT_PALETTE_INDEX_ADDR,
+            false => BACKGROUND_PALETTE_INDEX_ADDR,
         };
 
-        // In CGB mode, we should handle a proper palette update, in DMG, just write the data to memory
-        match self.is_cgb() {
+        // In CGB mode, we should handle a proper palette update, in DMG, just write the data to memory.
+        // The CGB boot ROM can still set them up for DMG games, which are then stuck with them
+        match self.is_cgb() || (self.boot_rom_mapped && self.model.is_cgb()) {
             true => {
                 // We write the data through this register, using the index register to figure out 
                 // which CGB palette byte we should write to. We use the lower 6 bits to get an address
@@ -354,11 +727,10 @@ impl Mmu {
                 let auto_increment = is_bit_set(&palette_index, 7);
                 let mut palette_addr = palette_index & 0b111111;  // bottom 6 bits here for addr
 
-                if addr == BACKGROUND_PALETTE_DATA_ADDR {
-                    self.cgb_background_palettes[palette_addr as usize] = data;
-                } else if addr == OBJECT_PALETTE_DATA_ADDR {
-                    self.cgb_object_palettes[palette_addr as usize] = data;
-                }
+                match is_object_palette {
+                    true => self.cgb_object_palettes[palette_addr as usize] = data,
+                    false => self.cgb_background_palettes[palette_addr as usize] = data,
+                };
 
                 // If the auto increment bit is set, then increment the palette address stored in those lower
                 // 6 bits
@@ -411,4 +783,14 @@ impl Mmu {
         self.memory[TIMER_CONTROL_ADDR as usize] = data;
     }
 
-}
\ No newline at end of file
+}
+
+fn check_boot_rom_size(model: Model, size: usize) -> Result<(), RustyBoyError> {
+    let expected_size = model.get_boot_rom_size();
+    match size == expected_size {
+        true => Ok(()),
+        false => Err(RustyBoyError::InvalidBootRom(format!(
+            "Boot ROM is {} bytes, the {:?} boot ROM is {} bytes", size, model, expected_size
+        ))),
+    }
+}
@@ -0,0 +1,135 @@
+use crate::header::*;
+use crate::utils::*;
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Model {
+    /**
+    * The Game Boy being emulated. Games tell them apart by the registers the boot ROM leaves
+    * behind, mostly A (0x01 DMG / SGB, 0xFF MGB / SGB2, 0x11 CGB / AGB) and B on a GBA
+    **/
+
+    DMG0,
+    DMG,
+    MGB,
+    SGB,
+    SGB2,
+    CGB,
+    AGB,
+}
+
+impl Model {
+
+    pub fn from_name(name: &str) -> Option<Model> {
+        match name.to_ascii_uppercase().as_str() {
+            "DMG0" => Some(Model::DMG0),
+            "DMG" => Some(Model::DMG),
+            "MGB" => Some(Model::MGB),
+            "SGB" => Some(Model::SGB),
+            "SGB2" => Some(Model::SGB2),
+            "CGB" => Some(Model::CGB),
+            "AGB" => Some(Model::AGB),
+            _ => None,
+        }
+    }
+
+    pub fn get_default(header: &CartridgeHeader) -> Model {
+        // Run each game on the oldest Game Boy it is made for
+        match header.is_cgb() {
+            true => Model::CGB,
+            false => Model::DMG,
+        }
+    }
+
+    pub fn is_cgb(&self) -> bool {
+        // The GBA runs Game Boy games on the same hardware as the CGB
+        matches!(self, Model::CGB | Model::AGB)
+    }
+
+    pub fn get_boot_rom_size(&self) -> usize {
+        match self.is_cgb() {
+            true => CGB_BOOT_ROM_SIZE,
+            false => DMG_BOOT_ROM_SIZE,
+        }
+    }
+
+    pub fn get_initial_registers(&self, header: &CartridgeHeader, title_checksum: Byte) -> (Word, Word, Word, Word) {
+        // AF, BC, DE and HL as the boot ROM leaves them, from the Pan Docs power up sequence. Some
+        // depend on the cartridge as the boot ROM was part way through checking it
+        let header_flags = match header.header_checksum {
+            0 => 0x80,
+            _ => 0xB0,
+        };
+
+        match self {
+            Model::DMG0 => (0x0100, 0xFF13, 0x00C1, 0x8403),
+            Model::DMG => (0x0100 | header_flags, 0x0013, 0x00D8, 0x014D),
+            Model::MGB => (0xFF00 | header_flags, 0x0013, 0x00D8, 0x014D),
+            Model::SGB => (0x0100, 0x0014, 0x0000, 0xC060),
+            Model::SGB2 => (0xFF00, 0x0014, 0x0000, 0xC060),
+            Model::CGB => Model::get_initial_cgb_registers(header, title_checksum),
+            Model::AGB => {
+                // The GBA boot ROM does one extra INC B at the end, which is how games spot it
+                let (af, bc, de, hl) = Model::get_initial_cgb_registers(header, title_checksum);
+                let b = ((bc >> 8) as Byte).wrapping_add(1);
+                let mut f = (af as Byte) & 0x10;
+                if b == 0 {
+                    f |= 0x80;
+                }
+                if b & 0x0F == 0 {
+                    f |= 0x20;
+                }
+                ((af & 0xFF00) | f as Word, ((b as Word) << 8) | (bc & 0x00FF), de, hl)
+            },
+        }
+    }
+
+    fn get_initial_cgb_registers(header: &CartridgeHeader, title_checksum: Byte) -> (Word, Word, Word, Word) {
+        if header.is_cgb() {
+            return (0x1180, 0x0000, 0xFF56, 0x000D);
+        }
+
+        // For DMG games the boot ROM picks a colour palette from a checksum of the title, but
+        // only for games published by Nintendo. B is left holding the checksum, and HL where the
+        // palette lookup ended up
+        let is_nintendo = header.get_licensee_code() == "01";
+        let b = if is_nintendo { title_checksum } else { 0x00 };
+        let hl = match b {
+            0x43 | 0x58 => 0x991A,
+            _ => 0x007C,
+        };
+
+        (0x1180, (b as Word) << 8, 0x0008, hl)
+    }
+
+    pub fn get_initial_divider(&self, header: &CartridgeHeader) -> Word {
+        // The internal 16 bit counter behind DIV (which is the upper byte) when the boot ROM hands
+        // over. Each boot ROM takes a different amount of time, so the phase differs between
+        // models - the SGB ones vary with how long the SNES takes, so these are typical values
+        match self {
+            Model::DMG0 => 0x1830,
+            Model::DMG | Model::MGB => 0xABCC,
+            Model::SGB | Model::SGB2 => 0xD85C,
+            Model::CGB | Model::AGB if header.is_cgb() => 0x1EA0,
+            Model::CGB | Model::AGB => 0x267C,
+        }
+    }
+
+    pub fn get_initial_io_registers(&self) -> Vec<(Word, Byte)> {
+        // IO registers the boot ROM leaves with values that differ between models. The ones
+        // that only exist on the CGB read back as 0xFF on older models
+        let is_cgb = self.is_cgb();
+        vec![
+            (SERIAL_CONTROL_ADDR, if is_cgb { 0x7F } else { 0x7E }),
+            (TIMER_CONTROL_ADDR, 0xF8),
+            (INTERRUPT_FLAG_ADDR, 0xE1),
+            (DMA_ADDR, if is_cgb { 0x00 } else { 0xFF }),
+            (SPEED_SWITCH_ADDR, if is_cgb { 0x7E } else { 0xFF }),
+            (BOOT_ROM_DISABLE_ADDR, 0xFF),
+            (VRAM_DMA_START_ADDR, 0xFF),
+            (VRAM_DMA_SOURCE_LOW_ADDR, 0xFF),
+            (VRAM_DMA_DESTINATION_HIGH_ADDR, 0xFF),
+            (VRAM_DMA_DESTINATION_LOW_ADDR, 0xFF),
+            (VRAM_DMA_TRANSFER_ADDR, 0xFF),
+        ]
+    }
+}
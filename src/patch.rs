@@ -0,0 +1,530 @@
+use crate::error::*;
+use crate::utils::*;
+
+pub fn apply_patch(source: &[u8], patch: &[u8]) -> Result<Vec<u8>, RustyBoyError> {
+    // Returns a patched copy of the ROM, the source is never touched
+    if patch.starts_with(IPS_MAGIC) {
+        apply_ips(source, patch)
+    } else if patch.starts_with(UPS_MAGIC) {
+        apply_ups(source, patch)
+    } else if patch.starts_with(BPS_MAGIC) {
+        apply_bps(source, patch)
+    } else {
+        Err(RustyBoyError::InvalidPatch(String::from("Not an IPS, UPS or BPS patch")))
+    }
+}
+
+fn apply_ips(source: &[u8], patch: &[u8]) -> Result<Vec<u8>, RustyBoyError> {
+    // A list of records, each a 24 bit offset and 16 bit size followed by that many bytes to
+    // write. A size of 0 means a run - a 16 bit count and a single byte to repeat. Everything is
+    // big endian, and records can write past the end of the ROM to grow it. Some patches add a
+    // 24 bit size after the EOF marker to truncate the ROM to
+    let mut target = source.to_vec();
+    let mut reader = PatchReader::new(patch, IPS_MAGIC.len());
+
+    loop {
+        if reader.remaining().starts_with(IPS_EOF) {
+            reader.skip(IPS_EOF.len());
+            break;
+        }
+
+        let offset = reader.read_be(3)?;
+        let size = reader.read_be(2)?;
+        let (bytes, length) = match size {
+            0 => {
+                let length = reader.read_be(2)?;
+                (vec![reader.read_byte()?; length], length)
+            },
+            _ => (reader.read_slice(size)?.to_vec(), size),
+        };
+
+        check_target_size(offset + length)?;
+        if target.len() < offset + length {
+            target.resize(offset + length, 0);
+        }
+        target[offset..(offset + length)].copy_from_slice(&bytes);
+    }
+
+    if reader.remaining().len() >= 3 {
+        target.truncate(reader.read_be(3)?);
+    }
+
+    Ok(target)
+}
+
+fn apply_ups(source: &[u8], patch: &[u8]) -> Result<Vec<u8>, RustyBoyError> {
+    // The source and target sizes, then hunks of a relative offset followed by bytes to XOR with
+    // the source, ending at a 0 byte (which also moves on one byte)
+    let (expected_source_crc, expected_target_crc) = check_patch_footer(patch)?;
+    check_source_crc(source, expected_source_crc)?;
+
+    let mut reader = PatchReader::new(&patch[..(patch.len() - PATCH_FOOTER_SIZE)], UPS_MAGIC.len());
+    let source_size = reader.read_number()?;
+    let target_size = reader.read_number()?;
+    check_source_size(source, source_size)?;
+    check_target_size(target_size)?;
+
+    let mut target = source.to_vec();
+    target.resize(target_size, 0);
+
+    let mut position = 0;
+    while !reader.remaining().is_empty() {
+        position += reader.read_number()?;
+        loop {
+            let byte = reader.read_byte()?;
+            if byte == 0 {
+                position += 1;
+                break;
+            }
+
+            if position < target.len() {
+                target[position] ^= byte;
+            }
+            position += 1;
+        }
+    }
+
+    check_target_crc(&target, expected_target_crc)?;
+    Ok(target)
+}
+
+fn apply_bps(source: &[u8], patch: &[u8]) -> Result<Vec<u8>, RustyBoyError> {
+    // The source and target sizes, some metadata we don't need, then a list of actions that build
+    // the target from start to end. Each action is a number holding the type (bottom 2 bits) and
+    // length - 1:
+    //     0   Source Read - copy from the source at the same position
+    //     1   Target Read - copy from the patch
+    //     2   Source Copy - copy from anywhere in the source, relative to the last source copy
+    //     3   Target Copy - copy from earlier in the target, relative to the last target copy
+    let (expected_source_crc, expected_target_crc) = check_patch_footer(patch)?;
+    check_source_crc(source, expected_source_crc)?;
+
+    let mut reader = PatchReader::new(&patch[..(patch.len() - PATCH_FOOTER_SIZE)], BPS_MAGIC.len());
+    let source_size = reader.read_number()?;
+    let target_size = reader.read_number()?;
+    check_source_size(source, source_size)?;
+    check_target_size(target_size)?;
+    let metadata_size = reader.read_number()?;
+    reader.skip(metadata_size);
+
+    let invalid = || RustyBoyError::InvalidPatch(String::from("BPS action goes out of bounds"));
+    let mut target = Vec::with_capacity(target_size);
+    let mut source_offset: isize = 0;
+    let mut target_offset: isize = 0;
+    while !reader.remaining().is_empty() {
+        let action = reader.read_number()?;
+        let length = (action >> 2) + 1;
+        if target.len() + length > target_size {
+            return Err(RustyBoyError::InvalidPatch(format!(
+                "BPS action writes past the end of the {} byte ROM", target_size
+            )));
+        }
+
+        match action & 0x03 {
+            0 => {
+                let start = target.len();
+                target.extend_from_slice(source.get(start..(start + length)).ok_or_else(invalid)?);
+            },
+            1 => target.extend_from_slice(reader.read_slice(length)?),
+            2 => {
+                source_offset += reader.read_signed_number()?;
+                let start = usize::try_from(source_offset).map_err(|_| invalid())?;
+                target.extend_from_slice(source.get(start..(start + length)).ok_or_else(invalid)?);
+                source_offset += length as isize;
+            },
+            _ => {
+                // The copy can overlap what it is writing, so it has to go a byte at a time
+                target_offset += reader.read_signed_number()?;
+                for _ in 0..length {
+                    let start = usize::try_from(target_offset).map_err(|_| invalid())?;
+                    target.push(*target.get(start).ok_or_else(invalid)?);
+                    target_offset += 1;
+                }
+            },
+        };
+    }
+
+    if target.len() != target_size {
+        return Err(RustyBoyError::InvalidPatch(format!(
+            "Patched ROM is {} bytes, expected {}", target.len(), target_size
+        )));
+    }
+
+    check_target_crc(&target, expected_target_crc)?;
+    Ok(target)
+}
+
+fn check_patch_footer(patch: &[u8]) -> Result<(u32, u32), RustyBoyError> {
+    // Returns the expected source and target CRCs, once the patch has been checked against its own
+    if patch.len() < PATCH_FOOTER_SIZE + 4 {
+        return Err(RustyBoyError::InvalidPatch(String::from("Patch is truncated")));
+    }
+
+    let footer = &patch[(patch.len() - PATCH_FOOTER_SIZE)..];
+    let read_crc = |i: usize| u32::from_le_bytes(footer[(i * 4)..((i + 1) * 4)].try_into().unwrap());
+
+    let patch_crc = crc32fast::hash(&patch[..(patch.len() - 4)]);
+    if patch_crc != read_crc(2) {
+        return Err(RustyBoyError::InvalidPatch(String::from("Patch is corrupt, its checksum doesn't match")));
+    }
+
+    Ok((read_crc(0), read_crc(1)))
+}
+
+fn check_source_size(source: &[u8], expected_size: usize) -> Result<(), RustyBoyError> {
+    match source.len() == expected_size {
+        true => Ok(()),
+        false => Err(RustyBoyError::InvalidPatch(format!(
+            "Patch is for a different ROM ({} bytes, expected {})", source.len(), expected_size
+        ))),
+    }
+}
+
+fn check_target_size(size: usize) -> Result<(), RustyBoyError> {
+    // The sizes come straight from the patch, so a corrupt or malicious one could otherwise have
+    // us allocate far more than any cartridge could hold
+    match size <= MAX_ROM_SIZE {
+        true => Ok(()),
+        false => Err(RustyBoyError::InvalidPatch(format!(
+            "Patched ROM would be {} bytes, more than the {} a cartridge can hold", size, MAX_ROM_SIZE
+        ))),
+    }
+}
+
+fn check_source_crc(source: &[u8], expected_crc: u32) -> Result<(), RustyBoyError> {
+    let crc = crc32fast::hash(source);
+    match crc == expected_crc {
+        true => Ok(()),
+        false => Err(RustyBoyError::InvalidPatch(format!(
+            "Patch is for a different ROM (CRC32 is {:08X}, expected {:08X})", crc, expected_crc
+        ))),
+    }
+}
+
+fn check_target_crc(target: &[u8], expected_crc: u32) -> Result<(), RustyBoyError> {
+    let crc = crc32fast::hash(target);
+    match crc == expected_crc {
+        true => Ok(()),
+        false => Err(RustyBoyError::InvalidPatch(format!(
+            "Patched ROM has the wrong CRC32 ({:08X}, expected {:08X})", crc, expected_crc
+        ))),
+    }
+}
+
+struct PatchReader<'a> {
+    patch: &'a [u8],
+    position: usize,
+}
+
+impl<'a> PatchReader<'a> {
+
+    fn new(patch: &'a [u8], position: usize) -> PatchReader<'a> {
+        PatchReader {
+            patch: patch,
+            position: position,
+        }
+    }
+
+    fn remaining(&self) -> &'a [u8] {
+        &self.patch[std::cmp::min(self.position, self.patch.len())..]
+    }
+
+    fn skip(&mut self, length: usize) {
+        self.position = self.position.saturating_add(length);
+    }
+
+    fn read_slice(&mut self, length: usize) -> Result<&'a [u8], RustyBoyError> {
+        let slice = self.remaining().get(..length)
+            .ok_or_else(|| RustyBoyError::InvalidPatch(String::from("Patch is truncated")))?;
+        self.position += length;
+        Ok(slice)
+    }
+
+    fn read_byte(&mut self) -> Result<Byte, RustyBoyError> {
+        Ok(self.read_slice(1)?[0])
+    }
+
+    fn read_be(&mut self, length: usize) -> Result<usize, RustyBoyError> {
+        Ok(self.read_slice(length)?.iter().fold(0, |value, byte| (value << 8) | *byte as usize))
+    }
+
+    fn read_number(&mut self) -> Result<usize, RustyBoyError> {
+        // UPS and BPS variable length numbers - 7 bits at a time, least significant first, with
+        // the top bit marking the last byte. Each continuation also adds one, so every value has
+        // exactly one encoding
+        let mut value: usize = 0;
+        let mut shift: usize = 1;
+        loop {
+            let byte = self.read_byte()?;
+            value = value.checked_add((byte & 0x7F) as usize * shift)
+                .ok_or_else(|| RustyBoyError::InvalidPatch(String::from("Number in patch is too large")))?;
+            if is_bit_set(&byte, 7) {
+                return Ok(value);
+            }
+            shift = shift.checked_shl(7).filter(|shift| *shift < (1 << 56))
+                .ok_or_else(|| RustyBoyError::InvalidPatch(String::from("Number in patch is too large")))?;
+            value += shift;
+        }
+    }
+
+    fn read_signed_number(&mut self) -> Result<isize, RustyBoyError> {
+        // The bottom bit is the sign
+        let value = self.read_number()?;
+        let magnitude = (value >> 1) as isize;
+        match value & 0x01 {
+            0 => Ok(magnitude),
+            _ => Ok(-magnitude),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode_number(patch: &mut Vec<u8>, mut value: usize) {
+        loop {
+            let byte = (value & 0x7F) as u8;
+            value >>= 7;
+            if value == 0 {
+                patch.push(0x80 | byte);
+                return;
+            }
+            patch.push(byte);
+            value -= 1;
+        }
+    }
+
+    fn encode_signed_number(patch: &mut Vec<u8>, value: isize) {
+        encode_number(patch, (value.unsigned_abs() << 1) | (value < 0) as usize);
+    }
+
+    fn add_footer(mut patch: Vec<u8>, source: &[u8], target: &[u8]) -> Vec<u8> {
+        patch.extend_from_slice(&crc32fast::hash(source).to_le_bytes());
+        patch.extend_from_slice(&crc32fast::hash(target).to_le_bytes());
+        let patch_crc = crc32fast::hash(&patch);
+        patch.extend_from_slice(&patch_crc.to_le_bytes());
+        patch
+    }
+
+    fn make_ups(source: &[u8], target: &[u8]) -> Vec<u8> {
+        let mut patch = UPS_MAGIC.to_vec();
+        encode_number(&mut patch, source.len());
+        encode_number(&mut patch, target.len());
+
+        let xor = |i: usize| source.get(i).unwrap_or(&0) ^ target.get(i).unwrap_or(&0);
+        let mut last = 0;
+        let mut i = 0;
+        while i < target.len() {
+            if xor(i) == 0 {
+                i += 1;
+                continue;
+            }
+
+            encode_number(&mut patch, i - last);
+            while i < target.len() && xor(i) != 0 {
+                patch.push(xor(i));
+                i += 1;
+            }
+            patch.push(0);
+            i += 1;
+            last = i;
+        }
+
+        add_footer(patch, source, target)
+    }
+
+    fn make_bps(source: &[u8], target: &[u8], actions: &[u8]) -> Vec<u8> {
+        let mut patch = BPS_MAGIC.to_vec();
+        encode_number(&mut patch, source.len());
+        encode_number(&mut patch, target.len());
+        encode_number(&mut patch, 0);
+        patch.extend_from_slice(actions);
+        add_footer(patch, source, target)
+    }
+
+    fn is_invalid_patch<T>(result: Result<T, RustyBoyError>) -> bool {
+        matches!(result, Err(RustyBoyError::InvalidPatch(_)))
+    }
+
+    #[test]
+    fn number_encoding_round_trips() {
+        for value in [0, 1, 0x7F, 0x80, 0x407F, 0x4080, MAX_ROM_SIZE, usize::MAX >> 8] {
+            let mut patch = Vec::new();
+            encode_number(&mut patch, value);
+            assert_eq!(PatchReader::new(&patch, 0).read_number().unwrap(), value);
+        }
+
+        for value in [0, 5, -5, -0x4000] {
+            let mut patch = Vec::new();
+            encode_signed_number(&mut patch, value);
+            assert_eq!(PatchReader::new(&patch, 0).read_signed_number().unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn ips_applies_records() {
+        let source = [0u8; 8];
+        let mut patch = IPS_MAGIC.to_vec();
+        patch.extend_from_slice(&[0x00, 0x00, 0x02, 0x00, 0x03, 0xAA, 0xBB, 0xCC]);
+        patch.extend_from_slice(IPS_EOF);
+
+        let target = apply_patch(&source, &patch).unwrap();
+        assert_eq!(target, [0x00, 0x00, 0xAA, 0xBB, 0xCC, 0x00, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn ips_applies_rle_records_and_grows_the_rom() {
+        let source = [0x11u8; 4];
+        let mut patch = IPS_MAGIC.to_vec();
+        patch.extend_from_slice(&[0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x04, 0x7E]);
+        patch.extend_from_slice(IPS_EOF);
+
+        let target = apply_patch(&source, &patch).unwrap();
+        assert_eq!(target, [0x11, 0x11, 0x11, 0x7E, 0x7E, 0x7E, 0x7E]);
+    }
+
+    #[test]
+    fn ips_truncates_after_eof() {
+        let source = [0x22u8; 8];
+        let mut patch = IPS_MAGIC.to_vec();
+        patch.extend_from_slice(&[0x00, 0x00, 0x00, 0x00, 0x01, 0x33]);
+        patch.extend_from_slice(IPS_EOF);
+        patch.extend_from_slice(&[0x00, 0x00, 0x03]);
+
+        let target = apply_patch(&source, &patch).unwrap();
+        assert_eq!(target, [0x33, 0x22, 0x22]);
+    }
+
+    #[test]
+    fn ips_rejects_truncated_and_oversized_patches() {
+        let mut patch = IPS_MAGIC.to_vec();
+        patch.extend_from_slice(&[0x00, 0x00, 0x00, 0x00, 0x04, 0x01]);
+        assert!(is_invalid_patch(apply_patch(&[0; 8], &patch)));
+
+        let mut patch = IPS_MAGIC.to_vec();
+        patch.extend_from_slice(&[0xFF, 0xFF, 0xFF, 0x00, 0x01, 0x01]);
+        patch.extend_from_slice(IPS_EOF);
+        assert!(is_invalid_patch(apply_patch(&[0; 8], &patch)));
+    }
+
+    #[test]
+    fn ups_round_trips() {
+        let source: Vec<u8> = (0..64).collect();
+        let mut target = source.clone();
+        target[3] = 0xFF;
+        target[40..44].copy_from_slice(&[0xDE, 0xAD, 0xBE, 0xEF]);
+
+        let grown: Vec<u8> = source.iter().copied().chain([0x01, 0x00, 0x02]).collect();
+        let shrunk = source[..32].to_vec();
+
+        for target in [target, grown, shrunk] {
+            assert_eq!(apply_patch(&source, &make_ups(&source, &target)).unwrap(), target);
+        }
+    }
+
+    #[test]
+    fn ups_rejects_bad_crcs() {
+        let source = [0x10u8; 16];
+        let target = [0x20u8; 16];
+        let patch = make_ups(&source, &target);
+
+        let mut corrupt = patch.clone();
+        corrupt[UPS_MAGIC.len() + 2] ^= 0x01;
+        assert!(is_invalid_patch(apply_patch(&source, &corrupt)));
+
+        assert!(is_invalid_patch(apply_patch(&[0x11u8; 16], &patch)));
+
+        let mut wrong_target = patch[..(patch.len() - PATCH_FOOTER_SIZE)].to_vec();
+        wrong_target.push(0x01);
+        wrong_target.push(0x00);
+        assert!(is_invalid_patch(apply_patch(&source, &add_footer(wrong_target, &source, &target))));
+    }
+
+    #[test]
+    fn ups_rejects_wrong_source_size() {
+        let source = [0x10u8; 16];
+        let mut patch = UPS_MAGIC.to_vec();
+        encode_number(&mut patch, 32);
+        encode_number(&mut patch, 16);
+        let patch = add_footer(patch, &source, &source);
+
+        assert!(is_invalid_patch(apply_patch(&source, &patch)));
+    }
+
+    #[test]
+    fn ups_rejects_oversized_targets() {
+        let source = [0x10u8; 16];
+        let mut patch = UPS_MAGIC.to_vec();
+        encode_number(&mut patch, source.len());
+        encode_number(&mut patch, usize::MAX >> 8);
+        let patch = add_footer(patch, &source, &source);
+
+        assert!(is_invalid_patch(apply_patch(&source, &patch)));
+    }
+
+    #[test]
+    fn bps_round_trips() {
+        let source: Vec<u8> = (0..16).collect();
+        let target = [0, 1, 2, 3, 0xAA, 0xBB, 0xAA, 0xBB, 0xAA, 12, 13, 14];
+
+        let mut actions = Vec::new();
+        // Source read 0 - 3
+        encode_number(&mut actions, (3 << 2) | 0);
+        // Target read 0xAA 0xBB
+        encode_number(&mut actions, (1 << 2) | 1);
+        actions.extend_from_slice(&[0xAA, 0xBB]);
+        // Target copy from 4, overlapping what it writes
+        encode_number(&mut actions, (2 << 2) | 3);
+        encode_signed_number(&mut actions, 4);
+        // Source copy 12 - 14
+        encode_number(&mut actions, (2 << 2) | 2);
+        encode_signed_number(&mut actions, 12);
+
+        let patch = make_bps(&source, &target, &actions);
+        assert_eq!(apply_patch(&source, &patch).unwrap(), target);
+    }
+
+    #[test]
+    fn bps_rejects_bad_crcs() {
+        let source = [0x10u8; 4];
+        let target = [0x20u8; 4];
+        let mut actions = Vec::new();
+        encode_number(&mut actions, (3 << 2) | 1);
+        actions.extend_from_slice(&target);
+        let patch = make_bps(&source, &target, &actions);
+        assert_eq!(apply_patch(&source, &patch).unwrap(), target);
+
+        let mut corrupt = patch.clone();
+        corrupt[patch.len() - 1] ^= 0x01;
+        assert!(is_invalid_patch(apply_patch(&source, &corrupt)));
+
+        assert!(is_invalid_patch(apply_patch(&[0x11u8; 4], &patch)));
+
+        assert!(is_invalid_patch(apply_patch(&source, &make_bps(&source, &[0x30u8; 4], &actions))));
+    }
+
+    #[test]
+    fn bps_rejects_actions_past_the_target_size() {
+        let source = [0x10u8; 4];
+        let target = [0x10u8; 4];
+        let mut actions = Vec::new();
+        encode_number(&mut actions, (3 << 2) | 0);
+        encode_number(&mut actions, ((usize::MAX >> 10) << 2) | 3);
+        encode_signed_number(&mut actions, 0);
+
+        assert!(is_invalid_patch(apply_patch(&source, &make_bps(&source, &target, &actions))));
+    }
+
+    #[test]
+    fn bps_rejects_oversized_targets() {
+        let source = [0x10u8; 4];
+        let mut patch = BPS_MAGIC.to_vec();
+        encode_number(&mut patch, source.len());
+        encode_number(&mut patch, MAX_ROM_SIZE + 1);
+        encode_number(&mut patch, 0);
+        let patch = add_footer(patch, &source, &source);
+
+        assert!(is_invalid_patch(apply_patch(&source, &patch)));
+    }
+}
@@ -1,5 +1,8 @@
+use std::io::Result;
+
 use crate::interrupts::*;
 use crate::mmu::*;
+use crate::state::*;
 use crate::timer::*;
 use crate::utils::*;
 
@@ -35,6 +38,17 @@ impl Ppu {
         &self.screen
     }
 
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_isize(self.scanline_counter);
+        writer.write_bytes(&self.screen);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        self.scanline_counter = reader.read_isize()?;
+        reader.read_bytes_into(&mut self.screen)?;
+        Ok(())
+    }
+
     pub fn update_graphics(&mut self, mmu: &mut Mmu, cycles: u8, debug: bool) {
         // Attempt to update the graphics. If we have taken more than the number
         // of cycles needed to update a scanline, it is time to draw it
@@ -151,8 +165,6 @@ impl Ppu {
         let mut should_request_stat_interrupt = false;
         let current_mode = self.get_lcd_mode(mmu);
 
-        let max_cycles_per_frame = MAX_CYCLES_PER_FRAME as isize;
-
         // If LCD is enabled, we should cycle through different LCD modes depending on what
         // "dot" we are drawing in the current scanline. We have 456 cycles per scanline
         // for scanlines 0-143. This is broken down as follows:
@@ -169,7 +181,7 @@ impl Ppu {
 
             should_request_stat_interrupt = self.is_vblank_stat_interrupt_enabled(mmu);
         } else {
-            if self.scanline_counter >= max_cycles_per_frame - 80 {
+            if self.scanline_counter >= CYCLES_PER_SCANLINE - 80 {
                 // This is Mode 2
                 self.set_lcd_mode(mmu, LcdMode::SPRITE_SEARCH);
 
@@ -179,7 +191,7 @@ impl Ppu {
 
                 should_request_stat_interrupt = self.is_oam_stat_interrupt_enabled(mmu);
 
-            } else if self.scanline_counter >= max_cycles_per_frame - 80 - 172 {
+            } else if self.scanline_counter >= CYCLES_PER_SCANLINE - 80 - 172 {
                 // This is Mode 3
                 self.set_lcd_mode(mmu, LcdMode::LCD_TRANSFER);
 
@@ -191,6 +203,11 @@ impl Ppu {
                 // THis is Mode 0
                 self.set_lcd_mode(mmu, LcdMode::H_BLANK);
 
+                // H-Blank VRAM DMA copies the next block as each line enters H-Blank
+                if current_mode != LcdMode::H_BLANK {
+                    mmu.do_hblank_vram_dma();
+                }
+
                 mmu.open_oam_access();
                 mmu.open_vram_access();
 
@@ -253,12 +270,12 @@ impl Ppu {
         let lsb = get_bit_val(&mmu.read_byte(LCD_STATUS_ADDR), 0);
         let lcd_mode = msb << 1 | lsb;
 
+        // Only 2 bits are used so every value is a valid mode
         match lcd_mode {
             0 => LcdMode::H_BLANK,
             1 => LcdMode::V_BLANK,
             2 => LcdMode::SPRITE_SEARCH,
-            3 => LcdMode::LCD_TRANSFER,
-            _ => panic!("Invalid LCD Mode - {}", lcd_mode)
+            _ => LcdMode::LCD_TRANSFER,
         }
     }
 
@@ -505,7 +522,7 @@ impl Ppu {
                             continue
                         }
 
-                        if is_bit_set(&attributes, 7) && !self.is_pixel_white(pixel_x, current_scanline) {
+                        if is_bit_set(&attributes, 7) && !self.is_pixel_white(mmu, pixel_x, current_scanline) {
                             // Sprite is only hidden under the background for colors 1 - 3 (so not white)
                             continue
                         }
@@ -639,17 +656,25 @@ impl Ppu {
         // Bit 3-2 - Color for index 1
         // Bit 1-0 - Color for index 0
 
+        // Color codes are only ever 2 bits (0 - 3), so treat anything else as the darkest
         let color = match color_code {
-            3 => get_bit_val(&pallette, 7) << 1 | get_bit_val(&pallette, 6),
-            2 => get_bit_val(&pallette, 5) << 1 | get_bit_val(&pallette, 4),
-            1 => get_bit_val(&pallette, 3) << 1 | get_bit_val(&pallette, 2),
             0 => get_bit_val(&pallette, 1) << 1 | get_bit_val(&pallette, 0),
-            _ => panic!("Invalid color code - {}", color_code)
+            1 => get_bit_val(&pallette, 3) << 1 | get_bit_val(&pallette, 2),
+            2 => get_bit_val(&pallette, 5) << 1 | get_bit_val(&pallette, 4),
+            _ => get_bit_val(&pallette, 7) << 1 | get_bit_val(&pallette, 6),
         };
 
-        Some(*GB_COLORS
-            .get(&color)
-            .expect(&format!("Color {} is not recognized", color)))
+        // A CGB running a DMG game uses the shade to pick from its palettes instead
+        if mmu.is_dmg_compat() {
+            let (palette_num, palettes) = match pallette_addr {
+                OBJ_COLOR_PALLETTE_ADDR_0 => (0, mmu.get_cgb_object_palettes()),
+                OBJ_COLOR_PALLETTE_ADDR_1 => (1, mmu.get_cgb_object_palettes()),
+                _ => (0, mmu.get_cgb_background_palettes()),
+            };
+            return self.get_cgb_color(mmu, color, palette_num, palettes);
+        }
+
+        GB_COLORS.get(&color).copied()
     }
 
     fn get_cgb_color(&self, mmu: &Mmu, color_code: u8, palette_num: u8, palettes: &[Byte]) -> Option<(Byte, Byte, Byte)> {
@@ -671,9 +696,15 @@ impl Ppu {
         Some((red, green, blue))
     }
 
-    fn is_pixel_white(&self, x: u8, y: u8) -> bool {
+    fn is_pixel_white(&self, mmu: &Mmu, x: u8, y: u8) -> bool {
         let base = ((y as u32) * 3 * SCREEN_WIDTH + (x as u32) * 3) as usize;
         let pixel = (self.screen[base], self.screen[base + 1], self.screen[base + 2]);
-        pixel.0 == 0xFF && pixel.1 == 0xFF && pixel.2 == 0xFF
+
+        // In compatibility mode the lightest shade is whatever colour the palette gives it
+        let white = match mmu.is_dmg_compat() {
+            true => self.get_cgb_color(mmu, 0, 0, mmu.get_cgb_background_palettes()).unwrap_or((0xFF, 0xFF, 0xFF)),
+            false => (0xFF, 0xFF, 0xFF),
+        };
+        pixel == white
     }
 }
\ No newline at end of file
@@ -0,0 +1,172 @@
+use std::f64::consts::PI;
+
+// Number of output samples each band-limited step is spread across, and the number of
+// sub-sample positions a step can be placed at
+const KERNEL_WIDTH: usize = 16;
+const KERNEL_PHASES: usize = 64;
+
+// Fraction of the output Nyquist frequency that is let through - a little below 1.0 so the
+// transition band of the filter doesn't fold back into the audible range
+const CUTOFF: f64 = 0.9;
+
+// Pole of the high pass filter used to remove DC offset, similar to the capacitor on the
+// real hardware's audio output
+const HIGH_PASS_FACTOR: f32 = 0.999;
+
+#[derive(Debug)]
+struct ResamplerChannel {
+    // Each entry holds the change in amplitude for that output sample, the final
+    // output is the running sum of these
+    deltas: Vec<f32>,
+    integrator: f32,
+    high_pass_input: f32,
+    high_pass_output: f32,
+}
+
+impl ResamplerChannel {
+    fn new() -> ResamplerChannel {
+        ResamplerChannel {
+            deltas: vec![0.0; KERNEL_WIDTH],
+            integrator: 0.0,
+            high_pass_input: 0.0,
+            high_pass_output: 0.0,
+        }
+    }
+
+    fn read_sample(&mut self, idx: usize) -> f32 {
+        self.integrator += self.deltas[idx];
+
+        let output = self.integrator - self.high_pass_input + HIGH_PASS_FACTOR * self.high_pass_output;
+        self.high_pass_input = self.integrator;
+        self.high_pass_output = output;
+
+        output
+    }
+}
+
+#[derive(Debug)]
+pub struct Resampler {
+    /**
+    * Band-limited resampler from the CPU clock rate (~4.19 MHz) down to the audio output rate.
+    *
+    * Rather than sampling the APU output directly (which aliases badly on square waves), every
+    * change in amplitude is recorded as a step at its exact clock time. Each step is
+    * drawn into the output as a windowed sinc step, which removes everything above the
+    * output Nyquist frequency. This is the same approach as blip_buf, used by many other emulators
+    **/
+
+    sample_rate: usize,
+
+    // Output samples per clock cycle
+    factor: f64,
+
+    // Position (in output samples) of clock 0 of the current frame
+    offset: f64,
+
+    kernel: Vec<[f32; KERNEL_WIDTH]>,
+    channels: Vec<ResamplerChannel>,
+}
+
+impl Resampler {
+
+    pub fn new(clock_rate: usize, sample_rate: usize, channel_count: usize) -> Resampler {
+        let mut channels = Vec::new();
+        for _ in 0..channel_count {
+            channels.push(ResamplerChannel::new());
+        }
+
+        Resampler {
+            sample_rate: sample_rate,
+            factor: sample_rate as f64 / clock_rate as f64,
+            offset: 0.0,
+            kernel: Resampler::build_kernel(),
+            channels: channels,
+        }
+    }
+
+    pub fn get_sample_rate(&self) -> usize {
+        self.sample_rate
+    }
+
+    pub fn add_delta(&mut self, channel: usize, clock_time: usize, delta: f32) {
+        // Find the output sample (and the fraction of the way into it) where this step lands
+        let position = self.offset + (clock_time as f64) * self.factor;
+        let idx = position.floor() as usize;
+        let phase = ((position - position.floor()) * KERNEL_PHASES as f64) as usize;
+
+        let deltas = &mut self.channels[channel].deltas;
+        if deltas.len() < idx + KERNEL_WIDTH {
+            deltas.resize(idx + KERNEL_WIDTH, 0.0);
+        }
+
+        for (i, k) in self.kernel[phase].iter().enumerate() {
+            deltas[idx + i] += delta * k;
+        }
+    }
+
+    pub fn end_frame(&mut self, clocks: usize) -> Vec<f32> {
+        // Every output sample before the end of the frame can no longer be touched by a new
+        // step, so they are complete and can be read out. Samples are interleaved per channel
+        self.offset += (clocks as f64) * self.factor;
+        let available = self.offset.floor() as usize;
+        self.offset -= available as f64;
+
+        let channel_count = self.channels.len();
+        let mut samples = vec![0.0; available * channel_count];
+
+        for (c, channel) in self.channels.iter_mut().enumerate() {
+            if channel.deltas.len() < available + KERNEL_WIDTH {
+                channel.deltas.resize(available + KERNEL_WIDTH, 0.0);
+            }
+
+            for i in 0..available {
+                samples[i * channel_count + c] = channel.read_sample(i);
+            }
+
+            // Shift the remaining (incomplete) samples to the front of the buffer
+            channel.deltas.drain(0..available);
+        }
+
+        samples
+    }
+
+    fn build_kernel() -> Vec<[f32; KERNEL_WIDTH]> {
+        // Build a table of band-limited impulses, one for each sub-sample phase. Each one
+        // is a sinc function with a Blackman window, delayed by half the kernel width so
+        // that it only ever affects samples at or after the step
+        let mut kernel = Vec::new();
+        let half_width = (KERNEL_WIDTH / 2) as f64;
+
+        for p in 0..KERNEL_PHASES {
+            let fraction = p as f64 / KERNEL_PHASES as f64;
+            let mut impulse = [0.0; KERNEL_WIDTH];
+            let mut sum = 0.0;
+
+            for (i, value) in impulse.iter_mut().enumerate() {
+                let t = i as f64 - fraction - half_width + 1.0;
+                let sinc = match t == 0.0 {
+                    true => CUTOFF,
+                    false => (PI * CUTOFF * t).sin() / (PI * t),
+                };
+
+                let window_position = (i as f64 + 1.0 - fraction) / KERNEL_WIDTH as f64;
+                let window = 0.42
+                    - 0.5 * (2.0 * PI * window_position).cos()
+                    + 0.08 * (4.0 * PI * window_position).cos();
+
+                *value = sinc * window;
+                sum += *value;
+            }
+
+            // Normalize so that a step of 1.0 always ends up exactly 1.0 higher
+            let mut normalized = [0.0; KERNEL_WIDTH];
+            for i in 0..KERNEL_WIDTH {
+                normalized[i] = (impulse[i] / sum) as f32;
+            }
+
+            kernel.push(normalized);
+        }
+
+        kernel
+    }
+}
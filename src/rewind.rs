@@ -0,0 +1,194 @@
+use std::collections::VecDeque;
+
+#[derive(Debug)]
+pub struct RewindBuffer {
+    /**
+    * Ring buffer of save states used to play the game backwards.
+    *
+    * A snapshot is taken every `interval` frames. Only the newest snapshot is kept whole - every
+    * older one is stored as the XOR of itself and the snapshot after it, which is mostly zeros
+    * as very little changes between snapshots, and is then run length encoded. Stepping
+    * backwards XORs the newest snapshot with the most recent delta to get the one before it
+    **/
+
+    capacity: usize,
+    interval: usize,
+    frame_counter: usize,
+    newest: Option<Vec<u8>>,
+    deltas: VecDeque<Vec<u8>>,
+}
+
+impl RewindBuffer {
+
+    pub fn new(capacity: usize, interval: usize) -> RewindBuffer {
+        RewindBuffer {
+            capacity: capacity,
+            interval: std::cmp::max(interval, 1),
+            frame_counter: 0,
+            newest: None,
+            deltas: VecDeque::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self.newest {
+            Some(_) => self.deltas.len() + 1,
+            None => 0,
+        }
+    }
+
+    pub fn get_memory_usage(&self) -> usize {
+        let newest_len = self.newest.as_ref().map_or(0, |s| s.len());
+        newest_len + self.deltas.iter().map(|d| d.len()).sum::<usize>()
+    }
+
+    pub fn clear(&mut self) {
+        self.frame_counter = 0;
+        self.newest = None;
+        self.deltas.clear();
+    }
+
+    pub fn should_snapshot(&mut self) -> bool {
+        // Called once per frame, returns true when it is time to take a snapshot
+        if self.capacity == 0 {
+            return false;
+        }
+
+        self.frame_counter += 1;
+        if self.frame_counter >= self.interval {
+            self.frame_counter = 0;
+            return true;
+        }
+
+        false
+    }
+
+    pub fn push(&mut self, snapshot: Vec<u8>) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        if let Some(newest) = self.newest.take() {
+            // If the snapshot size has changed, older deltas can't be applied anymore
+            if newest.len() != snapshot.len() {
+                self.deltas.clear();
+            } else {
+                self.deltas.push_back(compress(&xor(&newest, &snapshot)));
+            }
+        }
+
+        self.newest = Some(snapshot);
+
+        // Drop the oldest snapshots once we are over capacity (the newest counts towards this)
+        while self.deltas.len() + 1 > self.capacity {
+            self.deltas.pop_front();
+        }
+    }
+
+    pub fn pop(&mut self) -> Option<Vec<u8>> {
+        let newest = self.newest.take()?;
+
+        self.newest = self.deltas
+            .pop_back()
+            .map(|delta| xor(&newest, &decompress(&delta, newest.len())));
+
+        self.frame_counter = 0;
+        Some(newest)
+    }
+}
+
+fn xor(a: &[u8], b: &[u8]) -> Vec<u8> {
+    a.iter().zip(b.iter()).map(|(x, y)| x ^ y).collect()
+}
+
+fn write_length(out: &mut Vec<u8>, mut len: usize) {
+    // Lengths are written 7 bits at a time, with the top bit set if more bytes follow
+    loop {
+        let byte = (len & 0x7F) as u8;
+        len >>= 7;
+        if len == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn read_length(data: &[u8], position: &mut usize) -> usize {
+    let mut len = 0;
+    let mut shift = 0;
+    while *position < data.len() {
+        let byte = data[*position];
+        *position += 1;
+        len |= ((byte & 0x7F) as usize) << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            break;
+        }
+    }
+    len
+}
+
+fn compress(data: &[u8]) -> Vec<u8> {
+    // Encoded as a series of runs, each being:
+    //     Length of a run of repeated bytes, followed by the byte (if the length is non zero)
+    //     Length of literal bytes that follow, followed by the literal bytes
+    let mut out = Vec::new();
+    let mut i = 0;
+
+    while i < data.len() {
+        let mut run = 1;
+        while i + run < data.len() && data[i + run] == data[i] {
+            run += 1;
+        }
+
+        // Short runs aren't worth encoding, treat them as literals instead
+        if run < 4 {
+            run = 0;
+        }
+
+        write_length(&mut out, run);
+        if run > 0 {
+            out.push(data[i]);
+            i += run;
+        }
+
+        let literal_start = i;
+        while i < data.len() {
+            let is_run_start = i + 3 < data.len()
+                && data[i] == data[i + 1]
+                && data[i] == data[i + 2]
+                && data[i] == data[i + 3];
+            if is_run_start {
+                break;
+            }
+            i += 1;
+        }
+
+        write_length(&mut out, i - literal_start);
+        out.extend_from_slice(&data[literal_start..i]);
+    }
+
+    out
+}
+
+fn decompress(data: &[u8], len: usize) -> Vec<u8> {
+    let mut out = Vec::with_capacity(len);
+    let mut position = 0;
+
+    while position < data.len() {
+        let run = read_length(data, &mut position);
+        if run > 0 && position < data.len() {
+            let byte = data[position];
+            position += 1;
+            out.resize(out.len() + run, byte);
+        }
+
+        let literal_len = std::cmp::min(read_length(data, &mut position), data.len() - position);
+        out.extend_from_slice(&data[position..position + literal_len]);
+        position += literal_len;
+    }
+
+    out.resize(len, 0);
+    out
+}
@@ -1,35 +1,179 @@
 use std::fs;
+use std::path::{Path, PathBuf};
 
+use crate::archive::*;
+use crate::error::*;
+use crate::header::*;
+use crate::mbc::*;
+use crate::patch::*;
 use crate::utils::*;
 
 #[derive(Debug)]
 pub struct Rom {
-    data: Vec<u8>
+    data: Vec<u8>,
+    header: CartridgeHeader,
+
+    // The ROM's own file name, which for archives is the name of the file inside them
+    file_name: Option<String>,
+
+    // The IPS, UPS or BPS patch file applied on top of the ROM, if any
+    patch_name: Option<String>,
+
+    // Anything worth mentioning from unpacking the archive the ROM came in
+    archive_warnings: Vec<String>,
 }
 
 impl Rom {
 
-    pub fn new(file: &str) -> Rom {
-        let contents = fs::read(file)
-            .expect("Something went wrong reading the file");
+    pub fn new(file: &str) -> Result<Rom, RustyBoyError> {
+        Rom::open(file, None, None)
+    }
+
+    pub fn open(file: &str, entry: Option<&str>, patch_file: Option<&str>) -> Result<Rom, RustyBoyError> {
+        // Zip and gzip archives are unpacked, see Rom::unpack. Without a patch file we look for
+        // one next to the ROM with the same name, i.e. game.gb -> game.ips
+        let (file_name, data, archive_warnings) = extract_rom(fs::read(file)?, entry)?;
+        let file_name = file_name.or_else(|| guess_rom_file_name(file));
+
+        let patch_path = match patch_file {
+            Some(patch_file) => Some(PathBuf::from(patch_file)),
+            None => find_patch(file, file_name.as_deref()),
+        };
+        let patch = match &patch_path {
+            Some(patch_path) => Some(fs::read(patch_path)?),
+            None => None,
+        };
+
+        let mut rom = Rom::patch_and_parse(data, patch.as_deref())?;
+        rom.file_name = file_name;
+        rom.patch_name = patch_path.map(|path| path.to_string_lossy().into_owned());
+        rom.archive_warnings = archive_warnings;
+        Ok(rom)
+    }
+
+    pub fn from_bytes(data: Vec<u8>) -> Result<Rom, RustyBoyError> {
+        Rom::unpack(data, None, None)
+    }
+
+    pub fn unpack(data: Vec<u8>, entry: Option<&str>, patch: Option<&[u8]>) -> Result<Rom, RustyBoyError> {
+        // Archives use the entry with the given name, or the first .gb / .gbc file in them if
+        // there isn't one. Plain ROMs are used as they are
+        let (file_name, data, archive_warnings) = extract_rom(data, entry)?;
+        let mut rom = Rom::patch_and_parse(data, patch)?;
+        rom.file_name = file_name;
+        rom.patch_name = patch.map(|_| String::from("(in memory)"));
+        rom.archive_warnings = archive_warnings;
+        Ok(rom)
+    }
+
+    fn patch_and_parse(data: Vec<u8>, patch: Option<&[u8]>) -> Result<Rom, RustyBoyError> {
+        // The patch is applied to a copy, so the header (and its checksums) come from the
+        // patched ROM while the original is left as it was
+        match patch {
+            Some(patch) => Rom::parse(apply_patch(&data, patch)?),
+            None => Rom::parse(data),
+        }
+    }
+
+    fn parse(data: Vec<u8>) -> Result<Rom, RustyBoyError> {
+        // The cartridge header occupies 0x0100 - 0x014F, so we need at least that much
+        if data.len() < CARTRIDGE_HEADER_END_ADDR {
+            return Err(RustyBoyError::InvalidHeader(format!("ROM is only {} bytes long", data.len())));
+        }
+
+        let header = Rom::find_header(&data);
+
+        // Homebrew and test ROMs often get the logo or checksum wrong, so we only warn about
+        // those (see Rom::get_problems). If both are wrong though, this almost certainly isn't a Game Boy ROM at all
+        if !header.has_valid_logo && !header.is_header_checksum_valid() {
+            return Err(RustyBoyError::InvalidHeader(String::from(
+                "Nintendo logo and header checksum are both wrong, this doesn't look like a Game Boy ROM"
+            )));
+        }
+
+        Ok(Rom {
+            data: data,
+            header: header,
+            file_name: None,
+            patch_name: None,
+            archive_warnings: Vec::new(),
+        })
+    }
+
+    fn find_header(data: &[u8]) -> CartridgeHeader {
+        // MMM01 multi game cartridges boot with the last 32 KiB of the ROM mapped in, so their
+        // header is at the end of the ROM, and the one at the start belongs to the first game
+        let header = CartridgeHeader::parse(data);
+        if data.len() <= 0x8000 || header.cartridge_type.mbc_type == Some(MbcType::MMM01) {
+            return header;
+        }
+
+        let end_header_offset = data.len() - 0x8000;
+        let mut end_header = CartridgeHeader::parse(&data[end_header_offset..]);
+        let is_mmm01 = end_header.cartridge_type.mbc_type == Some(MbcType::MMM01);
+        if is_mmm01 && (end_header.has_valid_logo || end_header.is_header_checksum_valid()) {
+            // The global checksum still covers the whole ROM, not just the 32 KiB parsed above
+            end_header.computed_global_checksum = compute_global_checksum(data, end_header_offset);
+            return end_header;
+        }
 
-        Rom {
-            data: contents
+        header
+    }
+
+    pub fn debug_header(&self) -> String {
+        let header = &self.header;
+        let mut lines = vec![format!("ROM Title: {}", header.title)];
+        if let Some(manufacturer_code) = &header.manufacturer_code {
+            lines.push(format!("Manufacturer Code: {}", manufacturer_code));
+        }
+        lines.push(format!("Licensee Code: {}", header.get_licensee_code()));
+        lines.push(format!("Cartridge Type: 0x{:02X} - {}", header.cartridge_type.code, header.cartridge_type));
+        lines.push(format!("ROM Size: {} KiB ({} Banks)", header.get_rom_size() / 1024, header.rom_banks));
+        lines.push(format!("RAM Size: {} KiB", header.ram_size / 1024));
+        lines.push(format!("CGB: {:?}", header.cgb_support));
+        lines.push(format!("SGB: {}", header.sgb_supported));
+        lines.push(format!("Destination: {}", if header.is_japanese { "Japan" } else { "Overseas" }));
+        lines.push(format!("Version: {}", header.version));
+        if let Some(patch_name) = &self.patch_name {
+            lines.push(format!("Patch: {}", patch_name));
         }
+        lines.push(format!(
+            "Header Checksum: 0x{:02X} (Computed: 0x{:02X}, Valid: {})",
+            header.header_checksum, header.computed_header_checksum, header.is_header_checksum_valid()
+        ));
+        lines.push(format!(
+            "Global Checksum: 0x{:04X} (Computed: 0x{:04X}, Valid: {})",
+            header.global_checksum, header.computed_global_checksum, header.is_global_checksum_valid()
+        ));
+        lines.join("\n")
+    }
+
+    pub fn get_problems(&self) -> Vec<String> {
+        // Anything odd about the header or the archive it came in, which we run the ROM despite
+        // (see Rom::parse)
+        let mut problems = self.archive_warnings.clone();
+        problems.extend(self.header.get_problems(self.data.len()));
+        problems
+    }
+
+    pub fn get_header(&self) -> &CartridgeHeader {
+        &self.header
+    }
+
+    pub fn get_patch_name(&self) -> Option<&str> {
+        self.patch_name.as_deref()
     }
 
-    pub fn debug_header(&self) {
-        println!("\n---------------------------------\n");
-        let rom_title: String = self.data[0x134..0x143].to_vec().into_iter().map(|c| c as char).collect();
-        println!("ROM Title: {}", rom_title);
-        println!("Cartridge Type: 0x{:02X}", self.data[0x147]);
-        println!("Number of Banks: {}", self.get_number_of_banks());
-        println!("CGB: {}", self.is_cgb());
-        println!("\n---------------------------------\n");
+    pub fn get_file_name(&self) -> Option<&str> {
+        self.file_name.as_deref()
     }
 
     pub fn get_byte(&self, addr: usize) -> Byte {
-        self.data[addr]
+        // Reading past the end of the ROM returns open bus (0xFF)
+        match self.data.get(addr) {
+            Some(byte) => *byte,
+            None => 0xFF,
+        }
     }
 
     pub fn length(&self) -> usize {
@@ -37,29 +181,36 @@ impl Rom {
     }
 
     pub fn get_cartridge_type(&self) -> Byte {
-        self.data[0x0147]
+        self.header.cartridge_type.code
     }
 
     pub fn get_number_of_banks(&self) -> u16 {
-        match self.data[0x0148] {
-            0x00 => 2,
-            0x01 => 4,
-            0x02 => 8,
-            0x03 => 16,
-            0x04 => 32,
-            0x05 => 64,
-            0x06 => 128,
-            0x07 => 256,
-            0x08 => 512,
-            0x52 => 72,
-            0x53 => 80,
-            0x54 => 96,
-            _ => 2,
-        }
+        self.header.rom_banks as u16
+    }
+
+    pub fn get_header_checksum(&self) -> Byte {
+        self.header.header_checksum
+    }
+
+    pub fn get_global_checksum(&self) -> Word {
+        self.header.global_checksum
     }
 
     pub fn is_cgb(&self) -> bool {
-        self.data[0x0143] == 0x80 || self.data[0x0143] == 0xC0
+        self.header.is_cgb()
     }
 
-}
\ No newline at end of file
+}
+
+fn find_patch(file: &str, file_name: Option<&str>) -> Option<PathBuf> {
+    // Patches sit next to the ROM, named after either the file itself or the ROM inside it
+    let rom_path = Path::new(file);
+    let mut candidates = vec![rom_path.to_path_buf()];
+    if let Some(file_name) = file_name {
+        candidates.push(rom_path.with_file_name(file_name));
+    }
+
+    candidates.iter()
+        .flat_map(|candidate| PATCH_EXTENSIONS.iter().map(move |extension| candidate.with_extension(extension)))
+        .find(|patch_path| patch_path.is_file())
+}
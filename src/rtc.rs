@@ -0,0 +1,248 @@
+use std::io;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::state::*;
+use crate::utils::*;
+
+#[derive(Debug)]
+pub struct Rtc {
+    /**
+    * The Real Time Clock found on MBC3 cartridges. It counts seconds, minutes, hours and a 9 bit
+    * day counter, driven here by emulated cycles so it stays in step with the game (and with fast
+    * forward, rewind and save states). The game never reads the live registers directly - writing
+    * 0 then 1 to 0x6000 - 0x7FFF latches them, and reads return the latched copy.
+    *
+    * The registers are selected by writing 0x08 - 0x0C to 0x4000 - 0x5FFF:
+    *    08    Seconds  0 - 59
+    *    09    Minutes  0 - 59
+    *    0A    Hours    0 - 23
+    *    0B    Lower 8 bits of the day counter
+    *    0C    Bit 0 - Bit 8 of the day counter
+    *          Bit 6 - Halt (0 = clock running, 1 = clock stopped)
+    *          Bit 7 - Day counter carry, set when the day counter overflows and stays set until cleared
+    **/
+
+    seconds: Byte,
+    minutes: Byte,
+    hours: Byte,
+    days: Word,
+    halted: bool,
+    day_carry: bool,
+
+    latched: [Byte; 5],
+    latch_register: Byte,
+    cycle_counter: usize,
+}
+
+impl Rtc {
+
+    pub fn new() -> Rtc {
+        Rtc {
+            seconds: 0,
+            minutes: 0,
+            hours: 0,
+            days: 0,
+            halted: false,
+            day_carry: false,
+            latched: [0; 5],
+            latch_register: 0xFF,
+            cycle_counter: 0,
+        }
+    }
+
+    pub fn update(&mut self, cycles: u8) {
+        if self.halted {
+            return;
+        }
+
+        self.cycle_counter += cycles as usize;
+        while self.cycle_counter >= CLOCK_SPEED {
+            self.cycle_counter -= CLOCK_SPEED;
+            self.tick();
+        }
+    }
+
+    fn tick(&mut self) {
+        // Each register is only as wide as it needs to be, and only carries into the next when it
+        // reaches its limit. Games can write out of range values, which count up until the
+        // register overflows back to 0 without carrying
+        self.seconds = (self.seconds + 1) & 0x3F;
+        if self.seconds != 60 {
+            return;
+        }
+        self.seconds = 0;
+
+        self.minutes = (self.minutes + 1) & 0x3F;
+        if self.minutes != 60 {
+            return;
+        }
+        self.minutes = 0;
+
+        self.hours = (self.hours + 1) & 0x1F;
+        if self.hours != 24 {
+            return;
+        }
+        self.hours = 0;
+
+        self.days = (self.days + 1) & 0x1FF;
+        if self.days == 0 {
+            self.day_carry = true;
+        }
+    }
+
+    fn catch_up(&mut self, mut elapsed_seconds: u64) {
+        // Advance the clock by however long we were switched off for. Out of range values need to
+        // be ticked individually until they wrap, after which we can do it all at once
+        if self.halted {
+            return;
+        }
+
+        while elapsed_seconds > 0 && (self.seconds >= 60 || self.minutes >= 60 || self.hours >= 24) {
+            self.tick();
+            elapsed_seconds -= 1;
+        }
+
+        let mut total = ((((self.days as u64 * 24) + self.hours as u64) * 60 + self.minutes as u64) * 60)
+            + self.seconds as u64
+            + elapsed_seconds;
+
+        self.seconds = (total % 60) as Byte;
+        total /= 60;
+        self.minutes = (total % 60) as Byte;
+        total /= 60;
+        self.hours = (total % 24) as Byte;
+        total /= 24;
+
+        if total > 0x1FF {
+            self.day_carry = true;
+        }
+        self.days = (total & 0x1FF) as Word;
+    }
+
+    fn get_registers(&self) -> [Byte; 5] {
+        [
+            self.seconds,
+            self.minutes,
+            self.hours,
+            self.days as Byte,
+            ((self.days >> 8) as Byte & 0x01) | ((self.halted as Byte) << 6) | ((self.day_carry as Byte) << 7),
+        ]
+    }
+
+    pub fn latch(&mut self, data: Byte) {
+        // Writing 0 and then 1 copies the live registers into the latched ones
+        if self.latch_register == 0x00 && data == 0x01 {
+            self.latched = self.get_registers();
+        }
+        self.latch_register = data;
+    }
+
+    pub fn read_register(&self, register: usize) -> Byte {
+        self.latched[register - 0x08]
+    }
+
+    pub fn write_register(&mut self, register: usize, data: Byte) {
+        // Like read_register, callers only pass the clock registers 0x08 - 0x0C
+        debug_assert!((0x08..=0x0C).contains(&register), "Invalid RTC register [{:02X}]", register);
+        match register {
+            0x08 => {
+                // Writing the seconds also resets the sub second counter
+                self.seconds = data & 0x3F;
+                self.cycle_counter = 0;
+            },
+            0x09 => self.minutes = data & 0x3F,
+            0x0A => self.hours = data & 0x1F,
+            0x0B => self.days = (self.days & 0x100) | data as Word,
+            0x0C => {
+                self.days = (self.days & 0xFF) | (((data & 0x01) as Word) << 8);
+                self.halted = is_bit_set(&data, 6);
+                self.day_carry = is_bit_set(&data, 7);
+            },
+            _ => (),
+        };
+
+        // Games usually read back what they wrote without latching again, so keep the latched
+        // registers in step
+        self.latched[register - 0x08] = self.get_registers()[register - 0x08];
+    }
+
+    pub fn get_footer(&self) -> Vec<Byte> {
+        // The footer most emulators append to the save file (VBA-M, BGB, mGBA, SameBoy):
+        //     5 x 32 bit live registers (seconds, minutes, hours, days, days high)
+        //     5 x 32 bit latched registers
+        //     64 bit UNIX timestamp of when the save was written
+        // All values are little endian
+        let mut footer = Vec::with_capacity(RTC_FOOTER_SIZE);
+        for register in self.get_registers().iter().chain(self.latched.iter()) {
+            footer.extend_from_slice(&(*register as u32).to_le_bytes());
+        }
+        footer.extend_from_slice(&get_unix_timestamp().to_le_bytes());
+        footer
+    }
+
+    pub fn load_footer(&mut self, footer: &[Byte]) -> Option<String> {
+        // Older versions of the format only have a 32 bit timestamp, making the footer 44 bytes.
+        // Returns a warning if the footer is neither, in which case the clock is left alone
+        let timestamp = match footer.len() {
+            RTC_FOOTER_SIZE => u64::from_le_bytes(footer[40..48].try_into().unwrap()),
+            RTC_FOOTER_SIZE_32_BIT => u32::from_le_bytes(footer[40..44].try_into().unwrap()) as u64,
+            _ => return Some(format!("Ignoring RTC footer of unexpected size {}", footer.len())),
+        };
+
+        let registers: Vec<Byte> = footer[0..40].chunks(4).map(|value| value[0]).collect();
+        self.seconds = registers[0] & 0x3F;
+        self.minutes = registers[1] & 0x3F;
+        self.hours = registers[2] & 0x1F;
+        self.days = (registers[3] as Word) | (((registers[4] & 0x01) as Word) << 8);
+        self.halted = is_bit_set(&registers[4], 6);
+        self.day_carry = is_bit_set(&registers[4], 7);
+        self.latched.copy_from_slice(&registers[5..10]);
+        self.cycle_counter = 0;
+
+        // The clock kept running while the emulator was closed
+        self.catch_up(get_unix_timestamp().saturating_sub(timestamp));
+        None
+    }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        for register in self.get_registers().iter() {
+            writer.write_byte(*register);
+        }
+        for register in self.latched.iter() {
+            writer.write_byte(*register);
+        }
+        writer.write_byte(self.latch_register);
+        writer.write_usize(self.cycle_counter);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> io::Result<()> {
+        self.seconds = reader.read_byte()?;
+        self.minutes = reader.read_byte()?;
+        self.hours = reader.read_byte()?;
+        self.days = reader.read_byte()? as Word;
+        let days_high = reader.read_byte()?;
+        self.days |= ((days_high & 0x01) as Word) << 8;
+        self.halted = is_bit_set(&days_high, 6);
+        self.day_carry = is_bit_set(&days_high, 7);
+        for register in self.latched.iter_mut() {
+            *register = reader.read_byte()?;
+        }
+        self.latch_register = reader.read_byte()?;
+        self.cycle_counter = reader.read_usize()?;
+        Ok(())
+    }
+
+    pub fn debug(&self) -> String {
+        format!(
+            "RTC: Day {} {:02}:{:02}:{:02} (Halted: {}, Carry: {})",
+            self.days, self.hours, self.minutes, self.seconds, self.halted, self.day_carry
+        )
+    }
+}
+
+fn get_unix_timestamp() -> u64 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(duration) => duration.as_secs(),
+        Err(_) => 0,
+    }
+}
@@ -1,25 +1,65 @@
+use std::io;
+use std::io::{Error, ErrorKind};
+
+use crate::camera::*;
 use crate::cpu::*;
+use crate::error::*;
+use crate::header::*;
 use crate::joypad::*;
 use crate::mmu::*;
+use crate::model::*;
 use crate::ppu::*;
+use crate::rewind::*;
 use crate::rom::*;
+use crate::save_formats::*;
+use crate::state::*;
 use crate::timer::*;
 use crate::utils::*;
 
+// Snapshot of the CPU registers, for debugging and tooling
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct CpuRegisters {
+    pub af: Word,
+    pub bc: Word,
+    pub de: Word,
+    pub hl: Word,
+    pub sp: Word,
+    pub pc: Word,
+    pub interrupts_enabled: bool,
+    pub halted: bool,
+    pub stopped: bool,
+}
+
 pub struct RustyBoy {
     cpu: Cpu,
     pause: bool,
+    rewind_buffer: RewindBuffer,
 }
 
 impl RustyBoy {
 
-    pub fn new(file: &str) -> RustyBoy {
-        let rom = Rom::new(file);
-        rom.debug_header();
+    pub fn new(file: &str) -> Result<RustyBoy, RustyBoyError> {
+        RustyBoy::from_rom(Rom::new(file)?)
+    }
+
+    pub fn open(file: &str, entry: Option<&str>, patch_file: Option<&str>) -> Result<RustyBoy, RustyBoyError> {
+        // Like RustyBoy::new, but picks which ROM to use when the file is an archive holding
+        // several, and which IPS / UPS / BPS patch to apply instead of looking for one
+        RustyBoy::from_rom(Rom::open(file, entry, patch_file)?)
+    }
 
+    pub fn from_bytes(data: Vec<u8>) -> Result<RustyBoy, RustyBoyError> {
+        RustyBoy::from_rom(Rom::from_bytes(data)?)
+    }
+
+    pub fn from_bytes_with_patch(data: Vec<u8>, patch: &[u8]) -> Result<RustyBoy, RustyBoyError> {
+        RustyBoy::from_rom(Rom::unpack(data, None, Some(patch))?)
+    }
+
+    fn from_rom(rom: Rom) -> Result<RustyBoy, RustyBoyError> {
         let mut joypad = Joypad::new();
 
-        let mut mmu = Mmu::new(rom, joypad);
+        let mut mmu = Mmu::new(rom, joypad)?;
         mmu.reset();
 
         let mut timer = Timer::new();
@@ -29,23 +69,60 @@ impl RustyBoy {
         let mut cpu = Cpu::new(mmu, timer, ppu);
         cpu.reset();
 
-        RustyBoy {
+        Ok(RustyBoy {
             cpu: cpu,
             pause: false,
-        }
+            rewind_buffer: RewindBuffer::new(REWIND_BUFFER_CAPACITY, REWIND_SNAPSHOT_INTERVAL),
+        })
 
     }
 
-    pub fn run(&mut self) {
+    pub fn run_frame(&mut self) -> Result<&[u8], RustyBoyError> {
+        // Run a single frame and return the framebuffer - 160x144 pixels, 3 bytes (RGB) per pixel
+        self.run()?;
+        Ok(self.get_screen())
+    }
+
+    pub fn run(&mut self) -> Result<(), RustyBoyError> {
         let mut frame_cycles = 0;
 
         if !self.pause {
             while frame_cycles < MAX_CYCLES_PER_FRAME {
-                let cycles = self.cpu.execute();
+                let cycles = self.cpu.execute()?;
                 frame_cycles += cycles as usize;
 
                 self.cpu.handle_interrupts();
             }
+
+            if self.rewind_buffer.should_snapshot() {
+                let snapshot = self.save_state();
+                self.rewind_buffer.push(snapshot);
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn configure_rewind(&mut self, capacity: usize, interval: usize) {
+        // Capacity is the number of snapshots to hold and interval is the number of frames between
+        // each one, so the length of history is roughly capacity * interval frames. A capacity
+        // of 0 turns rewinding off
+        self.rewind_buffer = RewindBuffer::new(capacity, interval);
+    }
+
+    pub fn rewind(&mut self) -> io::Result<bool> {
+        // Step back to the previous snapshot, returns false if there is no more history. A
+        // snapshot that won't load means the rest of the history can't be trusted either, so
+        // it is all thrown away and the error returned
+        match self.rewind_buffer.pop() {
+            Some(snapshot) => {
+                if let Err(e) = self.load_state(&snapshot) {
+                    self.rewind_buffer.clear();
+                    return Err(e);
+                }
+                Ok(true)
+            },
+            None => Ok(false),
         }
     }
 
@@ -57,6 +134,15 @@ impl RustyBoy {
         self.cpu.get_vram_tiles()
     }
 
+    pub fn take_audio_samples(&mut self) -> Vec<f32> {
+        // Interleaved stereo (left, right) samples produced since the last call
+        self.cpu.take_audio_samples()
+    }
+
+    pub fn set_audio_sample_rate(&mut self, sample_rate: usize) {
+        self.cpu.set_audio_sample_rate(sample_rate);
+    }
+
     pub fn set_button_state(&mut self, button: usize) {
         self.cpu.set_button_state(button);
     }
@@ -70,22 +156,202 @@ impl RustyBoy {
         println!("Paused: {}", self.pause);
     }
 
+    pub fn get_rom_file_name(&self) -> Option<&str> {
+        // The ROM's file name, or for archives the name of the ROM inside it
+        self.cpu.get_rom_file_name()
+    }
+
+    pub fn get_cartridge_header(&self) -> &CartridgeHeader {
+        self.cpu.get_cartridge_header()
+    }
+
+    pub fn get_rom_problems(&self) -> Vec<String> {
+        // Warnings about the cartridge header (e.g. a bad checksum) or the archive the ROM came
+        // in. None of them stop the ROM running, so it is up to the frontend whether to show them
+        self.cpu.get_rom_problems()
+    }
+
+    pub fn debug_rom_header(&self) -> String {
+        self.cpu.debug_rom_header()
+    }
+
     pub fn get_external_ram(&self) -> &[Byte] {
         self.cpu.get_external_ram()
     }
 
-    pub fn load_external_ram(&mut self, buffer: Vec<Byte>) {
-        self.cpu.load_external_ram(buffer);
+    pub fn set_boot_rom(&mut self, boot_rom: Vec<u8>) -> Result<(), RustyBoyError> {
+        // Run a DMG (256 byte) or CGB (2304 byte) boot ROM before the game, restarting from power
+        // on. Needs calling before the first frame is run
+        self.cpu.set_boot_rom(boot_rom)
+    }
+
+    pub fn get_model(&self) -> Model {
+        self.cpu.get_model()
+    }
+
+    pub fn set_model(&mut self, model: Model) -> Result<(), RustyBoyError> {
+        // Which Game Boy to emulate, restarting from its power on state. Defaults to a CGB for CGB
+        // games and a DMG for everything else. Needs calling before the first frame is run, and
+        // before any boot ROM as it has to be the one for this model
+        self.cpu.set_model(model)
+    }
+
+    pub fn set_compat_palettes(&mut self, direction: usize, button: Option<usize>) -> bool {
+        // Recolour a DMG game running on a CGB as if the direction (UP_BUTTON etc.) and A or B
+        // had been held at boot. Returns false if the game isn't in compatibility mode or the
+        // combination doesn't pick a palette
+        self.cpu.set_compat_palettes(direction, button)
+    }
+
+    pub fn set_camera_sensor(&mut self, sensor: CameraSensor) {
+        // What the Game Boy Camera sees, see CameraSensor::from_path to use PNG files.
+        // Ignored by every other cartridge
+        self.cpu.set_camera_sensor(sensor);
+    }
+
+    pub fn set_tilt(&mut self, x: f32, y: f32) {
+        // For cartridges with an accelerometer (MBC7). Both axes range from -1.0 to 1.0, roughly
+        // 1g of tilt, with x positive when tilted right and y positive when tilted towards the
+        // player. Ignored by every other cartridge
+        self.cpu.set_tilt(x, y);
+    }
+
+    pub fn take_rumble_strength(&mut self) -> f32 {
+        // How much of the time since the last call the cartridge's rumble motor was on, from 0.0
+        // to 1.0. Games vary the strength by pulsing the motor faster than once a frame, so
+        // call this once per frame and forward it to the controller as the rumble strength
+        self.cpu.take_rumble_strength()
+    }
+
+    pub fn get_save_data(&self) -> Vec<u8> {
+        // The contents of the battery backed save file - the external RAM, followed by the RTC
+        // footer for cartridges with a clock. Load it back with load_external_ram
+        self.cpu.get_save_data()
+    }
+
+    pub fn take_external_ram_written(&mut self) -> bool {
+        // Whether the game has written to cartridge RAM since this was last called
+        self.cpu.take_external_ram_written()
+    }
+
+    pub fn export_save(&self, format: SaveFormat) -> (Vec<u8>, Vec<String>) {
+        // The save data converted for other emulators or flash carts, along with warnings if the
+        // format doesn't suit the cartridge
+        self.cpu.export_save(format)
+    }
+
+    pub fn load_external_ram(&mut self, buffer: Vec<Byte>) -> Vec<String> {
+        // Accepts saves from other emulators as well as our own, see save_formats::import_save.
+        // Returns warnings about anything in the save that didn't fit the cartridge
+        self.cpu.load_external_ram(buffer)
+    }
+
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new();
+
+        let (header_checksum, global_checksum) = self.cpu.get_rom_checksums();
+        for byte in SAVE_STATE_MAGIC {
+            writer.write_byte(*byte);
+        }
+        writer.write_word(SAVE_STATE_VERSION);
+        writer.write_byte(header_checksum);
+        writer.write_word(global_checksum);
+        writer.write_byte(self.cpu.get_model() as Byte);
+
+        self.cpu.save_state(&mut writer);
+
+        writer.into_bytes()
+    }
+
+    pub fn load_state(&mut self, data: &[u8]) -> io::Result<()> {
+        // Each component overwrites itself as it is read, so a state that turns out to be
+        // truncated or corrupt part way through would leave us half loaded. Keep the current
+        // state to put back if that happens, so a failed load changes nothing. Putting back a
+        // state we just saved should always work, but if it doesn't, say so rather than panic
+        let previous_state = self.save_state();
+        let e = match self.apply_state(data) {
+            Ok(_) => return Ok(()),
+            Err(e) => e,
+        };
+
+        match self.apply_state(&previous_state) {
+            Ok(_) => Err(e),
+            Err(restore_error) => Err(io::Error::new(e.kind(), format!(
+                "{}, and restoring the state from before the load also failed - {}", e, restore_error
+            ))),
+        }
+    }
+
+    fn apply_state(&mut self, data: &[u8]) -> io::Result<()> {
+        let mut reader = StateReader::new(data);
+
+        let mut magic = [0; 4];
+        for byte in magic.iter_mut() {
+            *byte = reader.read_byte()?;
+        }
+        if &magic != SAVE_STATE_MAGIC {
+            return Err(Error::new(ErrorKind::InvalidData, "Not a Rusty Boy save state"));
+        }
+
+        let version = reader.read_word()?;
+        if version != SAVE_STATE_VERSION {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Save state version {} is not supported (expected {})", version, SAVE_STATE_VERSION)
+            ));
+        }
+
+        // Make sure the state was made with this ROM before loading any of it
+        let (header_checksum, global_checksum) = self.cpu.get_rom_checksums();
+        if reader.read_byte()? != header_checksum || reader.read_word()? != global_checksum {
+            return Err(Error::new(ErrorKind::InvalidData, "Save state was made with a different ROM"));
+        }
+
+        // The registers and memory would make no sense to a different model
+        if reader.read_byte()? != self.cpu.get_model() as Byte {
+            return Err(Error::new(ErrorKind::InvalidData, "Save state was made on a different model"));
+        }
+
+        self.cpu.load_state(&mut reader)?;
+
+        if !reader.is_finished() {
+            return Err(Error::new(ErrorKind::InvalidData, "Save state has unexpected trailing data"));
+        }
+
+        Ok(())
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.pause
+    }
+
+    pub fn get_cpu_registers(&self) -> CpuRegisters {
+        self.cpu.get_registers()
+    }
+
+    pub fn read_memory(&self, addr: Word) -> Byte {
+        // Read from the memory bus without side effects, for debugging and tooling
+        self.cpu.peek_memory(addr)
+    }
+
+    pub fn get_debug_state(&self) -> String {
+        let mut state = String::new();
+        state.push_str("\n---------------- PPU ----------------\n\n");
+        state.push_str(&self.cpu.debug_ppu());
+        state.push_str("\n\n---------------- MMU ----------------\n\n");
+        state.push_str(&self.cpu.debug_mmu());
+        state.push_str("\n\n---------------- APU ----------------\n\n");
+        state.push_str(&self.cpu.debug_apu());
+        state.push_str("\n\n--------------- REWIND --------------\n\n");
+        state.push_str(&format!("Snapshots: {}\nMemory: {} KB", self.rewind_buffer.len(), self.rewind_buffer.get_memory_usage() / 1024));
+        state.push_str("\n\n---------------- CPU ----------------\n\n");
+        state.push_str(&self.cpu.debug_cpu());
+        state
     }
 
     pub fn debug(&self) {
         if self.pause {
-            println!("\n---------------- PPU ----------------\n");
-            println!("{}", self.cpu.debug_ppu());
-            println!("\n---------------- MMU ----------------\n");
-            println!("{}", self.cpu.debug_mmu());
-            println!("\n---------------- CPU ----------------\n");
-            println!("{}", self.cpu.debug_cpu());
+            println!("{}", self.get_debug_state());
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,229 @@
+use crate::mbc::*;
+use crate::utils::*;
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum SaveFormat {
+    /**
+    * The save file layouts used by other emulators and flash carts. Saves are always loaded by
+    * working out which of these they are from their size, these are only needed for exporting
+    **/
+
+    // RAM followed by the 48 byte RTC footer - what we write, as do BGB, SameBoy, mGBA and VBA-M
+    NATIVE,
+
+    // Just the RAM, as flash carts and emulators without RTC support use
+    RAW,
+
+    // RAM followed by the 44 byte RTC footer with a 32 bit timestamp from older VBA-M versions
+    RTC_32_BIT,
+
+    // MBC2's 512 x 4 bits of RAM packed two to a byte, low nibble first
+    MBC2_PACKED,
+}
+
+impl SaveFormat {
+
+    pub fn from_name(name: &str) -> Option<SaveFormat> {
+        match name.to_ascii_lowercase().as_str() {
+            "native" => Some(SaveFormat::NATIVE),
+            "raw" | "srm" => Some(SaveFormat::RAW),
+            "rtc44" => Some(SaveFormat::RTC_32_BIT),
+            "mbc2-packed" => Some(SaveFormat::MBC2_PACKED),
+            _ => None,
+        }
+    }
+}
+
+fn get_footer_sizes(mbc_type: MbcType, has_rtc: bool) -> &'static [usize] {
+    // What can follow the RAM in a save file for each kind of cartridge
+    match mbc_type {
+        MbcType::MBC3 if has_rtc => &[RTC_FOOTER_SIZE, RTC_FOOTER_SIZE_32_BIT],
+        MbcType::HUC3 => &[HUC3_RTC_FOOTER_SIZE],
+        _ => &[],
+    }
+}
+
+pub fn import_save(mut data: Vec<Byte>, mbc_type: MbcType, ram_size: usize, has_rtc: bool) -> (Vec<Byte>, Vec<String>) {
+    // Convert a save file from any of the formats we know into what Mbc::load_external_ram
+    // expects - the RAM, followed by the cartridge's own footer if it has one. Also returns
+    // warnings about anything that had to be dropped or padded to make it fit
+    if mbc_type == MbcType::MBC2 {
+        return import_mbc2_save(data);
+    }
+
+    let footer_sizes = get_footer_sizes(mbc_type, has_rtc);
+    let footer_size = data.len().checked_sub(ram_size);
+    if footer_size == Some(0) || footer_size.is_some_and(|footer_size| footer_sizes.contains(&footer_size)) {
+        return (data, Vec::new());
+    }
+
+    // A save from a cartridge with a clock, which this one doesn't have
+    if footer_size.is_some_and(|footer_size| footer_size == RTC_FOOTER_SIZE || footer_size == RTC_FOOTER_SIZE_32_BIT) {
+        data.truncate(ram_size);
+        return (data, vec![String::from("Save file has an RTC footer, but the cartridge has no clock - ignoring it")]);
+    }
+
+    // Whatever is left over is the wrong size, so drop or pad it rather than the MBC mistaking
+    // part of the RAM for a footer
+    let warning = format!(
+        "Save file is {} bytes, but the cartridge has {} bytes of RAM{} - {}",
+        data.len(),
+        ram_size,
+        if footer_sizes.is_empty() { "" } else { " and a clock" },
+        if data.len() > ram_size { "the extra data is ignored" } else { "the rest of RAM is left empty" }
+    );
+    data.resize(ram_size, 0);
+    (data, vec![warning])
+}
+
+fn import_mbc2_save(data: Vec<Byte>) -> (Vec<Byte>, Vec<String>) {
+    // MBC2 RAM is 512 nibbles. Most emulators store them one per byte (some with the upper nibble
+    // set), but some pack them two to a byte, and flash carts dump the whole 8 KiB of 0xA000 -
+    // 0xBFFF, which echoes them 16 times
+    let mut warnings = Vec::new();
+    let nibbles: Vec<Byte> = match data.len() {
+        MBC2_RAM_SIZE => data,
+        MBC2_PACKED_RAM_SIZE => data.iter().flat_map(|byte| [*byte & 0x0F, *byte >> 4]).collect(),
+        RAM_BANK_SIZE => data[..MBC2_RAM_SIZE].to_vec(),
+        _ => {
+            warnings.push(format!(
+                "Save file is {} bytes, but MBC2 saves should be {}, {} or {} bytes - {}",
+                data.len(), MBC2_RAM_SIZE, MBC2_PACKED_RAM_SIZE, RAM_BANK_SIZE,
+                if data.len() > MBC2_RAM_SIZE { "the extra data is ignored" } else { "the rest of RAM is left empty" }
+            ));
+            let mut data = data;
+            data.resize(MBC2_RAM_SIZE, 0);
+            data
+        },
+    };
+
+    (nibbles.iter().map(|nibble| nibble & 0x0F).collect(), warnings)
+}
+
+pub fn export_save(mut data: Vec<Byte>, mbc_type: MbcType, ram_size: usize, format: SaveFormat) -> (Vec<Byte>, Vec<String>) {
+    // Convert what Mbc::get_save_data returned into the given format. Formats that don't make
+    // sense for the cartridge (like packing anything other than MBC2) leave it as it is, with a
+    // warning saying so
+    let data = match format {
+        SaveFormat::NATIVE => data,
+        SaveFormat::RAW => {
+            data.truncate(ram_size);
+            data
+        },
+        SaveFormat::RTC_32_BIT if data.len() == ram_size + RTC_FOOTER_SIZE => {
+            // The registers are the same, only the timestamp is cut down to its lower 32 bits
+            data.truncate(ram_size + RTC_FOOTER_SIZE_32_BIT);
+            data
+        },
+        SaveFormat::RTC_32_BIT => {
+            return (data, vec![String::from("Only MBC3 saves with a clock have an RTC footer to shorten, leaving the save as it is")]);
+        },
+        SaveFormat::MBC2_PACKED if mbc_type == MbcType::MBC2 => {
+            data.chunks(2)
+                .map(|nibbles| (nibbles[0] & 0x0F) | (nibbles.get(1).unwrap_or(&0) << 4))
+                .collect()
+        },
+        SaveFormat::MBC2_PACKED => {
+            return (data, vec![String::from("Only MBC2 saves can be packed, leaving the save as it is")]);
+        },
+    };
+
+    (data, Vec::new())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn import(data: Vec<Byte>, mbc_type: MbcType, ram_size: usize, has_rtc: bool) -> (Vec<Byte>, usize) {
+        let (data, warnings) = import_save(data, mbc_type, ram_size, has_rtc);
+        (data, warnings.len())
+    }
+
+    #[test]
+    fn import_keeps_saves_of_the_right_size() {
+        let save: Vec<Byte> = (0..=255).cycle().take(RAM_BANK_SIZE).collect();
+        assert_eq!(import(save.clone(), MbcType::MBC1, RAM_BANK_SIZE, false), (save, 0));
+    }
+
+    #[test]
+    fn import_keeps_rtc_footers_for_cartridges_with_a_clock() {
+        for footer_size in [RTC_FOOTER_SIZE, RTC_FOOTER_SIZE_32_BIT] {
+            let save = vec![0x5A; RAM_BANK_SIZE + footer_size];
+            assert_eq!(import(save.clone(), MbcType::MBC3, RAM_BANK_SIZE, true), (save, 0));
+        }
+
+        let save = vec![0x5A; RAM_BANK_SIZE + HUC3_RTC_FOOTER_SIZE];
+        assert_eq!(import(save.clone(), MbcType::HUC3, RAM_BANK_SIZE, false), (save, 0));
+    }
+
+    #[test]
+    fn import_drops_rtc_footers_for_cartridges_without_a_clock() {
+        let mut save = vec![0x5A; RAM_BANK_SIZE];
+        save.extend_from_slice(&[0xA5; RTC_FOOTER_SIZE]);
+        assert_eq!(import(save, MbcType::MBC3, RAM_BANK_SIZE, false), (vec![0x5A; RAM_BANK_SIZE], 1));
+    }
+
+    #[test]
+    fn import_pads_or_truncates_saves_of_the_wrong_size() {
+        let mut padded = vec![0x5A; 100];
+        padded.resize(RAM_BANK_SIZE, 0);
+        assert_eq!(import(vec![0x5A; 100], MbcType::MBC5, RAM_BANK_SIZE, false), (padded, 1));
+
+        // Too big to be RAM followed by a footer the cartridge could have
+        let save = vec![0x5A; RAM_BANK_SIZE + 3];
+        assert_eq!(import(save, MbcType::MBC3, RAM_BANK_SIZE, true), (vec![0x5A; RAM_BANK_SIZE], 1));
+    }
+
+    #[test]
+    fn import_unpacks_mbc2_saves() {
+        let nibbles: Vec<Byte> = (0..MBC2_RAM_SIZE).map(|i| (i % 16) as Byte).collect();
+
+        // One nibble per byte, with or without the upper nibble set
+        assert_eq!(import(nibbles.clone(), MbcType::MBC2, MBC2_RAM_SIZE, false), (nibbles.clone(), 0));
+        let with_upper: Vec<Byte> = nibbles.iter().map(|nibble| nibble | 0xF0).collect();
+        assert_eq!(import(with_upper, MbcType::MBC2, MBC2_RAM_SIZE, false), (nibbles.clone(), 0));
+
+        // Two nibbles per byte, low nibble first
+        let packed: Vec<Byte> = nibbles.chunks(2).map(|pair| pair[0] | (pair[1] << 4)).collect();
+        assert_eq!(import(packed, MbcType::MBC2, MBC2_RAM_SIZE, false), (nibbles.clone(), 0));
+
+        // The whole of 0xA000 - 0xBFFF, echoing the RAM
+        let echoed: Vec<Byte> = nibbles.iter().copied().cycle().take(RAM_BANK_SIZE).collect();
+        assert_eq!(import(echoed, MbcType::MBC2, MBC2_RAM_SIZE, false), (nibbles.clone(), 0));
+
+        let mut short = nibbles[..100].to_vec();
+        short.resize(MBC2_RAM_SIZE, 0);
+        assert_eq!(import(nibbles[..100].to_vec(), MbcType::MBC2, MBC2_RAM_SIZE, false), (short, 1));
+    }
+
+    #[test]
+    fn export_round_trips_through_import() {
+        let mut save = vec![0x5A; RAM_BANK_SIZE];
+        save.extend_from_slice(&[0xA5; RTC_FOOTER_SIZE]);
+
+        let (raw, warnings) = export_save(save.clone(), MbcType::MBC3, RAM_BANK_SIZE, SaveFormat::RAW);
+        assert_eq!((raw, warnings.len()), (vec![0x5A; RAM_BANK_SIZE], 0));
+
+        let (native, _) = export_save(save.clone(), MbcType::MBC3, RAM_BANK_SIZE, SaveFormat::NATIVE);
+        assert_eq!(import(native, MbcType::MBC3, RAM_BANK_SIZE, true), (save.clone(), 0));
+
+        let (rtc_32_bit, warnings) = export_save(save.clone(), MbcType::MBC3, RAM_BANK_SIZE, SaveFormat::RTC_32_BIT);
+        assert_eq!((rtc_32_bit.len(), warnings.len()), (RAM_BANK_SIZE + RTC_FOOTER_SIZE_32_BIT, 0));
+        assert_eq!(rtc_32_bit[..], save[..(RAM_BANK_SIZE + RTC_FOOTER_SIZE_32_BIT)]);
+
+        let nibbles: Vec<Byte> = (0..MBC2_RAM_SIZE).map(|i| (i % 16) as Byte).collect();
+        let (packed, warnings) = export_save(nibbles.clone(), MbcType::MBC2, MBC2_RAM_SIZE, SaveFormat::MBC2_PACKED);
+        assert_eq!((packed.len(), warnings.len()), (MBC2_PACKED_RAM_SIZE, 0));
+        assert_eq!(import(packed, MbcType::MBC2, MBC2_RAM_SIZE, false), (nibbles, 0));
+    }
+
+    #[test]
+    fn export_warns_about_formats_that_dont_suit_the_cartridge() {
+        let save = vec![0x5A; RAM_BANK_SIZE];
+        for format in [SaveFormat::RTC_32_BIT, SaveFormat::MBC2_PACKED] {
+            let (data, warnings) = export_save(save.clone(), MbcType::MBC1, RAM_BANK_SIZE, format);
+            assert_eq!((data, warnings.len()), (save.clone(), 1));
+        }
+    }
+}
@@ -0,0 +1,145 @@
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::rusty_boy::*;
+use crate::utils::*;
+
+#[derive(Debug)]
+pub struct SaveManager {
+    /**
+    * Keeps the battery backed RAM of a cartridge in a save file. Saves are written to a temporary
+    * file that is then renamed over the real one, so a crash part way through never leaves a
+    * half written save behind, and the previous few saves are kept as game.sav.1, game.sav.2 and
+    * so on (1 being the newest). Games don't say when they have finished saving, so we autosave
+    * once they have stopped writing to cartridge RAM for a few seconds
+    **/
+
+    save_file: PathBuf,
+    backups: usize,
+    has_battery: bool,
+    last_ram_write: Option<Instant>,
+
+    // What is in the save file already, so we don't write it again when nothing has changed
+    last_saved: Vec<Byte>,
+}
+
+impl SaveManager {
+
+    pub fn new(rusty_boy: &RustyBoy, rom_file: &str, save_dir: Option<&str>, backups: usize) -> SaveManager {
+        // Saves are named after the ROM (or the ROM inside an archive), i.e. game.gb -> game.sav,
+        // and live next to it unless given a directory of their own
+        let rom_path = Path::new(rom_file);
+        let file_name = match rusty_boy.get_rom_file_name() {
+            Some(file_name) => PathBuf::from(file_name),
+            None => PathBuf::from(rom_path.file_name().unwrap_or_default()),
+        };
+
+        let directory = match save_dir {
+            Some(save_dir) => PathBuf::from(save_dir),
+            None => rom_path.parent().map(Path::to_path_buf).unwrap_or_default(),
+        };
+
+        SaveManager {
+            save_file: directory.join(file_name).with_extension("sav"),
+            backups: backups,
+            has_battery: rusty_boy.get_cartridge_header().cartridge_type.has_battery,
+            last_ram_write: None,
+            last_saved: Vec::new(),
+        }
+    }
+
+    pub fn get_save_file(&self) -> &Path {
+        &self.save_file
+    }
+
+    pub fn load(&mut self, rusty_boy: &mut RustyBoy) -> io::Result<Vec<String>> {
+        // Not having a save yet is fine, the game will make one. Returns any warnings from
+        // RustyBoy::load_external_ram
+        if !self.has_battery {
+            return Ok(Vec::new());
+        }
+
+        let buffer = match fs::read(&self.save_file) {
+            Ok(buffer) => buffer,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+
+        let warnings = rusty_boy.load_external_ram(buffer);
+        self.last_saved = rusty_boy.get_save_data();
+        Ok(warnings)
+    }
+
+    pub fn update(&mut self, rusty_boy: &mut RustyBoy) -> io::Result<()> {
+        // Call once a frame - this is what notices writes to cartridge RAM and autosaves
+        if rusty_boy.take_external_ram_written() {
+            self.last_ram_write = Some(Instant::now());
+        }
+
+        match self.last_ram_write {
+            Some(last_ram_write) if last_ram_write.elapsed() >= Duration::from_secs(AUTOSAVE_DELAY_SECS) => {
+                self.last_ram_write = None;
+                self.save(rusty_boy)
+            },
+            _ => Ok(()),
+        }
+    }
+
+    pub fn save(&mut self, rusty_boy: &RustyBoy) -> io::Result<()> {
+        // Only cartridges with a battery keep their RAM when switched off
+        if !self.has_battery {
+            return Ok(());
+        }
+
+        let data = rusty_boy.get_save_data();
+        if data.is_empty() || data == self.last_saved {
+            return Ok(());
+        }
+
+        if let Some(directory) = self.save_file.parent().filter(|directory| !directory.as_os_str().is_empty()) {
+            fs::create_dir_all(directory)?;
+        }
+
+        let temp_file = self.get_numbered_file("tmp");
+        {
+            let mut file = File::create(&temp_file)?;
+            file.write_all(&data)?;
+            file.sync_all()?;
+        }
+
+        self.rotate_backups()?;
+        fs::rename(&temp_file, &self.save_file)?;
+
+        self.last_saved = data;
+        Ok(())
+    }
+
+    fn rotate_backups(&self) -> io::Result<()> {
+        // Shift every backup up by one, dropping the oldest, then the current save becomes .1
+        if self.backups == 0 || !self.save_file.exists() {
+            return Ok(());
+        }
+
+        for i in (1..self.backups).rev() {
+            let backup = self.get_numbered_file(&i.to_string());
+            if backup.exists() {
+                fs::rename(&backup, self.get_numbered_file(&(i + 1).to_string()))?;
+            }
+        }
+
+        fs::copy(&self.save_file, self.get_numbered_file("1"))?;
+        Ok(())
+    }
+
+    fn get_numbered_file(&self, suffix: &str) -> PathBuf {
+        // game.sav -> game.sav.<suffix>
+        let mut file_name = self.save_file.file_name().unwrap_or_default().to_os_string();
+        file_name.push(".");
+        file_name.push(suffix);
+        self.save_file.with_file_name(file_name)
+    }
+}
@@ -0,0 +1,145 @@
+use std::io::{Error, ErrorKind, Result};
+
+use crate::utils::*;
+
+// Save states start with a small header so we can reject files that aren't save states,
+// were made by an incompatible version, or were made with a different ROM or model
+//     0x00 - 0x03  Magic "RBST"
+//     0x04 - 0x05  Format version (little endian)
+//     0x06         ROM header checksum (0x014D)
+//     0x07 - 0x08  ROM global checksum (0x014E - 0x014F)
+//     0x09         Model
+// Everything following the header is each component's state, written in a fixed order
+pub const SAVE_STATE_MAGIC: &[u8; 4] = b"RBST";
+pub const SAVE_STATE_VERSION: u16 = 9;
+
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+
+    pub fn new() -> StateWriter {
+        StateWriter {
+            data: Vec::new(),
+        }
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+
+    pub fn write_byte(&mut self, val: Byte) {
+        self.data.push(val);
+    }
+
+    pub fn write_bool(&mut self, val: bool) {
+        self.data.push(val as Byte);
+    }
+
+    pub fn write_word(&mut self, val: Word) {
+        self.data.extend_from_slice(&val.to_le_bytes());
+    }
+
+    pub fn write_u32(&mut self, val: u32) {
+        self.data.extend_from_slice(&val.to_le_bytes());
+    }
+
+    pub fn write_u64(&mut self, val: u64) {
+        self.data.extend_from_slice(&val.to_le_bytes());
+    }
+
+    pub fn write_usize(&mut self, val: usize) {
+        self.write_u64(val as u64);
+    }
+
+    pub fn write_isize(&mut self, val: isize) {
+        self.write_u64(val as i64 as u64);
+    }
+
+    pub fn write_bytes(&mut self, val: &[Byte]) {
+        // Byte arrays are prefixed with their length so we can validate them on load
+        self.write_u32(val.len() as u32);
+        self.data.extend_from_slice(val);
+    }
+}
+
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> StateReader<'a> {
+
+    pub fn new(data: &'a [u8]) -> StateReader<'a> {
+        StateReader {
+            data: data,
+            position: 0,
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.position == self.data.len()
+    }
+
+    pub fn read_byte(&mut self) -> Result<Byte> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool> {
+        Ok(self.read_byte()? != 0)
+    }
+
+    pub fn read_word(&mut self) -> Result<Word> {
+        let bytes = self.take(2)?;
+        Ok(Word::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    pub fn read_usize(&mut self) -> Result<usize> {
+        Ok(self.read_u64()? as usize)
+    }
+
+    pub fn read_isize(&mut self) -> Result<isize> {
+        Ok(self.read_u64()? as i64 as isize)
+    }
+
+    pub fn read_bytes(&mut self) -> Result<&'a [Byte]> {
+        let len = self.read_u32()? as usize;
+        self.take(len)
+    }
+
+    pub fn read_bytes_into(&mut self, dest: &mut [Byte]) -> Result<()> {
+        // Read a byte array into a fixed size buffer, which must match what was saved
+        let bytes = self.read_bytes()?;
+        if bytes.len() != dest.len() {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Save state has {} bytes where {} were expected", bytes.len(), dest.len())
+            ));
+        }
+
+        dest.copy_from_slice(bytes);
+        Ok(())
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [Byte]> {
+        if self.position + len > self.data.len() {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "Save state is truncated"));
+        }
+
+        let bytes = &self.data[self.position..self.position + len];
+        self.position += len;
+        Ok(bytes)
+    }
+}
@@ -1,5 +1,8 @@
+use std::io::Result;
+
 use crate::interrupts::*;
 use crate::mmu::*;
+use crate::state::*;
 use crate::utils::*;
 
 pub struct Timer {
@@ -16,6 +19,24 @@ impl Timer {
         }
     }
 
+    pub fn reset(&mut self, divider: Word) {
+        // DIV is the upper byte of a 16 bit counter, the lower byte is how far we are through its
+        // next increment
+        self.divider_counter = (divider & 0xFF) as usize;
+        self.timer_counter = 0;
+    }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_usize(self.divider_counter);
+        writer.write_usize(self.timer_counter);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        self.divider_counter = reader.read_usize()?;
+        self.timer_counter = reader.read_usize()?;
+        Ok(())
+    }
+
     pub fn update(&mut self, mmu: &mut Mmu, cycles: u8) {
         self.update_divider_register(mmu, cycles);
 
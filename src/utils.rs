@@ -19,6 +19,12 @@ pub const MAX_CYCLES_PER_FRAME: usize = (CLOCK_SPEED as f32 / 59.7275) as usize;
 pub const PROGRAM_COUNTER_INIT: Word = 0x100;
 pub const STACK_POINTER_INIT: Word = 0xFFFE;
 
+// Boot ROMs are mapped over the start of the cartridge until 0xFF50 is written. The DMG one covers
+// 0x0000 - 0x00FF, the CGB one also covers 0x0200 - 0x08FF, leaving the cartridge header visible
+pub const BOOT_ROM_DISABLE_ADDR: Word = 0xFF50;
+pub const DMG_BOOT_ROM_SIZE: usize = 0x100;
+pub const CGB_BOOT_ROM_SIZE: usize = 0x900;
+
 // Timers
 pub const DIVIDER_REGISTER_ADDR: Word = 0xFF04;
 pub const TIMER_ADDR: Word = 0xFF05;
@@ -90,18 +96,171 @@ pub const BG_COLOR_PALLETTE_ADDR: Word = 0xFF47;
 pub const OBJ_COLOR_PALLETTE_ADDR_0: Word = 0xFF48;
 pub const OBJ_COLOR_PALLETTE_ADDR_1: Word = 0xFF49;
 
+// Writing here copies 0xXX00 - 0xXX9F into OAM
+pub const DMA_ADDR: Word = 0xFF46;
+
+// Cartridge Header
+// Every cartridge has a header at 0x0100 - 0x014F describing what hardware is on the cartridge
+//     0104 - 0133  Nintendo logo, checked by the boot ROM
+//     0134 - 0143  Title, on later cartridges this shrinks to make room for the fields below
+//     013F - 0142  Manufacturer code (later cartridges only)
+//     0143         CGB flag, 0x80 = CGB compatible, 0xC0 = CGB only
+//     0144 - 0145  New licensee code, used when the old licensee code is 0x33
+//     0146         SGB flag, 0x03 = SGB functions supported
+//     0147         Cartridge type - the MBC and any extra hardware (RAM, battery, RTC, rumble)
+//     0148         ROM size
+//     0149         RAM size
+//     014A         Destination code, 0x00 = Japan, 0x01 = Overseas
+//     014B         Old licensee code
+//     014C         Version number
+//     014D         Header checksum over 0x0134 - 0x014C, checked by the boot ROM
+//     014E - 014F  Global checksum over the whole ROM (big endian), not checked by anything
+pub const CARTRIDGE_LOGO_ADDR: usize = 0x0104;
+pub const CARTRIDGE_TITLE_ADDR: usize = 0x0134;
+pub const MANUFACTURER_CODE_ADDR: usize = 0x013F;
+pub const CGB_FLAG_ADDR: usize = 0x0143;
+pub const NEW_LICENSEE_CODE_ADDR: usize = 0x0144;
+pub const SGB_FLAG_ADDR: usize = 0x0146;
+pub const CARTRIDGE_TYPE_ADDR: usize = 0x0147;
+pub const ROM_SIZE_ADDR: usize = 0x0148;
+pub const RAM_SIZE_ADDR: usize = 0x0149;
+pub const DESTINATION_CODE_ADDR: usize = 0x014A;
+pub const OLD_LICENSEE_CODE_ADDR: usize = 0x014B;
+pub const ROM_VERSION_ADDR: usize = 0x014C;
+pub const HEADER_CHECKSUM_ADDR: usize = 0x014D;
+pub const GLOBAL_CHECKSUM_ADDR: usize = 0x014E;
+pub const CARTRIDGE_HEADER_END_ADDR: usize = 0x0150;
+pub const ROM_BANK_SIZE: usize = 0x4000;  // In bytes
+pub const MAX_ROM_SIZE: usize = 0x800000;  // 8 MiB, the most an MBC5 can address
+
+pub const NINTENDO_LOGO: [Byte; 48] = [
+    0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0C, 0x00, 0x0D,
+    0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E, 0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99,
+    0xBB, 0xBB, 0x67, 0x63, 0x6E, 0x0E, 0xEC, 0xCC, 0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E,
+];
+
 // Banking
 pub const ROM_BANKING_MODE_ADDR: Word = 0x147;
 pub const RAM_BANK_COUNT_ADDR: Word = 0x148;
-pub const MAXIMUM_RAM_BANKS: usize = 4;
 pub const RAM_BANK_SIZE: usize = 0x2000;  // In bytes
 
+// Size of the RTC data appended to save files, see Rtc::get_footer. Some emulators write
+// an older version with a 32 bit timestamp instead of 64 bits
+pub const RTC_FOOTER_SIZE: usize = 48;
+pub const RTC_FOOTER_SIZE_32_BIT: usize = 44;
+
+// MBC2 has 512 x 4 bits of RAM, which some emulators save packed two nibbles to a byte
+pub const MBC2_RAM_SIZE: usize = 0x200;
+pub const MBC2_PACKED_RAM_SIZE: usize = MBC2_RAM_SIZE / 2;
+
+// MBC7 - the EEPROM holds 128 16 bit words, and the accelerometer reads 0x81D0 when level,
+// changing by about 0x70 for each 1g of tilt. Erasing a reading sets it to 0x8000
+pub const EEPROM_SIZE: usize = 256;
+pub const EEPROM_WORDS: usize = EEPROM_SIZE / 2;
+pub const ACCELEROMETER_CENTER: Word = 0x81D0;
+pub const ACCELEROMETER_ONE_G: Word = 0x70;
+pub const ACCELEROMETER_ERASED: Word = 0x8000;
+
+// HuC1 and HuC3 IR port - reads 0xC0 with no light and 0xC1 while light is being received, so
+// only bit 0 changes. Bits 6 and 7 always read as 1 and bits 1 - 5 as 0
+pub const IR_NO_LIGHT: Byte = 0xC0;
+
+// HuC3 keeps time as minutes into the day plus a day counter. Save files have a 17 byte
+// footer for the clock after the RAM, see HuC3::load_external_ram
+pub const HUC3_MINUTES_PER_DAY: u64 = 60 * 24;
+pub const HUC3_RTC_FOOTER_SIZE: usize = 17;
+
+// Zip files start with a local file header, gzip files with their own 2 byte magic number
+pub const ZIP_MAGIC: [u8; 4] = [0x50, 0x4B, 0x03, 0x04];
+pub const GZIP_MAGIC: [u8; 2] = [0x1F, 0x8B];
+
+// Soft patches - each format starts with its own magic string. UPS and BPS both end with the
+// CRC32 of the source, the target and the patch itself
+pub const IPS_MAGIC: &[u8] = b"PATCH";
+pub const IPS_EOF: &[u8] = b"EOF";
+pub const UPS_MAGIC: &[u8] = b"UPS1";
+pub const BPS_MAGIC: &[u8] = b"BPS1";
+pub const PATCH_FOOTER_SIZE: usize = 12;
+pub const PATCH_EXTENSIONS: [&str; 3] = ["ips", "ups", "bps"];
+
+// Pocket Camera - always has 128 KiB of RAM. Setting bit 4 of the RAM bank maps the camera
+// registers (0x36 of them, mirrored every 0x80 bytes) to 0xA000 - 0xBFFF instead. Captured images
+// are written to RAM bank 0 at 0xA100 as 16x14 tiles
+pub const POCKET_CAMERA_RAM_SIZE: usize = 0x20000;
+pub const CAMERA_REGISTER_COUNT: usize = 0x36;
+pub const CAMERA_REGISTERS_BANK: usize = 0x10;
+pub const CAMERA_WIDTH: usize = 128;
+pub const CAMERA_HEIGHT: usize = 112;
+pub const CAMERA_IMAGE_ADDR: usize = 0x0100;
+pub const CAMERA_IMAGE_SIZE: usize = (CAMERA_WIDTH / 8) * (CAMERA_HEIGHT / 8) * 16;
+pub const CAMERA_CAPTURE_BASE_CYCLES: usize = 129792;
+pub const CAMERA_CAPTURE_N_CYCLES: usize = 2048;
+pub const CAMERA_CAPTURE_EXPOSURE_CYCLES: usize = 64;
+
+// Battery saves are written once the game has left cartridge RAM alone for this long, keeping
+// this many older saves as backups
+pub const AUTOSAVE_DELAY_SECS: u64 = 3;
+pub const SAVE_BACKUPS: usize = 3;
+
+// How long each rumble request to the controller lasts, it is renewed every frame the motor is on
+pub const RUMBLE_DURATION_MS: u32 = 100;
+
 #[derive(Debug)]
 pub enum BankingMode {
     RAM,
     ROM
 }
 
+// Audio
+// The APU registers live between 0xFF10 and 0xFF26, with Wave RAM (32 4-bit samples)
+// occupying 0xFF30 - 0xFF3F
+//     NR10 - NR14  Channel 1 (Square with Sweep)
+//     NR21 - NR24  Channel 2 (Square)
+//     NR30 - NR34  Channel 3 (Wave)
+//     NR41 - NR44  Channel 4 (Noise)
+//     NR50         Master volume and VIN panning
+//     NR51         Sound panning - which channels go to which output terminal
+//     NR52         Sound on/off - Bit 7 turns the APU on/off, Bits 0-3 are the read only channel status
+pub const AUDIO_REGISTERS_START_ADDR: Word = 0xFF10;
+pub const AUDIO_REGISTERS_END_ADDR: Word = 0xFF3F;
+pub const NR10_ADDR: Word = 0xFF10;
+pub const NR11_ADDR: Word = 0xFF11;
+pub const NR12_ADDR: Word = 0xFF12;
+pub const NR13_ADDR: Word = 0xFF13;
+pub const NR14_ADDR: Word = 0xFF14;
+pub const NR21_ADDR: Word = 0xFF16;
+pub const NR22_ADDR: Word = 0xFF17;
+pub const NR23_ADDR: Word = 0xFF18;
+pub const NR24_ADDR: Word = 0xFF19;
+pub const NR30_ADDR: Word = 0xFF1A;
+pub const NR31_ADDR: Word = 0xFF1B;
+pub const NR32_ADDR: Word = 0xFF1C;
+pub const NR33_ADDR: Word = 0xFF1D;
+pub const NR34_ADDR: Word = 0xFF1E;
+pub const NR41_ADDR: Word = 0xFF20;
+pub const NR42_ADDR: Word = 0xFF21;
+pub const NR43_ADDR: Word = 0xFF22;
+pub const NR44_ADDR: Word = 0xFF23;
+pub const NR50_ADDR: Word = 0xFF24;
+pub const NR51_ADDR: Word = 0xFF25;
+pub const NR52_ADDR: Word = 0xFF26;
+pub const WAVE_RAM_START_ADDR: Word = 0xFF30;
+
+// The frame sequencer runs at 512 Hz and clocks the length counters, volume envelopes and sweep
+pub const CYCLES_PER_FRAME_SEQUENCER_STEP: usize = 8192;
+pub const AUDIO_SAMPLE_RATE: usize = 48000;
+
+// Number of cycles between each batch of samples the resampler hands back (~1ms)
+pub const CYCLES_PER_AUDIO_FRAME: usize = 4096;
+
+// Number of frames worth of audio the frontend keeps queued before it stops to let the device catch up
+pub const AUDIO_QUEUED_FRAMES: usize = 3;
+
+// Rewind
+// By default take a snapshot every 4 frames and hold on to ~60 seconds of them
+pub const REWIND_SNAPSHOT_INTERVAL: usize = 4;
+pub const REWIND_BUFFER_CAPACITY: usize = 900;
+
 // Interrupts
 // Known as IE (Interrupt Enable) register, which denotes which interrupts are currently enabled
 // Bit 0 = VBlank Interrupt - INT $40
@@ -119,6 +278,9 @@ pub const INTERRUPT_ENABLE_ADDR: Word = 0xFFFF;
 // Bit 4 = Joypad Interrupt - INT $60
 pub const INTERRUPT_FLAG_ADDR: Word = 0xFF0F;
 
+// Serial
+pub const SERIAL_CONTROL_ADDR: Word = 0xFF02;
+
 #[derive(Debug, PartialEq, Eq, Copy, Clone)]
 pub enum Interrupt {
     V_BLANK,
@@ -166,9 +328,20 @@ pub const HALF_CARRY_FLAG: usize = 5;
 pub const CARRY_FLAG: usize = 4;
 
 // CGB Specifics
+// KEY0 - the boot ROM sets bit 2 to run DMG games in compatibility mode, then it is locked
+pub const CGB_MODE_SELECT_ADDR: Word = 0xFF4C;
+pub const SPEED_SWITCH_ADDR: Word = 0xFF4D;
 pub const VRAM_BANK_SELECT_ADDR: Word = 0xFF4F;
+// HDMA1 - HDMA5. The source (HDMA1 / 2) and VRAM destination (HDMA3 / 4) are 16 byte aligned,
+// writing HDMA5 starts a transfer of (bits 0 - 6 + 1) * 0x10 bytes - all at once while the CPU
+// waits if bit 7 is clear, or 0x10 bytes per H-Blank if it is set
 pub const VRAM_DMA_START_ADDR: Word = 0xFF51;
+pub const VRAM_DMA_SOURCE_LOW_ADDR: Word = 0xFF52;
+pub const VRAM_DMA_DESTINATION_HIGH_ADDR: Word = 0xFF53;
+pub const VRAM_DMA_DESTINATION_LOW_ADDR: Word = 0xFF54;
 pub const VRAM_DMA_TRANSFER_ADDR: Word = 0xFF55;
+pub const VRAM_DMA_BLOCK_SIZE: usize = 0x10;
+pub const VRAM_DMA_CYCLES_PER_BLOCK: usize = 32;  // At normal speed, the CPU takes twice as many in double speed
 pub const WRAM_BANK_SELECT_ADDR: Word = 0xFF70;
 pub const BACKGROUND_PALETTE_INDEX_ADDR: Word = 0xFF68;
 pub const BACKGROUND_PALETTE_DATA_ADDR: Word = 0xFF69;